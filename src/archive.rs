@@ -0,0 +1,120 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use tar::{Builder, EntryType, Header};
+
+use crate::paths::to_portable;
+
+/// Creates a tar archive of `src_dir` at `out`, returning the archive size in bytes.
+/// With `reproducible`, entries are sorted by path and timestamps, owners and permissions
+/// are normalized, so the same tree always yields a byte-identical archive.
+pub fn create_tar(src_dir: &Path, out: &Path, reproducible: bool) -> io::Result<u64> {
+    let file = BufWriter::new(File::create(out)?);
+    let mut builder = Builder::new(file);
+    if reproducible {
+        append_dir_normalized(&mut builder, src_dir, src_dir)?;
+    } else {
+        builder.append_dir_all(".", src_dir)?;
+    }
+    builder.into_inner()?.flush()?;
+    Ok(fs::metadata(out)?.len())
+}
+
+/// Appends a directory's contents in sorted order with fixed metadata. Symlinks are stored as
+/// links, not followed.
+fn append_dir_normalized<W: Write>(builder: &mut Builder<W>, root: &Path, dir: &Path) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let relative = to_portable(path.strip_prefix(root).unwrap());
+        let metadata = entry.metadata()?;
+        let mut header = Header::new_gnu();
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        if metadata.file_type().is_symlink() {
+            // Store the link itself; following it would stream the target under the link's size
+            header.set_entry_type(EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            builder.append_link(&mut header, relative, to_portable(&fs::read_link(&path)?))?;
+        } else if metadata.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", relative), io::empty())?;
+            append_dir_normalized(builder, root, &path)?;
+        } else {
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(if is_executable(&metadata) { 0o755 } else { 0o644 });
+            header.set_size(metadata.len());
+            builder.append_data(&mut header, relative, File::open(&path)?)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reproducible_archives_are_identical() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("tree");
+        fs::create_dir_all(src.join("b_dir")).unwrap();
+        fs::write(src.join("z.txt"), b"last").unwrap();
+        fs::write(src.join("a.txt"), b"first").unwrap();
+        fs::write(src.join("b_dir/inner.txt"), b"inner").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+            std::os::unix::fs::symlink("missing", src.join("m_dangling")).unwrap();
+            std::os::unix::fs::symlink("b_dir", src.join("n_dir_link")).unwrap();
+        }
+
+        let first = temp_dir.path().join("first.tar");
+        create_tar(&src, &first, true).unwrap();
+
+        // Touch the tree so timestamps differ between the two runs
+        filetime::set_file_mtime(src.join("a.txt"), filetime::FileTime::from_unix_time(1_000_000, 0)).unwrap();
+        let second = temp_dir.path().join("second.tar");
+        create_tar(&src, &second, true).unwrap();
+
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+
+        let mut archive = tar::Archive::new(File::open(&first).unwrap());
+        let entries: Vec<(String, Option<String>)> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                let link = e.link_name().unwrap().map(|l| l.to_string_lossy().into_owned());
+                (e.path().unwrap().to_string_lossy().into_owned(), link)
+            })
+            .collect();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        #[cfg(unix)]
+        {
+            assert_eq!(names, vec!["a.txt", "b_dir/", "b_dir/inner.txt", "link", "m_dangling", "n_dir_link", "z.txt"]);
+            assert_eq!(entries[3].1.as_deref(), Some("a.txt"));
+            assert_eq!(entries[4].1.as_deref(), Some("missing"));
+            assert_eq!(entries[5].1.as_deref(), Some("b_dir"));
+        }
+        #[cfg(not(unix))]
+        assert_eq!(names, vec!["a.txt", "b_dir/", "b_dir/inner.txt", "z.txt"]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::file_explorer::copy_file;
+
+/// A file attribute a copy can be asked to preserve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attr {
+    /// Modification time, to the second. Access times change on read, so they are not checked.
+    Times,
+    /// Permission bits (the read-only flag outside Unix).
+    Mode,
+    /// Extended attributes.
+    Xattr,
+    /// Owning user and group.
+    Owner,
+}
+
+impl Attr {
+    /// Reads `--times`, `--mode`, `--xattr` and `--owner` from a command line.
+    pub fn from_args(parts: &[&str]) -> Vec<Attr> {
+        [("--times", Attr::Times), ("--mode", Attr::Mode), ("--xattr", Attr::Xattr), ("--owner", Attr::Owner)]
+            .into_iter()
+            .filter(|(flag, _)| parts.contains(flag))
+            .map(|(_, attr)| attr)
+            .collect()
+    }
+}
+
+impl fmt::Display for Attr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Attr::Times => "times",
+            Attr::Mode => "mode",
+            Attr::Xattr => "xattr",
+            Attr::Owner => "owner",
+        })
+    }
+}
+
+/// Whether one requested attribute survived the copy, with the reason when it didn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrCheck {
+    pub attr: Attr,
+    pub preserved: bool,
+    pub detail: String,
+}
+
+/// Copies the file `src` to `dst`, applies each requested attribute to the destination, then
+/// reads both files back and reports whether every attribute matches. Failing to apply an
+/// attribute (e.g. xattrs on a filesystem without them) is reported rather than returned as an error.
+pub fn copy_verify_attrs(src: &Path, dst: &Path, attrs: &[Attr]) -> io::Result<Vec<AttrCheck>> {
+    if src.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a directory", src.display())));
+    }
+    copy_file(src, dst)?;
+    let mut checks = Vec::new();
+    for &attr in attrs {
+        let check = match apply(attr, src, dst).and_then(|_| compare(attr, src, dst)) {
+            Ok(None) => AttrCheck { attr, preserved: true, detail: String::new() },
+            Ok(Some(detail)) => AttrCheck { attr, preserved: false, detail },
+            Err(e) => AttrCheck { attr, preserved: false, detail: e.to_string() },
+        };
+        checks.push(check);
+    }
+    Ok(checks)
+}
+
+/// Copies one attribute from `src` onto `dst`.
+fn apply(attr: Attr, src: &Path, dst: &Path) -> io::Result<()> {
+    let meta = fs::metadata(src)?;
+    match attr {
+        Attr::Times => filetime::set_file_times(
+            dst,
+            filetime::FileTime::from_last_access_time(&meta),
+            filetime::FileTime::from_last_modification_time(&meta),
+        ),
+        Attr::Mode => fs::set_permissions(dst, meta.permissions()),
+        Attr::Xattr => copy_xattrs(src, dst),
+        Attr::Owner => copy_owner(&meta, dst),
+    }
+}
+
+/// Returns a description of the difference, or `None` if the attribute matches.
+fn compare(attr: Attr, src: &Path, dst: &Path) -> io::Result<Option<String>> {
+    let (a, b) = (fs::metadata(src)?, fs::metadata(dst)?);
+    let differ = |what: String| Ok(Some(what));
+    match attr {
+        Attr::Times => {
+            let secs = |m: &fs::Metadata| filetime::FileTime::from_last_modification_time(m).unix_seconds();
+            if secs(&a) != secs(&b) {
+                return differ(format!("mtime {} -> {}", secs(&a), secs(&b)));
+            }
+        }
+        Attr::Mode => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = |m: &fs::Metadata| m.permissions().mode() & 0o7777;
+                if mode(&a) != mode(&b) {
+                    return differ(format!("mode {:o} -> {:o}", mode(&a), mode(&b)));
+                }
+            }
+            if a.permissions().readonly() != b.permissions().readonly() {
+                return differ("read-only flag differs".to_string());
+            }
+        }
+        Attr::Xattr => {
+            if read_xattrs(src)? != read_xattrs(dst)? {
+                return differ("extended attributes differ".to_string());
+            }
+        }
+        Attr::Owner => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                if (a.uid(), a.gid()) != (b.uid(), b.gid()) {
+                    return differ(format!("owner {}:{} -> {}:{}", a.uid(), a.gid(), b.uid(), b.gid()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("{} not supported on this platform", what))
+}
+
+#[cfg(target_os = "linux")]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Reads all extended attributes of a file as sorted (name, value) pairs.
+#[cfg(target_os = "linux")]
+fn read_xattrs(path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let path = c_path(path)?;
+    // SAFETY: a null buffer with size 0 asks only for the required length.
+    let len = unsafe { libc::listxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut names = vec![0u8; len as usize];
+    // SAFETY: `names` has room for `names.len()` bytes.
+    let len = unsafe { libc::listxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(len as usize);
+    let mut attrs = Vec::new();
+    for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        let c_name = std::ffi::CString::new(name).expect("split on NUL");
+        // SAFETY: as above, first query the length, then fill a buffer of that size.
+        let size = unsafe { libc::getxattr(path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe { libc::getxattr(path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        value.truncate(size as usize);
+        attrs.push((name.to_vec(), value));
+    }
+    attrs.sort();
+    Ok(attrs)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_xattrs(_path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Err(unsupported("Extended attributes"))
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let target = c_path(dst)?;
+    for (name, value) in read_xattrs(src)? {
+        let c_name = std::ffi::CString::new(name).expect("xattr names contain no NUL");
+        // SAFETY: the pointers and length describe live buffers for the duration of the call.
+        let rc = unsafe { libc::setxattr(target.as_ptr(), c_name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(unsupported("Extended attributes"))
+}
+
+#[cfg(unix)]
+fn copy_owner(meta: &fs::Metadata, dst: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    std::os::unix::fs::chown(dst, Some(meta.uid()), Some(meta.gid()))
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_meta: &fs::Metadata, _dst: &Path) -> io::Result<()> {
+    Err(unsupported("Ownership"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_copy_verify_times_and_mode() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src.sh");
+        let dst = temp_dir.path().join("dst.sh");
+        fs::write(&src, b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o750)).unwrap();
+        filetime::set_file_mtime(&src, filetime::FileTime::from_unix_time(1_500_000_000, 0)).unwrap();
+
+        let attrs = Attr::from_args(&["copy-verify-attrs", "a", "b", "--times", "--mode"]);
+        assert_eq!(attrs, vec![Attr::Times, Attr::Mode]);
+        let checks = copy_verify_attrs(&src, &dst, &attrs).unwrap();
+        assert_eq!(checks.len(), 2);
+        assert!(checks.iter().all(|c| c.preserved), "{:?}", checks);
+        assert_eq!(fs::metadata(&dst).unwrap().permissions().mode() & 0o777, 0o750);
+
+        assert!(compare(Attr::Times, &src, &dst).unwrap().is_none());
+        filetime::set_file_mtime(&dst, filetime::FileTime::from_unix_time(1_600_000_000, 0)).unwrap();
+        assert!(compare(Attr::Times, &src, &dst).unwrap().is_some());
+    }
+}
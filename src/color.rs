@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+const RESET: &str = "\x1b[0m";
+const DIR: &str = "\x1b[1;34m";
+const SYMLINK: &str = "\x1b[36m";
+const EXECUTABLE: &str = "\x1b[32m";
+
+/// When listings should use ANSI colors, as given by `--color=auto|always|never`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Color only when stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn parse(s: &str) -> Option<ColorMode> {
+        match s {
+            "auto" => Some(ColorMode::Auto),
+            "always" => Some(ColorMode::Always),
+            "never" => Some(ColorMode::Never),
+            _ => None,
+        }
+    }
+
+    /// Finds a `--color=MODE` argument, defaulting to `Auto` when absent or unrecognized.
+    pub fn from_args(parts: &[&str]) -> ColorMode {
+        parts
+            .iter()
+            .find_map(|p| p.strip_prefix("--color="))
+            .and_then(ColorMode::parse)
+            .unwrap_or_default()
+    }
+
+    /// Whether colors should be written; `Auto` turns them off when output is redirected.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// Picks the color for a directory entry: directories, symlinks and executables differ.
+fn entry_color(path: &Path) -> Option<&'static str> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    if meta.file_type().is_symlink() {
+        Some(SYMLINK)
+    } else if meta.is_dir() {
+        Some(DIR)
+    } else if is_executable(path, &meta) {
+        Some(EXECUTABLE)
+    } else {
+        None
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(_path: &Path, meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path, _meta: &fs::Metadata) -> bool {
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    matches!(ext.as_str(), "exe" | "bat" | "cmd" | "com" | "ps1")
+}
+
+/// Wraps `text` (usually the entry's name) in the color for `path` when `enabled`.
+pub fn paint(path: &Path, text: &str, enabled: bool) -> String {
+    match entry_color(path) {
+        Some(color) if enabled => format!("{}{}{}", color, text, RESET),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint_by_entry_kind() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("sub");
+        let file = temp_dir.path().join("plain.txt");
+        fs::create_dir(&dir).unwrap();
+        fs::write(&file, b"x").unwrap();
+
+        assert_eq!(paint(&dir, "sub/", true), format!("{}sub/{}", DIR, RESET));
+        assert_eq!(paint(&dir, "sub/", false), "sub/");
+        assert_eq!(paint(&file, "plain.txt", true), "plain.txt");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = temp_dir.path().join("run.sh");
+            fs::write(&script, b"#!/bin/sh\n").unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(paint(&script, "run.sh", true).starts_with(EXECUTABLE));
+            let link = temp_dir.path().join("link");
+            std::os::unix::fs::symlink(&dir, &link).unwrap();
+            assert!(paint(&link, "link", true).starts_with(SYMLINK));
+        }
+        assert_eq!(ColorMode::from_args(&["ls", "--color=never"]), ColorMode::Never);
+        assert_eq!(ColorMode::from_args(&["ls"]), ColorMode::Auto);
+        assert!(!ColorMode::Never.enabled());
+    }
+}
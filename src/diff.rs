@@ -0,0 +1,145 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A single line of a line-based diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Options controlling how lines are normalized before they are compared.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffOptions {
+    /// Collapse runs of whitespace and ignore leading/trailing whitespace.
+    pub ignore_whitespace: bool,
+    /// Ignore whitespace at the end of each line only.
+    pub ignore_trailing_whitespace: bool,
+    /// Skip lines that are empty after normalization.
+    pub ignore_blank_lines: bool,
+}
+
+/// Normalizes a line according to the diff options, producing the comparison key.
+pub fn normalize_line(line: &str, opts: &DiffOptions) -> String {
+    if opts.ignore_whitespace {
+        line.split_whitespace().collect::<Vec<_>>().join(" ")
+    } else if opts.ignore_trailing_whitespace {
+        line.trim_end().to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+/// Computes a line diff between two texts using a longest-common-subsequence table.
+pub fn diff_lines(a: &str, b: &str, opts: &DiffOptions) -> Vec<DiffLine> {
+    let keep = |line: &&str| !opts.ignore_blank_lines || !normalize_line(line, opts).is_empty();
+    let a_lines: Vec<&str> = a.lines().filter(keep).collect();
+    let b_lines: Vec<&str> = b.lines().filter(keep).collect();
+    let a_keys: Vec<String> = a_lines.iter().map(|l| normalize_line(l, opts)).collect();
+    let b_keys: Vec<String> = b_lines.iter().map(|l| normalize_line(l, opts)).collect();
+
+    let (n, m) = (a_keys.len(), b_keys.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a_keys[i] == b_keys[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a_keys[i] == b_keys[j] {
+            result.push(DiffLine::Same(a_lines[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(DiffLine::Removed(a_lines[i].to_string()));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(b_lines[j].to_string()));
+            j += 1;
+        }
+    }
+    result.extend(a_lines[i..].iter().map(|l| DiffLine::Removed(l.to_string())));
+    result.extend(b_lines[j..].iter().map(|l| DiffLine::Added(l.to_string())));
+    result
+}
+
+/// Reads two files and computes their line diff.
+pub fn diff_files(a: &Path, b: &Path, opts: &DiffOptions) -> io::Result<Vec<DiffLine>> {
+    let a_text = fs::read_to_string(a)?;
+    let b_text = fs::read_to_string(b)?;
+    Ok(diff_lines(&a_text, &b_text, opts))
+}
+
+/// Returns true if the diff contains any added or removed lines.
+pub fn has_changes(diff: &[DiffLine]) -> bool {
+    diff.iter().any(|l| !matches!(l, DiffLine::Same(_)))
+}
+
+/// Prints a diff in a simple unified-like format.
+pub fn print_diff(diff: &[DiffLine]) {
+    if !has_changes(diff) {
+        println!("Files are identical.");
+        return;
+    }
+    for line in diff {
+        match line {
+            DiffLine::Same(l) => println!("  {}", l),
+            DiffLine::Removed(l) => println!("- {}", l),
+            DiffLine::Added(l) => println!("+ {}", l),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indentation_only_difference() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let a = temp_dir.path().join("a.conf");
+        let b = temp_dir.path().join("b.conf");
+        fs::write(&a, "[section]\n  key = value\n  other  =  1\n").unwrap();
+        fs::write(&b, "[section]\n\tkey = value\n    other = 1   \n").unwrap();
+
+        let strict = diff_files(&a, &b, &DiffOptions::default()).unwrap();
+        assert!(has_changes(&strict));
+
+        let opts = DiffOptions { ignore_whitespace: true, ..Default::default() };
+        let relaxed = diff_files(&a, &b, &opts).unwrap();
+        assert!(!has_changes(&relaxed));
+    }
+
+    #[test]
+    fn test_trailing_whitespace_and_blank_lines() {
+        let a = "one\n\ntwo  \n";
+        let b = "one\ntwo\n";
+        let trailing = DiffOptions { ignore_trailing_whitespace: true, ..Default::default() };
+        assert!(has_changes(&diff_lines(a, b, &trailing)));
+        let blank = DiffOptions { ignore_blank_lines: true, ..trailing };
+        assert!(!has_changes(&diff_lines(a, b, &blank)));
+    }
+
+    #[test]
+    fn test_diff_reports_changed_line() {
+        let diff = diff_lines("a\nb\nc\n", "a\nx\nc\n", &DiffOptions::default());
+        assert_eq!(
+            diff,
+            vec![
+                DiffLine::Same("a".into()),
+                DiffLine::Removed("b".into()),
+                DiffLine::Added("x".into()),
+                DiffLine::Same("c".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file_explorer::{files_equal, walk_files};
+use crate::hash::{sha256_file, tree_hash, DirHash};
+
+/// Finds files under `root` with identical content. Files are first grouped by size and only
+/// those sharing a size are hashed, so unique sizes are never read; files with matching hashes
+/// are then compared byte for byte. Empty files are ignored.
+/// Each group is sorted, and groups are ordered by path.
+pub fn find_duplicates(root: &Path) -> io::Result<Vec<Vec<PathBuf>>> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for (relative, metadata) in walk_files(root)? {
+        if metadata.len() > 0 {
+            by_size.entry(metadata.len()).or_default().push(root.join(relative));
+        }
+    }
+
+    let mut groups = Vec::new();
+    for candidates in by_size.into_values().filter(|c| c.len() > 1) {
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in candidates {
+            by_hash.entry(sha256_file(&path)?).or_default().push(path);
+        }
+        for group in by_hash.into_values().filter(|g| g.len() > 1) {
+            let split = split_confirmed(group, |a, b| files_equal(a, b))?;
+            groups.extend(split.into_iter().filter(|g| g.len() > 1));
+        }
+    }
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort();
+    Ok(groups)
+}
+
+/// Splits `items` into groups whose members `same` confirms are equal to the group's first one.
+fn split_confirmed<T>(items: Vec<T>, same: impl Fn(&T, &T) -> io::Result<bool>) -> io::Result<Vec<Vec<T>>> {
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    'items: for item in items {
+        for group in &mut groups {
+            if same(&group[0], &item)? {
+                group.push(item);
+                continue 'items;
+            }
+        }
+        groups.push(vec![item]);
+    }
+    Ok(groups)
+}
+
+/// Whether two directories hold the same relative paths with identical contents. Symlinks
+/// match when they point at the same target.
+fn dirs_equal(a: &Path, b: &Path) -> io::Result<bool> {
+    let (a_files, b_files) = (walk_files(a)?, walk_files(b)?);
+    if a_files.len() != b_files.len() {
+        return Ok(false);
+    }
+    for ((a_rel, a_meta), (b_rel, b_meta)) in a_files.iter().zip(&b_files) {
+        let same = a_rel == b_rel
+            && a_meta.file_type().is_symlink() == b_meta.file_type().is_symlink()
+            && if a_meta.file_type().is_symlink() {
+                fs::read_link(a.join(a_rel))? == fs::read_link(b.join(b_rel))?
+            } else {
+                files_equal(&a.join(a_rel), &b.join(b_rel))?
+            };
+        if !same {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Prints each group of identical files.
+pub fn print_duplicates(groups: &[Vec<PathBuf>]) {
+    if groups.is_empty() {
+        println!("No duplicate files found.");
+        return;
+    }
+    for group in groups {
+        println!("Identical files:");
+        for path in group {
+            println!("  {}", path.display());
+        }
+    }
+}
+
+/// A group of directories with identical recursive content.
+#[derive(Debug)]
+pub struct DupeDirGroup {
+    pub size: u64,
+    pub dirs: Vec<PathBuf>,
+}
+
+/// Finds directories under `root` whose entire recursive content is identical. Directories
+/// with matching tree hashes are confirmed by comparing their files byte for byte.
+/// Groups nested inside another reported group are omitted, so only the topmost
+/// redundant copies are listed.
+pub fn find_duplicate_dirs(root: &Path) -> io::Result<Vec<DupeDirGroup>> {
+    let mut hashes: Vec<DirHash> = Vec::new();
+    tree_hash(root, &mut hashes)?;
+
+    let mut by_hash: HashMap<&str, Vec<&DirHash>> = HashMap::new();
+    for dir in hashes.iter().filter(|d| d.entries > 0 && d.path != root) {
+        by_hash.entry(dir.hash.as_str()).or_default().push(dir);
+    }
+    let mut confirmed: Vec<Vec<&DirHash>> = Vec::new();
+    for group in by_hash.into_values().filter(|g| g.len() > 1) {
+        let split = split_confirmed(group, |a, b| dirs_equal(&a.path, &b.path))?;
+        confirmed.extend(split.into_iter().filter(|g| g.len() > 1));
+    }
+    let duplicated: HashSet<&Path> = confirmed.iter().flatten().map(|d| d.path.as_path()).collect();
+
+    let mut groups: Vec<DupeDirGroup> = confirmed
+        .into_iter()
+        .filter(|g| {
+            !g.iter()
+                .all(|d| d.path.parent().is_some_and(|p| duplicated.contains(p)))
+        })
+        .map(|g| {
+            let mut dirs: Vec<PathBuf> = g.iter().map(|d| d.path.clone()).collect();
+            dirs.sort();
+            DupeDirGroup { size: g[0].size, dirs }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.dirs.cmp(&b.dirs)));
+    Ok(groups)
+}
+
+/// Prints each group of identical directories.
+pub fn print_duplicate_dirs(groups: &[DupeDirGroup]) {
+    if groups.is_empty() {
+        println!("No duplicate directories found.");
+        return;
+    }
+    for group in groups {
+        println!("Identical directories ({} bytes each):", group.size);
+        for dir in &group.dirs {
+            println!("  {}", dir.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn make_tree(root: &Path, extra: &str) {
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::write(root.join("notes.txt"), "backup notes").unwrap();
+        fs::write(root.join("photos/a.jpg"), format!("jpeg{}", extra)).unwrap();
+    }
+
+    #[test]
+    fn test_identical_subtrees_are_grouped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        make_tree(&root.join("backup1"), "");
+        make_tree(&root.join("backup2"), "");
+        make_tree(&root.join("other"), "changed");
+
+        let groups = find_duplicate_dirs(root).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].dirs, vec![root.join("backup1"), root.join("backup2")]);
+        assert_eq!(groups[0].size, 16);
+    }
+
+    #[test]
+    fn test_find_duplicates_by_content() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        make_tree(&root.join("backup1"), "");
+        make_tree(&root.join("backup2"), "");
+        fs::write(root.join("same_size.txt"), "backup_notes").unwrap();
+        fs::write(root.join("empty1"), "").unwrap();
+        fs::write(root.join("empty2"), "").unwrap();
+
+        let groups = find_duplicates(root).unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                vec![root.join("backup1/notes.txt"), root.join("backup2/notes.txt")],
+                vec![root.join("backup1/photos/a.jpg"), root.join("backup2/photos/a.jpg")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_matches_are_confirmed() {
+        let groups = split_confirmed(vec![1, 2, 3, 4, 5], |a, b| Ok(a % 2 == b % 2)).unwrap();
+        assert_eq!(groups, vec![vec![1, 3, 5], vec![2, 4]]);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        make_tree(&root.join("a"), "");
+        make_tree(&root.join("b"), "");
+        make_tree(&root.join("c"), "!");
+        assert!(dirs_equal(&root.join("a"), &root.join("b")).unwrap());
+        assert!(!dirs_equal(&root.join("a"), &root.join("c")).unwrap());
+        fs::write(root.join("b/extra.txt"), "extra").unwrap();
+        assert!(!dirs_equal(&root.join("a"), &root.join("b")).unwrap());
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Errors from the USB and OneDrive operations, distinguishing the failures callers may want to
+/// handle from plain I/O errors.
+#[derive(Debug, Error)]
+pub enum RuError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("USB device not found: {0}")]
+    UsbNotFound(String),
+    #[error("{0} is not a removable drive; refusing to modify it")]
+    NotRemovable(String),
+    #[error("Failed to eject USB device {0}")]
+    EjectFailed(String),
+    #[error("{0} holds the operating system or this program; refusing to modify it")]
+    SystemDrive(String),
+    #[error("{0} is write-protected (mounted read-only)")]
+    ReadOnly(String),
+    #[error("USB device {0} is busy; close any programs using it and try again")]
+    DeviceBusy(String),
+    #[error("Failed to format USB device {0}")]
+    FormatFailed(String),
+    #[error("Failed to relabel USB device {0}")]
+    LabelFailed(String),
+    #[error("Not enough space on {device}: {needed} bytes needed, {available} available")]
+    InsufficientSpace { device: String, needed: u64, available: u64 },
+    #[error("Checksum mismatch after copying to {path}: source sha256 {source_digest}, destination sha256 {dest_digest}")]
+    ChecksumMismatch { path: String, source_digest: String, dest_digest: String },
+    #[error("{} file(s) could not be copied: {}", .0.len(), describe_failures(.0))]
+    CopyFailed(Vec<(PathBuf, io::Error)>),
+    #[error("Rufus failed to create a bootable USB on {0}")]
+    RufusFailed(String),
+    #[error("Could not locate OneDrive Personal directory")]
+    OneDriveNotFound,
+}
+
+/// Lists each failed path with its error, for `CopyFailed`'s message.
+fn describe_failures(failures: &[(PathBuf, io::Error)]) -> String {
+    failures
+        .iter()
+        .map(|(path, e)| format!("{}: {}", path.display(), e))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Lets `RuError`s flow through code that works in `io::Result`, such as the explorer commands.
+impl From<RuError> for io::Error {
+    fn from(e: RuError) -> Self {
+        let kind = match &e {
+            RuError::Io(inner) => inner.kind(),
+            RuError::UsbNotFound(_) | RuError::OneDriveNotFound => io::ErrorKind::NotFound,
+            RuError::NotRemovable(_) | RuError::SystemDrive(_) => io::ErrorKind::PermissionDenied,
+            RuError::DeviceBusy(_) => io::ErrorKind::ResourceBusy,
+            RuError::ReadOnly(_) => io::ErrorKind::ReadOnlyFilesystem,
+            RuError::InsufficientSpace { .. } => io::ErrorKind::StorageFull,
+            RuError::ChecksumMismatch { .. } => io::ErrorKind::InvalidData,
+            RuError::CopyFailed(failures) if failures.len() == 1 => failures[0].1.kind(),
+            RuError::EjectFailed(_)
+            | RuError::FormatFailed(_)
+            | RuError::LabelFailed(_)
+            | RuError::RufusFailed(_)
+            | RuError::CopyFailed(_) => io::ErrorKind::Other,
+        };
+        match e {
+            RuError::Io(inner) => inner,
+            other => io::Error::new(kind, other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_round_trip() {
+        let err: RuError = io::Error::new(io::ErrorKind::NotFound, "gone").into();
+        assert!(matches!(err, RuError::Io(_)));
+        assert_eq!(io::Error::from(err).to_string(), "gone");
+
+        let err = io::Error::from(RuError::NotRemovable("C:".to_string()));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("C: is not a removable drive"));
+    }
+}
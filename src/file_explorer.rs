@@ -1,19 +1,64 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::env;
-use std::fs::{self, DirEntry, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::fmt;
+use std::fs::{self, DirEntry, File, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::archive::create_tar;
+use crate::attrs::{copy_verify_attrs, Attr};
+use crate::color::{self, ColorMode};
+use crate::dates::{days_since_epoch, format_date, format_day, parse_date, SECS_PER_DAY};
+use crate::diff::{diff_files, print_diff, DiffOptions};
+use crate::dupes::{find_duplicate_dirs, find_duplicates, print_duplicate_dirs, print_duplicates};
+use crate::fuzzy;
+use crate::hash::{copy_file_hashed, hash_file, repair_chunks, verify_chunks, HashAlgo, DEFAULT_VERIFY_CHUNK};
+use crate::manifest::{diff_permissions, verify_manifest, Manifest};
+use crate::onedrive::{cache_dir, cache_to_onedrive, CacheMode, DEFAULT_MAX_DELETE_PERCENT};
+use crate::options::{self, Options, OverwritePolicy, PartialOptions};
+use crate::owners;
+use crate::paths::{expand_path, home_dir, portablize_file, relative_to, resolve_file, to_portable};
+use crate::session::{self, Session};
+use crate::stats::{self, ScanOptions};
+use crate::sync::{self, sync_space_needed};
+use crate::tags::TagIndex;
+use crate::template::copy_with_template;
+use crate::transfer::{self, FdBudget, TransferQueue};
 
 /// Represents a file or directory and its metadata.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct FileItem {
     pub path: PathBuf,
     pub is_dir: bool,
     pub size: u64,
+    #[serde(serialize_with = "serialize_unix_time")]
     pub modified: Option<SystemTime>,
 }
 
+/// Serializes a timestamp as whole seconds since the Unix epoch, or null.
+fn serialize_unix_time<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(t) => serializer.serialize_some(&t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()),
+        None => serializer.serialize_none(),
+    }
+}
+
 impl FileItem {
     pub fn new(entry: &DirEntry) -> io::Result<Self> {
         let metadata = entry.metadata()?;
@@ -25,9 +70,28 @@ impl FileItem {
         })
     }
 
-    pub fn display(&self) {
+    /// Prints the entry with a human-readable size column, coloring the name when `color` is set.
+    pub fn display(&self, color: bool) {
+        println!("{}", self.line(false, color));
+    }
+
+    /// Prints the entry with the size in raw bytes, for scripts that parse the output.
+    pub fn display_raw(&self) {
+        println!("{}", self.line(true, false));
+    }
+
+    /// Writes the entry line to `out`; `raw` selects byte sizes as in `display_raw`.
+    pub fn display_to(&self, out: &mut dyn Write, raw: bool, color: bool) -> io::Result<()> {
+        writeln!(out, "{}", self.line(raw, color && !raw))
+    }
+
+    fn line(&self, raw: bool, color: bool) -> String {
         let file_type = if self.is_dir { "<DIR>" } else { "     " };
-        let size_disp = if self.is_dir { "".to_string() } else { format!("{:>10}", self.size) };
+        let size_disp = match (self.is_dir, raw) {
+            (true, _) => "".to_string(),
+            (false, true) => self.size.to_string(),
+            (false, false) => human_size(self.size),
+        };
         let mod_disp = match self.modified {
             Some(m) => {
                 let duration = m.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
@@ -35,21 +99,92 @@ impl FileItem {
             }
             None => "n/a".to_string(),
         };
-        println!(
-            "{} {:>10} {:<40} {}",
-            file_type,
-            size_disp,
-            self.path.file_name().unwrap().to_string_lossy(),
-            mod_disp
-        );
+        let name = format!("{:<40}", self.path.file_name().unwrap().to_string_lossy());
+        format!("{} {:>10} {} {}", file_type, size_disp, color::paint(&self.path, &name, color), mod_disp)
+    }
+}
+
+/// Formats a byte count with binary units, e.g. `512 B`, `1.0 KiB` or `4.2 GiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Step up a unit whenever the value would round to 1024.0 at one decimal
+    while value >= 1023.95 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Field a directory listing is sorted by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    pub fn parse(s: &str) -> Option<SortKey> {
+        match s.to_lowercase().as_str() {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "modified" | "mtime" | "time" => Some(SortKey::Modified),
+            _ => None,
+        }
     }
 }
 
-/// Lists contents in a directory with optional recursion.
-pub fn list_dir(path: &Path, recursive: bool) -> io::Result<()> {
+/// How `collect_dir` orders its results.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+    /// Place directories before files regardless of the key and direction.
+    pub dirs_first: bool,
+}
+
+impl SortOrder {
+    fn sort(&self, items: &mut [FileItem]) {
+        items.sort_by(|a, b| {
+            let by_key = match self.key {
+                SortKey::Name => a.path.cmp(&b.path),
+                SortKey::Size => a.size.cmp(&b.size).then_with(|| a.path.cmp(&b.path)),
+                SortKey::Modified => a.modified.cmp(&b.modified).then_with(|| a.path.cmp(&b.path)),
+            };
+            let by_key = if self.descending { by_key.reverse() } else { by_key };
+            if self.dirs_first {
+                b.is_dir.cmp(&a.is_dir).then(by_key)
+            } else {
+                by_key
+            }
+        });
+    }
+}
+
+/// Collects the entries of a directory, descending into subdirectories when `recursive`.
+/// `max_depth` limits the descent relative to `path`: depth 0 lists only `path` itself.
+/// Entries whose names match an `exclude` glob are skipped along with their subtrees.
+/// Hidden entries (see `is_hidden`) are skipped unless `show_hidden` is set.
+/// Directories that can't be read are skipped with a warning. The result is ordered by `sort`;
+/// names compare by full path so recursive listings stay grouped by directory.
+pub fn collect_dir(
+    path: &Path,
+    recursive: bool,
+    max_depth: Option<usize>,
+    exclude: &[String],
+    sort: &SortOrder,
+    show_hidden: bool,
+) -> io::Result<Vec<FileItem>> {
+    let mut items = Vec::new();
     let mut queue = VecDeque::new();
-    queue.push_back(path.to_path_buf());
-    while let Some(current_path) = queue.pop_front() {
+    queue.push_back((path.to_path_buf(), 0));
+    while let Some((current_path, depth)) = queue.pop_front() {
         let entries = match fs::read_dir(&current_path) {
             Ok(e) => e,
             Err(e) => {
@@ -57,57 +192,629 @@ pub fn list_dir(path: &Path, recursive: bool) -> io::Result<()> {
                 continue;
             }
         };
-        println!("\nListing: {:?}", current_path);
         for entry in entries {
             let entry = entry?;
+            if is_excluded(&entry.path(), exclude) || (!show_hidden && is_hidden(&entry)) {
+                continue;
+            }
             let file_item = FileItem::new(&entry)?;
-            file_item.display();
-            if recursive && file_item.is_dir {
-                queue.push_back(file_item.path.clone());
+            if recursive && file_item.is_dir && max_depth.is_none_or(|max| depth < max) {
+                queue.push_back((file_item.path.clone(), depth + 1));
             }
+            items.push(file_item);
+        }
+    }
+    sort.sort(&mut items);
+    Ok(items)
+}
+
+/// Returns true if an entry is hidden: its name starts with a dot, or on Windows it also
+/// has the hidden file attribute.
+pub fn is_hidden(entry: &DirEntry) -> bool {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    false
+}
+
+/// Returns true if the file name of `path` matches one of the `exclude` globs.
+/// Patterns that are not valid globs are compared literally.
+pub fn is_excluded(path: &Path, exclude: &[String]) -> bool {
+    let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+        return false;
+    };
+    exclude.iter().any(|pattern| match glob::Pattern::new(pattern) {
+        Ok(p) => p.matches(&name),
+        Err(_) => *pattern == name,
+    })
+}
+
+/// Options for `list_dir`, as set by the `ls` flags.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub recursive: bool,
+    /// Levels to descend below the listed directory; see `collect_dir`.
+    pub max_depth: Option<usize>,
+    pub exclude: Vec<String>,
+    pub sort: SortOrder,
+    /// Show sizes in bytes instead of human-readable units.
+    pub raw: bool,
+    pub color: bool,
+    /// Print the entries as a JSON array instead of columns.
+    pub json: bool,
+    /// Include hidden entries.
+    pub show_hidden: bool,
+    /// Group the entries under their owning user.
+    pub by_owner: bool,
+}
+
+/// Lists contents in a directory according to `opts`.
+pub fn list_dir(path: &Path, opts: &ListOptions) -> io::Result<()> {
+    list_dir_to(&mut io::stdout(), path, opts)
+}
+
+/// Like `list_dir`, writing the listing to `out`.
+pub fn list_dir_to(out: &mut dyn Write, path: &Path, opts: &ListOptions) -> io::Result<()> {
+    let items =
+        collect_dir(path, opts.recursive, opts.max_depth, &opts.exclude, &opts.sort, opts.show_hidden)?;
+    if opts.json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&items)?)?;
+        return Ok(());
+    }
+    writeln!(out, "\nListing: {:?}", path)?;
+    if !opts.by_owner {
+        for item in &items {
+            item.display_to(out, opts.raw, opts.color)?;
+        }
+        return Ok(());
+    }
+    let mut by_owner: BTreeMap<String, Vec<&FileItem>> = BTreeMap::new();
+    for item in &items {
+        let owner = fs::symlink_metadata(&item.path)
+            .ok()
+            .and_then(|m| owners::owner_ids(&m))
+            .map_or_else(|| "unknown".to_string(), |(uid, _)| owners::user_name(uid));
+        by_owner.entry(owner).or_default().push(item);
+    }
+    for (owner, items) in by_owner {
+        writeln!(out, "\n{}:", owner)?;
+        for item in items {
+            item.display_to(out, opts.raw, opts.color)?;
         }
     }
     Ok(())
 }
 
-/// Copies a file from src to dst.
+/// Copies a file from src to dst, along with its permissions and access and modification
+/// times. The data goes to a sibling temp file that is renamed over `dst` only once the copy
+/// and attributes are complete, so an interrupted copy never leaves a truncated destination.
 pub fn copy_file(src: &Path, dst: &Path) -> io::Result<u64> {
+    copy_file_with(src, dst, OverwritePolicy::Overwrite, true)
+}
+
+/// Like `copy_file`, with `overwrite` deciding whether an existing `dst` is replaced (nothing is
+/// copied when it is kept). Without `preserve_times` the copy gets the current time instead.
+pub fn copy_file_with(src: &Path, dst: &Path, overwrite: OverwritePolicy, preserve_times: bool) -> io::Result<u64> {
+    if !may_overwrite(dst, overwrite, &mut confirm_overwrite) {
+        return Ok(0);
+    }
+    // Taken before reading, since reading may update the source's access time.
+    let meta = fs::metadata(src)?;
     let mut src_file = File::open(src)?;
-    let mut dst_file = File::create(dst)?;
-    let copied = io::copy(&mut src_file, &mut dst_file)?;
-    fs::set_permissions(dst, fs::metadata(src)?.permissions())?;
-    Ok(copied)
+    copy_atomic(&mut src_file, &meta, dst, preserve_times)
 }
 
-/// Recursively copies a directory.
-pub fn copy_dir(src: &Path, dst: &Path) -> io::Result<()> {
-    if !dst.exists() {
-        fs::create_dir(dst)?;
+/// Numbers the temp files of copies running at once in this process.
+static COPY_TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes everything from `reader` to a temp file next to `dst`, gives it the permissions and
+/// (with `preserve_times`) the times from `meta`, then renames it into place. The temp file is
+/// removed on any error.
+fn copy_atomic<R: Read>(reader: &mut R, meta: &Metadata, dst: &Path, preserve_times: bool) -> io::Result<u64> {
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    let count = COPY_TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp = dst.with_file_name(format!(".{}.copy-{}-{}", name, std::process::id(), count));
+    let result = File::create(&temp).and_then(|mut out| {
+        let copied = io::copy(reader, &mut out)?;
+        drop(out);
+        fs::set_permissions(&temp, meta.permissions())?;
+        if preserve_times {
+            filetime::set_file_times(
+                &temp,
+                filetime::FileTime::from_last_access_time(meta),
+                filetime::FileTime::from_last_modification_time(meta),
+            )?;
+        }
+        fs::rename(&temp, dst)?;
+        Ok(copied)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
     }
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
+    result
+}
+
+/// Returns true if two files have identical contents.
+/// Files of different sizes are rejected without reading either, and the
+/// comparison stops at the first chunk that differs.
+pub fn files_equal(a: &Path, b: &Path) -> io::Result<bool> {
+    files_equal_counting(a, b, &mut 0)
+}
+
+/// Like `files_equal`, adding the number of bytes read from each file to `read`.
+fn files_equal_counting(a: &Path, b: &Path, read: &mut u64) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut a_file = BufReader::new(File::open(a)?);
+    let mut b_file = BufReader::new(File::open(b)?);
+    let mut a_buf = vec![0u8; 64 * 1024];
+    let mut b_buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = fill_buffer(&mut a_file, &mut a_buf)?;
+        let m = fill_buffer(&mut b_file, &mut b_buf)?;
+        *read += n as u64;
+        if n != m || a_buf[..n] != b_buf[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the bytes read.
+fn fill_buffer<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// How `copy_dir_with` treats symbolic links inside the tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    Skip,
+    /// Create a link at the destination pointing at the same target.
+    #[default]
+    Recreate,
+    /// Copy whatever the link points to. A link back to a folder that is already being copied
+    /// is skipped with a warning instead of recursing forever.
+    Follow,
+}
+
+impl SymlinkPolicy {
+    pub fn parse(s: &str) -> Option<SymlinkPolicy> {
+        match s {
+            "skip" => Some(SymlinkPolicy::Skip),
+            "recreate" => Some(SymlinkPolicy::Recreate),
+            "follow" => Some(SymlinkPolicy::Follow),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SymlinkPolicy::Skip => "skip",
+            SymlinkPolicy::Recreate => "recreate",
+            SymlinkPolicy::Follow => "follow",
+        })
+    }
+}
+
+/// Recursively copies a directory, creating `dst` and any missing parents. Copying into an
+/// existing directory merges into it, replacing files of the same name. Symlinks are
+/// recreated as links. Errors name the file or directory that failed.
+pub fn copy_dir(src: &Path, dst: &Path) -> io::Result<()> {
+    copy_dir_with(src, dst, SymlinkPolicy::default(), OverwritePolicy::Overwrite)
+}
+
+/// Like `copy_dir`, with `symlinks` choosing what happens to symbolic links and `overwrite`
+/// deciding, file by file, whether existing files in a merge are replaced.
+pub fn copy_dir_with(src: &Path, dst: &Path, symlinks: SymlinkPolicy, overwrite: OverwritePolicy) -> io::Result<()> {
+    copy_dir_each(src, dst, symlinks, &mut |from, to| copy_file_with(from, to, overwrite, true)).map(|_| ())
+}
+
+/// Walks `src` as `copy_dir_with` does, handing each regular file to `copy` and returning the
+/// total bytes it reports.
+fn copy_dir_each(
+    src: &Path,
+    dst: &Path,
+    symlinks: SymlinkPolicy,
+    copy: &mut dyn FnMut(&Path, &Path) -> io::Result<u64>,
+) -> io::Result<u64> {
+    let canonical = src.canonicalize().map_err(|e| io::Error::new(e.kind(), format!("{}: {}", src.display(), e)))?;
+    copy_dir_walk(src, dst, symlinks, copy, &mut HashSet::from([canonical]))
+}
+
+/// The recursion behind `copy_dir_each`. `ancestors` holds the canonical paths of the folders
+/// currently being copied, so a followed link back to one of them is caught.
+fn copy_dir_walk(
+    src: &Path,
+    dst: &Path,
+    symlinks: SymlinkPolicy,
+    copy: &mut dyn FnMut(&Path, &Path) -> io::Result<u64>,
+    ancestors: &mut HashSet<PathBuf>,
+) -> io::Result<u64> {
+    let context = |path: &Path, e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+    let mut copied = 0;
+    fs::create_dir_all(dst).map_err(|e| context(dst, e))?;
+    for entry in fs::read_dir(src).map_err(|e| context(src, e))? {
+        let entry = entry.map_err(|e| context(src, e))?;
         let src_path = entry.path();
         let dst_path = dst.join(entry.file_name());
+        let is_link = fs::symlink_metadata(&src_path).map_err(|e| context(&src_path, e))?.file_type().is_symlink();
+        match symlinks {
+            SymlinkPolicy::Skip if is_link => continue,
+            SymlinkPolicy::Recreate if is_link => {
+                copy_symlink(&src_path, &dst_path).map_err(|e| context(&src_path, e))?;
+                continue;
+            }
+            _ => {}
+        }
         if src_path.is_dir() {
-            copy_dir(&src_path, &dst_path)?;
+            let canonical = src_path.canonicalize().map_err(|e| context(&src_path, e))?;
+            if ancestors.contains(&canonical) {
+                eprintln!("Skipping {}: it links back to a folder being copied", src_path.display());
+                continue;
+            }
+            ancestors.insert(canonical.clone());
+            copied += copy_dir_walk(&src_path, &dst_path, symlinks, copy, ancestors)?;
+            ancestors.remove(&canonical);
         } else {
+            copied += copy(&src_path, &dst_path).map_err(|e| context(&src_path, e))?;
+        }
+    }
+    Ok(copied)
+}
+
+/// Creates a link at `dst` with the same target as the link at `src`, replacing any existing
+/// file or link there.
+fn copy_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+    if fs::symlink_metadata(dst).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(dst)?;
+    }
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(&target, dst);
+    #[cfg(windows)]
+    return if src.is_dir() {
+        std::os::windows::fs::symlink_dir(&target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(&target, dst)
+    };
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot create a link to {} on this platform", target.display()),
+    ));
+}
+
+/// Copies a file or directory tree according to resolved copy options, returning the bytes copied.
+/// Existing destination files are replaced, skipped or asked about as `opts.overwrite` says,
+/// one file at a time when merging into a directory.
+pub fn copy_with_options(src: &Path, dst: &Path, opts: &Options) -> io::Result<u64> {
+    copy_with_options_confirming(src, dst, opts, &mut confirm_overwrite)
+}
+
+/// Asks on stdin whether to replace an existing file; anything but "y" or "yes" declines.
+fn confirm_overwrite(path: &Path) -> bool {
+    print!("Overwrite {}? [y/N] ", path.display());
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Like `copy_with_options`, with `confirm` deciding about each existing destination file
+/// under `OverwritePolicy::Prompt`, so a directory merge can skip only the conflicting files.
+fn copy_with_options_confirming(
+    src: &Path,
+    dst: &Path,
+    opts: &Options,
+    confirm: &mut dyn FnMut(&Path) -> bool,
+) -> io::Result<u64> {
+    if opts.verbose {
+        println!("Effective options: {}", opts);
+    }
+    if !src.is_dir() {
+        return copy_one_with_options(src, dst, opts, confirm);
+    }
+    copy_dir_each(src, dst, opts.symlinks, &mut |from, to| copy_one_with_options(from, to, opts, confirm))
+}
+
+/// Whether a copy onto `dst` may go ahead under `policy`.
+fn may_overwrite(dst: &Path, policy: OverwritePolicy, confirm: &mut dyn FnMut(&Path) -> bool) -> bool {
+    match policy {
+        _ if !dst.exists() => true,
+        OverwritePolicy::Overwrite => true,
+        OverwritePolicy::Skip => false,
+        OverwritePolicy::Prompt => confirm(dst),
+    }
+}
+
+fn copy_one_with_options(
+    src: &Path,
+    dst: &Path,
+    opts: &Options,
+    confirm: &mut dyn FnMut(&Path) -> bool,
+) -> io::Result<u64> {
+    if !may_overwrite(dst, opts.overwrite, confirm) {
+        println!("Skipped existing {}", dst.display());
+        return Ok(0);
+    }
+    let copied = copy_file_with(src, dst, OverwritePolicy::Overwrite, opts.preserve_times)?;
+    if opts.verify && !files_equal(src, dst)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Verification failed for {}", dst.display()),
+        ));
+    }
+    if opts.verbose {
+        println!("Copied {} -> {}", src.display(), dst.display());
+    }
+    Ok(copied)
+}
+
+/// Files touched while finishing an interrupted directory copy.
+#[derive(Debug, Default)]
+pub struct ResumeReport {
+    /// Files that were missing and copied from scratch.
+    pub copied: Vec<PathBuf>,
+    /// Short files that were completed by appending the missing tail.
+    pub resumed: Vec<PathBuf>,
+    /// Files that were larger than the source or failed verification and were copied again.
+    pub recopied: Vec<PathBuf>,
+    pub skipped: usize,
+}
+
+/// Finishes a directory copy that died partway. Missing files are copied, files shorter
+/// than their source are completed from where they stopped, and larger (corrupt) files are
+/// copied again. With `verify`, same-size and resumed files are also compared byte-for-byte.
+pub fn resume_copy_dir(src: &Path, dst: &Path, verify: bool) -> io::Result<ResumeReport> {
+    let mut report = ResumeReport::default();
+    for (relative, src_meta) in walk_files(src)? {
+        let src_path = src.join(&relative);
+        let dst_path = dst.join(&relative);
+        if let Some(parent) = dst_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let dst_len = match fs::metadata(&dst_path) {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                copy_file(&src_path, &dst_path)?;
+                report.copied.push(relative);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if dst_len > src_meta.len() {
+            copy_file(&src_path, &dst_path)?;
+            report.recopied.push(relative);
+        } else if dst_len < src_meta.len() {
+            append_from(&src_path, &dst_path, dst_len)?;
+            if verify && !files_equal(&src_path, &dst_path)? {
+                copy_file(&src_path, &dst_path)?;
+                report.recopied.push(relative);
+            } else {
+                report.resumed.push(relative);
+            }
+        } else if verify && !files_equal(&src_path, &dst_path)? {
             copy_file(&src_path, &dst_path)?;
+            report.recopied.push(relative);
+        } else {
+            report.skipped += 1;
+        }
+        if fs::metadata(&dst_path)?.len() != src_meta.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Size mismatch after copying {}", dst_path.display()),
+            ));
+        }
+    }
+    Ok(report)
+}
+
+/// Name of the log written to the destination of `salvage_copy_dir` when files are quarantined.
+pub const QUARANTINE_LOG: &str = "quarantine.log";
+
+/// Result of a directory copy that tolerates unreadable files.
+#[derive(Debug, Default)]
+pub struct SalvageReport {
+    pub copied: usize,
+    pub bytes: u64,
+    /// Files that still failed after every retry, with the last error.
+    pub quarantined: Vec<(PathBuf, String)>,
+}
+
+/// Runs `op`, retrying it up to `retries` more times after a failure with a growing pause
+/// (100ms, 200ms, ...) in between. Returns the last result.
+pub fn with_retries<T>(retries: u32, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(_) if attempt < retries => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(100 * attempt as u64));
+            }
+            result => return result,
         }
     }
-    Ok(())
 }
 
-/// Moves a file or directory.
+/// Copies a directory tree, retrying each failed file up to `retries` more times. Files that
+/// still cannot be copied are recorded in `quarantine.log` in `dst` and skipped, so a few bad
+/// sectors don't abort a whole backup.
+pub fn salvage_copy_dir(src: &Path, dst: &Path, retries: u32) -> io::Result<SalvageReport> {
+    salvage_copy_dir_with(src, dst, retries, &copy_file)
+}
+
+fn salvage_copy_dir_with(
+    src: &Path,
+    dst: &Path,
+    retries: u32,
+    copy: &dyn Fn(&Path, &Path) -> io::Result<u64>,
+) -> io::Result<SalvageReport> {
+    let mut report = SalvageReport::default();
+    fs::create_dir_all(dst)?;
+    for (relative, _) in walk_files(src)? {
+        let src_path = src.join(&relative);
+        let dst_path = dst.join(&relative);
+        if let Some(parent) = dst_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match with_retries(retries, || copy(&src_path, &dst_path)) {
+            Ok(bytes) => {
+                report.copied += 1;
+                report.bytes += bytes;
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&dst_path);
+                eprintln!("Quarantined {}: {}", relative.display(), e);
+                report.quarantined.push((relative, e.to_string()));
+            }
+        }
+    }
+    if !report.quarantined.is_empty() {
+        let mut log = OpenOptions::new().create(true).append(true).open(dst.join(QUARANTINE_LOG))?;
+        for (relative, error) in &report.quarantined {
+            writeln!(log, "{}\t{}", relative.display(), error)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Appends the bytes of `src` beyond `offset` to `dst`.
+fn append_from(src: &Path, dst: &Path, offset: u64) -> io::Result<u64> {
+    let mut src_file = File::open(src)?;
+    src_file.seek(SeekFrom::Start(offset))?;
+    let mut dst_file = OpenOptions::new().append(true).open(dst)?;
+    io::copy(&mut src_file, &mut dst_file)
+}
+
+/// Moves a file or directory. Files are renamed, falling back to copy and delete when the
+/// destination is on another filesystem. Directories are copied, and the source is only
+/// removed once every file in the copy has been compared with its original.
 pub fn move_path(src: &Path, dst: &Path) -> io::Result<()> {
+    move_path_with(src, dst, &|from, to| fs::rename(from, to))
+}
+
+fn move_path_with(src: &Path, dst: &Path, rename: &dyn Fn(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
     if src.is_dir() {
         copy_dir(src, dst)?;
+        for (relative, meta) in walk_files(src)? {
+            let (from, to) = (src.join(&relative), dst.join(&relative));
+            // Links were recreated rather than followed, so only their targets need to match.
+            let same = if meta.file_type().is_symlink() {
+                fs::read_link(&from)? == fs::read_link(&to)?
+            } else {
+                files_equal(&from, &to)?
+            };
+            if !same {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Copy of {} differs; source kept", src.join(&relative).display()),
+                ));
+            }
+        }
         fs::remove_dir_all(src)?;
-    } else {
-        fs::rename(src, dst)?;
+        return Ok(());
+    }
+    match rename(src, dst) {
+        // EXDEV (18 on Linux) or ERROR_NOT_SAME_DEVICE on Windows.
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_file(src, dst)?;
+            if !files_equal(src, dst)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Copy of {} differs; source kept", src.display()),
+                ));
+            }
+            fs::remove_file(src)
+        }
+        result => result,
+    }
+}
+
+/// Moves a file or directory and carries any tags attached to it over to the new path.
+pub fn move_tagged(src: &Path, dst: &Path, tags: &mut TagIndex) -> io::Result<()> {
+    move_path(src, dst)?;
+    if tags.rename_path(src, dst) {
+        tags.save()?;
     }
     Ok(())
 }
 
+/// Exchanges two existing paths. On Linux this uses `renameat2(RENAME_EXCHANGE)`, which is
+/// atomic; elsewhere (or if the filesystem doesn't support it) it falls back to three renames
+/// through a temporary name.
+pub fn swap_paths(a: &Path, b: &Path) -> io::Result<()> {
+    fs::symlink_metadata(a)?;
+    fs::symlink_metadata(b)?;
+    #[cfg(target_os = "linux")]
+    match exchange_paths_atomic(a, b) {
+        Ok(()) => return Ok(()),
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EINVAL) | Some(libc::EOPNOTSUPP)) => {}
+        Err(e) => return Err(e),
+    }
+    swap_paths_with_temp(a, b)
+}
+
+/// Atomically exchanges two paths with `renameat2(RENAME_EXCHANGE)`.
+#[cfg(target_os = "linux")]
+fn exchange_paths_atomic(a: &Path, b: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_c = |p: &Path| {
+        CString::new(p.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (a_c, b_c) = (to_c(a)?, to_c(b)?);
+    // SAFETY: both pointers come from live CStrings, and AT_FDCWD resolves them like rename(2)
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            a_c.as_ptr(),
+            libc::AT_FDCWD,
+            b_c.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Swaps two paths with three renames, restoring `a` if the second rename fails.
+fn swap_paths_with_temp(a: &Path, b: &Path) -> io::Result<()> {
+    let name = a.file_name().unwrap_or_default().to_string_lossy();
+    let temp = a.with_file_name(format!(".{}.swap-{}", name, std::process::id()));
+    fs::rename(a, &temp)?;
+    if let Err(e) = fs::rename(b, a) {
+        fs::rename(&temp, a)?;
+        return Err(e);
+    }
+    fs::rename(&temp, b)
+}
+
 /// Deletes a file or directory (recursive for directories).
 pub fn delete_path(path: &Path) -> io::Result<()> {
     if path.is_dir() {
@@ -118,6 +825,44 @@ pub fn delete_path(path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Name of the per-user trash directory under the home directory.
+pub const TRASH_DIR: &str = ".ruforus_trash";
+
+/// The trash directory: `~/.ruforus_trash`, or under the temp directory when there is no home.
+pub fn trash_dir() -> PathBuf {
+    home_dir().unwrap_or_else(env::temp_dir).join(TRASH_DIR)
+}
+
+/// Moves a file or directory into the trash and returns its new location.
+pub fn trash_path(path: &Path) -> io::Result<PathBuf> {
+    trash_path_in(path, &trash_dir())
+}
+
+/// Moves `path` into `trash`, numbering the name as "name (2).ext" when it is already taken.
+/// A move across filesystems falls back to copying and then deleting the original.
+fn trash_path_in(path: &Path, trash: &Path) -> io::Result<PathBuf> {
+    fs::symlink_metadata(path)?;
+    fs::create_dir_all(trash)?;
+    let name = Path::new(path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot trash {}", path.display()))
+    })?);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = name.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let target = std::iter::once(trash.join(name))
+        .chain((2..).map(|n| trash.join(format!("{} ({}){}", stem, n, ext))))
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .expect("unbounded range always yields a free name");
+    if path.is_dir() {
+        fs::rename(path, &target).or_else(|e| match e.kind() {
+            io::ErrorKind::CrossesDevices => move_path(path, &target),
+            _ => Err(e),
+        })?;
+    } else {
+        move_path(path, &target)?;
+    }
+    Ok(target)
+}
+
 /// Reads the contents of a file and prints to stdout.
 pub fn cat_file(path: &Path) -> io::Result<()> {
     let mut file = File::open(path)?;
@@ -127,6 +872,129 @@ pub fn cat_file(path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Pipes the bytes of `src` through an external command and writes its stdout to `dest`,
+/// or back over `src` via a temporary file and rename when `dest` is `None`.
+/// Input is fed from a separate thread so large outputs cannot deadlock the pipes.
+/// A non-zero exit status is an error and leaves the destination untouched.
+pub fn filter_file(src: &Path, program: &str, args: &[&str], dest: Option<&Path>) -> io::Result<u64> {
+    let mut input = File::open(src)?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let feeder = thread::spawn(move || io::copy(&mut input, &mut stdin));
+
+    let target = dest.unwrap_or(src);
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = target.with_file_name(format!(".{}.filter-{}", name, std::process::id()));
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let written = File::create(&temp).and_then(|mut out| io::copy(&mut stdout, &mut out));
+    if written.is_err() {
+        // Nothing is draining the output any more, so a chatty command would block forever.
+        drop(stdout);
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    // A command that exits without reading all of its input closes the pipe early; that is
+    // not an error in itself, so the feeder's result only matters if the command succeeded.
+    let fed = feeder.join().expect("stdin feeder panicked");
+
+    let result = written.and_then(|bytes| {
+        if !status.success() {
+            return Err(io::Error::other(format!("{} exited with {}", program, status)));
+        }
+        match fed {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+        fs::rename(&temp, target)?;
+        Ok(bytes)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// A byte order mark at the start of a text file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bom {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Bom {
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            Bom::Utf8 => &[0xef, 0xbb, 0xbf],
+            Bom::Utf16Le => &[0xff, 0xfe],
+            Bom::Utf16Be => &[0xfe, 0xff],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bom::Utf8 => "UTF-8",
+            Bom::Utf16Le => "UTF-16LE",
+            Bom::Utf16Be => "UTF-16BE",
+        }
+    }
+
+    /// Recognizes the BOM at the start of `data`, if any.
+    pub fn detect(data: &[u8]) -> Option<Bom> {
+        [Bom::Utf8, Bom::Utf16Le, Bom::Utf16Be].into_iter().find(|b| data.starts_with(b.bytes()))
+    }
+}
+
+/// Reports which BOM, if any, the file starts with.
+pub fn detect_bom(path: &Path) -> io::Result<Option<Bom>> {
+    let mut head = Vec::with_capacity(3);
+    File::open(path)?.take(3).read_to_end(&mut head)?;
+    Ok(Bom::detect(&head))
+}
+
+/// Rewrites `path` as `prefix` followed by its content from `skip` bytes on, through a
+/// temporary file that is renamed over the original so a failure never leaves it half-written.
+fn rewrite_with_prefix(path: &Path, prefix: &[u8], skip: u64) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.bom-{}", name, std::process::id()));
+    let result = (|| {
+        let mut input = File::open(path)?;
+        input.seek(SeekFrom::Start(skip))?;
+        let mut out = File::create(&temp)?;
+        out.write_all(prefix)?;
+        io::copy(&mut input, &mut out)?;
+        out.sync_all()?;
+        fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Removes a leading BOM from the file and returns the one removed.
+pub fn strip_bom(path: &Path) -> io::Result<Option<Bom>> {
+    let bom = detect_bom(path)?;
+    if let Some(b) = bom {
+        rewrite_with_prefix(path, &[], b.bytes().len() as u64)?;
+    }
+    Ok(bom)
+}
+
+/// Prepends a UTF-8 BOM unless the file already starts with a BOM; returns whether one was added.
+pub fn add_utf8_bom(path: &Path) -> io::Result<bool> {
+    if detect_bom(path)?.is_some() {
+        return Ok(false);
+    }
+    rewrite_with_prefix(path, Bom::Utf8.bytes(), 0)?;
+    Ok(true)
+}
+
 /// Creates an empty file or updates the modification time.
 pub fn touch_file(path: &Path) -> io::Result<()> {
     if path.exists() {
@@ -138,6 +1006,13 @@ pub fn touch_file(path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Creates a directory, along with any missing parents when `parents` is set.
+/// Errors name the directory that could not be created.
+pub fn make_dir(path: &Path, parents: bool) -> io::Result<()> {
+    let result = if parents { fs::create_dir_all(path) } else { fs::create_dir(path) };
+    result.map_err(|e| io::Error::new(e.kind(), format!("Cannot create directory {}: {}", path.display(), e)))
+}
+
 /// Renames a file or directory.
 pub fn rename_path(src: &Path, dst: &Path) -> io::Result<()> {
     fs::rename(src, dst)?;
@@ -157,52 +1032,574 @@ pub fn change_dir(path: &Path) -> io::Result<()> {
     Ok(())
 }
 
-/// Searches for files by name pattern in the directory tree.
-pub fn search_files(root: &Path, pattern: &str) -> io::Result<()> {
-    let mut stack = VecDeque::new();
-    stack.push_back(root.to_path_buf());
-    while let Some(current) = stack.pop_front() {
-        let entries = match fs::read_dir(&current) {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
-        for entry in entries {
+/// Lists every file under `root` with its metadata, keyed by path relative to `root`.
+pub fn walk_files(root: &Path) -> io::Result<Vec<(PathBuf, Metadata)>> {
+    let mut files = Vec::new();
+    if !root.is_dir() {
+        return Ok(files);
+    }
+    let mut queue = VecDeque::new();
+    queue.push_back(root.to_path_buf());
+    while let Some(dir) = queue.pop_front() {
+        for entry in fs::read_dir(&dir)? {
             let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                stack.push_back(path.clone());
-            } else if let Some(name) = path.file_name() {
-                if name.to_string_lossy().contains(pattern) {
-                    println!("{}", path.display());
-                }
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                queue.push_back(entry.path());
+            } else {
+                let relative = entry.path().strip_prefix(root).unwrap().to_path_buf();
+                files.push((relative, metadata));
             }
         }
     }
-    Ok(())
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
 }
 
-/// Gets file metadata and prints details.
-pub fn stat_file(path: &Path) -> io::Result<()> {
-    let metadata = fs::metadata(path)?;
-    println!("Path: {}", path.display());
-    println!("Is directory: {}", metadata.is_dir());
-    println!("Size: {}", metadata.len());
-    if let Ok(modified) = metadata.modified() {
-        println!("Modified: {:?}", modified);
+/// Entropy (bits/byte) above which data is very likely already compressed or encrypted.
+pub const HIGH_ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// Computes the Shannon entropy of a file's bytes in bits per byte (0.0 to 8.0),
+/// streaming the file into a byte histogram.
+pub fn file_entropy(path: &Path) -> io::Result<f64> {
+    let mut file = File::open(path)?;
+    let mut counts = [0u64; 256];
+    let mut total = 0u64;
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        for &b in &buffer[..n] {
+            counts[b as usize] += 1;
+        }
+        total += n as u64;
     }
-    Ok(())
+    if total == 0 {
+        return Ok(0.0);
+    }
+    Ok(counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum())
 }
 
-/// Reads a file line by line.
-pub fn read_lines(path: &Path) -> io::Result<()> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    for (i, line) in reader.lines().enumerate() {
-        println!("{:>4}: {}", i + 1, line?);
+/// Files modified on a single day.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DayGroup {
+    pub date: String,
+    pub count: usize,
+    pub size: u64,
+}
+
+/// Groups files under `root` by modification date, most recent first.
+/// With `days`, only files modified within the last `days` days (counting today) are included.
+pub fn timeline(root: &Path, days: Option<i64>, now: SystemTime) -> io::Result<Vec<DayGroup>> {
+    let today = days_since_epoch(now);
+    let mut by_day: BTreeMap<i64, (usize, u64)> = BTreeMap::new();
+    for (_, metadata) in walk_files(root)? {
+        let Ok(modified) = metadata.modified() else { continue };
+        let day = days_since_epoch(modified);
+        if days.is_some_and(|n| day <= today - n) {
+            continue;
+        }
+        let group = by_day.entry(day).or_default();
+        group.0 += 1;
+        group.1 += metadata.len();
+    }
+    Ok(by_day
+        .into_iter()
+        .rev()
+        .map(|(day, (count, size))| DayGroup { date: format_day(day), count, size })
+        .collect())
+}
+
+/// Files in one directory counted by how recently they were modified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Heat {
+    /// Modified on the same day as `now`.
+    pub today: usize,
+    /// Modified in the 6 days before today.
+    pub week: usize,
+    /// Modified 7 to 29 days before today.
+    pub month: usize,
+    pub older: usize,
+}
+
+/// Counts the files directly inside each directory under `root` by modification window,
+/// keyed by the directory relative to `root` (empty for `root` itself).
+pub fn heatmap(root: &Path, now: SystemTime) -> io::Result<BTreeMap<PathBuf, Heat>> {
+    let today = days_since_epoch(now);
+    let mut heat: BTreeMap<PathBuf, Heat> = BTreeMap::new();
+    for (relative, metadata) in walk_files(root)? {
+        let dir = relative.parent().unwrap_or(Path::new("")).to_path_buf();
+        let entry = heat.entry(dir).or_default();
+        let age = metadata.modified().map(|m| today - days_since_epoch(m)).unwrap_or(i64::MAX);
+        match age {
+            ..=0 => entry.today += 1,
+            1..=6 => entry.week += 1,
+            7..=29 => entry.month += 1,
+            _ => entry.older += 1,
+        }
+    }
+    Ok(heat)
+}
+
+/// Newest and oldest modification times in a tree, for checking that a backup is current.
+#[derive(Debug)]
+pub struct Freshness {
+    pub files: usize,
+    pub newest: Option<(PathBuf, SystemTime)>,
+    pub oldest: Option<(PathBuf, SystemTime)>,
+    /// Median age of the files in days, relative to `now`.
+    pub median_age_days: Option<f64>,
+    /// Files older than the requested number of days, oldest first.
+    pub stale: Vec<PathBuf>,
+}
+
+/// Reports the newest, oldest and median file ages under `root`, listing the files
+/// older than `stale_days` days when given. Files without a modification time are ignored.
+pub fn freshness(root: &Path, stale_days: Option<i64>, now: SystemTime) -> io::Result<Freshness> {
+    let mut files: Vec<(PathBuf, SystemTime)> = walk_files(root)?
+        .into_iter()
+        .filter_map(|(relative, metadata)| metadata.modified().ok().map(|m| (relative, m)))
+        .collect();
+    files.sort_by_key(|(_, modified)| *modified);
+
+    let age_secs = |t: SystemTime| match now.duration_since(t) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    };
+    let median_age_days = match files.len() {
+        0 => None,
+        n if n % 2 == 1 => Some(age_secs(files[n / 2].1)),
+        n => Some((age_secs(files[n / 2 - 1].1) + age_secs(files[n / 2].1)) / 2.0),
+    }
+    .map(|secs| secs / SECS_PER_DAY as f64);
+    let stale = match stale_days {
+        Some(days) => files
+            .iter()
+            .take_while(|(_, modified)| age_secs(*modified) > (days * SECS_PER_DAY) as f64)
+            .map(|(relative, _)| relative.clone())
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Freshness {
+        files: files.len(),
+        newest: files.last().cloned(),
+        oldest: files.first().cloned(),
+        median_age_days,
+        stale,
+    })
+}
+
+/// How `search_files` decides whether a file matches.
+#[derive(Debug, Clone)]
+pub enum NameMatcher {
+    /// Plain substring of the file name.
+    Substring(String),
+    /// Shell-style wildcard. Patterns containing `/` match the path relative to the search root,
+    /// others match the file name only.
+    Glob(glob::Pattern),
+    /// Regular expression over the file name.
+    Regex(Regex),
+    /// Matches if any of the inner matchers does.
+    Any(Vec<NameMatcher>),
+    /// Matches only if all of the inner matchers do.
+    All(Vec<NameMatcher>),
+}
+
+impl NameMatcher {
+    /// Compiles a glob pattern, reporting invalid patterns as an `InvalidInput` error.
+    pub fn glob(pattern: &str) -> io::Result<Self> {
+        glob::Pattern::new(pattern).map(NameMatcher::Glob).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid glob '{}': {}", pattern, e))
+        })
+    }
+
+    /// Parses a single pattern: globs if it contains wildcards, otherwise a substring.
+    pub fn parse(pattern: &str) -> io::Result<Self> {
+        let pattern = pattern.trim_matches('"');
+        if pattern.contains(['*', '?', '[']) {
+            NameMatcher::glob(pattern)
+        } else {
+            Ok(NameMatcher::Substring(pattern.to_string()))
+        }
+    }
+
+    /// Parses a list of patterns, where `-e PATTERN` introduces a regular expression.
+    /// Regular expressions are compiled case-insensitively when `case_insensitive` is set.
+    pub fn parse_list(args: &[&str], case_insensitive: bool) -> io::Result<Vec<Self>> {
+        let mut matchers = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if *arg == "-e" {
+                let pattern = iter.next().map(|p| p.trim_matches('"')).unwrap_or_default();
+                matchers.push(NameMatcher::Regex(build_regex(pattern, case_insensitive)?));
+            } else {
+                matchers.push(NameMatcher::parse(arg)?);
+            }
+        }
+        Ok(matchers)
+    }
+
+    /// Checks a file given its path relative to the search root.
+    /// With `case_insensitive`, ASCII letters always compare equal regardless of case;
+    /// folding of non-ASCII characters is best-effort.
+    pub fn matches(&self, relative: &Path, case_insensitive: bool) -> bool {
+        let name = relative.file_name().unwrap_or_default().to_string_lossy();
+        let options = glob::MatchOptions { case_sensitive: !case_insensitive, ..Default::default() };
+        match self {
+            NameMatcher::Substring(s) if case_insensitive => name.to_lowercase().contains(&s.to_lowercase()),
+            NameMatcher::Substring(s) => name.contains(s.as_str()),
+            NameMatcher::Glob(p) if p.as_str().contains('/') => {
+                let options = glob::MatchOptions { require_literal_separator: true, ..options };
+                p.matches_with(&to_portable(relative), options)
+            }
+            NameMatcher::Glob(p) => p.matches_with(&name, options),
+            NameMatcher::Regex(re) => re.is_match(&name),
+            NameMatcher::Any(matchers) => matchers.iter().any(|m| m.matches(relative, case_insensitive)),
+            NameMatcher::All(matchers) => matchers.iter().all(|m| m.matches(relative, case_insensitive)),
+        }
+    }
+}
+
+/// Compiles a regular expression, reporting syntax errors as `InvalidInput`.
+pub fn build_regex(pattern: &str, case_insensitive: bool) -> io::Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid regex: {}", e)))
+}
+
+/// Parses a byte count with an optional binary `K`, `M` or `G` suffix, e.g. `10M`.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1u64 << 10),
+        'M' => (&s[..s.len() - 1], 1 << 20),
+        'G' => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Size, modification-time and ownership bounds applied to `find` results. Unset bounds accept everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AttrFilter {
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub newer: Option<SystemTime>,
+    pub older: Option<SystemTime>,
+    /// Owning uid; only Unix files have one.
+    pub owner: Option<u32>,
+    /// Owning gid; only Unix files have one.
+    pub group: Option<u32>,
+}
+
+impl AttrFilter {
+    pub fn is_empty(&self) -> bool {
+        self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.newer.is_none()
+            && self.older.is_none()
+            && self.owner.is_none()
+            && self.group.is_none()
+    }
+
+    /// Returns true if the owner and group ids satisfy the ownership bounds.
+    pub fn matches_ids(&self, uid: u32, gid: u32) -> bool {
+        self.owner.is_none_or(|o| o == uid) && self.group.is_none_or(|g| g == gid)
+    }
+
+    /// Returns true if the file's size, modification time and ownership fall within every set bound.
+    pub fn matches(&self, meta: &Metadata) -> bool {
+        if self.min_size.is_some_and(|min| meta.len() < min) || self.max_size.is_some_and(|max| meta.len() > max) {
+            return false;
+        }
+        if (self.owner.is_some() || self.group.is_some())
+            && !owners::owner_ids(meta).is_some_and(|(uid, gid)| self.matches_ids(uid, gid))
+        {
+            return false;
+        }
+        if self.newer.is_none() && self.older.is_none() {
+            return true;
+        }
+        match meta.modified() {
+            Ok(modified) => {
+                self.newer.is_none_or(|t| modified > t) && self.older.is_none_or(|t| modified < t)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Collects the files matching `matcher` and `filter` in the directory tree.
+/// Files whose metadata cannot be read are skipped.
+pub fn find_matches(
+    root: &Path,
+    matcher: &NameMatcher,
+    case_insensitive: bool,
+    filter: &AttrFilter,
+) -> io::Result<Vec<PathBuf>> {
+    let mut matches = Vec::new();
+    let mut stack = VecDeque::new();
+    stack.push_back(root.to_path_buf());
+    while let Some(current) = stack.pop_front() {
+        let entries = match fs::read_dir(&current) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push_back(path.clone());
+            } else if matcher.matches(path.strip_prefix(root).unwrap_or(&path), case_insensitive)
+                && (filter.is_empty() || fs::metadata(&path).is_ok_and(|m| filter.matches(&m)))
+            {
+                matches.push(path);
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Searches for files matching `matcher` and `filter` in the directory tree.
+pub fn search_files(root: &Path, matcher: &NameMatcher, case_insensitive: bool, filter: &AttrFilter) -> io::Result<()> {
+    for path in find_matches(root, matcher, case_insensitive, filter)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Collects files whose name (or whole path, with `full_path`) matches a regular expression.
+pub fn search_files_regex(root: &Path, re: &Regex, full_path: bool) -> io::Result<Vec<PathBuf>> {
+    let mut matches = Vec::new();
+    let mut stack = VecDeque::new();
+    stack.push_back(root.to_path_buf());
+    while let Some(current) = stack.pop_front() {
+        let entries = match fs::read_dir(&current) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push_back(path);
+                continue;
+            }
+            let haystack = if full_path {
+                path.to_string_lossy()
+            } else {
+                path.file_name().unwrap_or_default().to_string_lossy()
+            };
+            if re.is_match(&haystack) {
+                matches.push(path);
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Gets file metadata and prints details.
+pub fn stat_file(path: &Path, json: bool) -> io::Result<()> {
+    stat_file_to(&mut io::stdout(), path, json)
+}
+
+/// Like `stat_file`, writing the details to `out`.
+pub fn stat_file_to(out: &mut dyn Write, path: &Path, json: bool) -> io::Result<()> {
+    let info = StatInfo::new(path)?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&info)?)?;
+        return Ok(());
+    }
+    writeln!(out, "Path: {}", info.path.display())?;
+    writeln!(out, "Is directory: {}", info.is_dir)?;
+    writeln!(out, "Size: {}", info.size)?;
+    writeln!(out, "Read-only: {}", info.readonly)?;
+    for (label, time) in [("Modified", info.modified), ("Accessed", info.accessed), ("Created", info.created)] {
+        if let Some(time) = time {
+            writeln!(out, "{}: {:?}", label, time)?;
+        }
+    }
+    Ok(())
+}
+
+/// The details shown by `stat`.
+#[derive(Debug, Serialize)]
+pub struct StatInfo {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    pub readonly: bool,
+    #[serde(serialize_with = "serialize_unix_time")]
+    pub modified: Option<SystemTime>,
+    #[serde(serialize_with = "serialize_unix_time")]
+    pub accessed: Option<SystemTime>,
+    #[serde(serialize_with = "serialize_unix_time")]
+    pub created: Option<SystemTime>,
+}
+
+impl StatInfo {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(StatInfo {
+            path: path.to_path_buf(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            readonly: metadata.permissions().readonly(),
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+            created: metadata.created().ok(),
+        })
+    }
+}
+
+/// Reads a file line by line.
+pub fn read_lines(path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    for (i, line) in reader.lines().enumerate() {
+        println!("{:>4}: {}", i + 1, line?);
+    }
+    Ok(())
+}
+
+/// Prints the first `n` lines of a file, reading no further than needed.
+pub fn head_file(path: &Path, n: usize) -> io::Result<()> {
+    for line in first_lines(BufReader::new(File::open(path)?), n)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Reads up to `n` lines, stopping as soon as they have been read.
+fn first_lines<R: BufRead>(reader: R, n: usize) -> io::Result<Vec<String>> {
+    let mut lines = Vec::with_capacity(n.min(1024));
+    if n == 0 {
+        return Ok(lines);
+    }
+    for line in reader.lines() {
+        lines.push(line?);
+        if lines.len() == n {
+            break;
+        }
+    }
+    Ok(lines)
+}
+
+/// Prints the last `n` lines of a file, reading backwards from the end in chunks.
+pub fn tail_file(path: &Path, n: usize) -> io::Result<()> {
+    for line in last_lines(&mut File::open(path)?, n)? {
+        println!("{}", line);
     }
     Ok(())
 }
 
+/// Reads the last `n` lines by seeking backwards in 8 KiB chunks until `n` line breaks have
+/// been seen, so only the tail of the input is ever held in memory. Each chunk is scanned once
+/// as it is read and the chunks are joined only at the end. A missing final newline
+/// is fine; a trailing one does not start an extra empty line.
+fn last_lines<R: Read + Seek>(reader: &mut R, n: usize) -> io::Result<Vec<String>> {
+    const CHUNK: u64 = 8 * 1024;
+    let len = reader.seek(SeekFrom::End(0))?;
+    if n == 0 || len == 0 {
+        return Ok(Vec::new());
+    }
+    let mut chunks: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut newlines = 0;
+    let mut pos = len;
+    while pos > 0 && newlines < n {
+        let size = CHUNK.min(pos);
+        pos -= size;
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut chunk = vec![0u8; size as usize];
+        reader.read_exact(&mut chunk)?;
+        // The file's own final newline terminates the last line rather than separating two
+        let counted = if pos + size == len && chunk.ends_with(b"\n") { &chunk[..chunk.len() - 1] } else { &chunk[..] };
+        newlines += counted.iter().filter(|b| **b == b'\n').count();
+        chunks.push_front(chunk);
+    }
+    let tail: Vec<u8> = chunks.into_iter().flatten().collect();
+    let searchable = if tail.ends_with(b"\n") { &tail[..tail.len() - 1] } else { &tail[..] };
+    let start = searchable
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(n - 1)
+        .map(|(i, _)| i + 1);
+    let text = String::from_utf8_lossy(&tail[start.unwrap_or(0)..]).into_owned();
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// Counts (lines, words, bytes) in a file, streaming it in chunks. Words are separated by
+/// Unicode whitespace; the byte count is of the bytes actually read.
+pub fn count_file(path: &Path) -> io::Result<(usize, usize, usize)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (mut lines, mut words, mut bytes) = (0, 0, 0);
+    let mut in_word = false;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)?;
+        if n == 0 {
+            break;
+        }
+        bytes += n;
+        if line.ends_with(b"\n") {
+            lines += 1;
+        }
+        for c in String::from_utf8_lossy(&line).chars() {
+            if c.is_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                words += 1;
+            }
+        }
+    }
+    Ok((lines, words, bytes))
+}
+
+/// Files larger than this are skipped by `grep_tree`, which is meant for text, not disk images.
+pub const GREP_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Searches the contents of every file under `root` for `needle`, returning
+/// (path relative to `root`, 1-based line number, line) for each matching line.
+/// Files that aren't valid UTF-8 or are larger than `GREP_MAX_BYTES` are skipped.
+pub fn grep_tree(root: &Path, needle: &str) -> io::Result<Vec<(PathBuf, usize, String)>> {
+    let mut matches = Vec::new();
+    for (relative, metadata) in walk_files(root)? {
+        if metadata.len() > GREP_MAX_BYTES {
+            continue;
+        }
+        let Ok(file) = File::open(root.join(&relative)) else { continue };
+        let mut hits = Vec::new();
+        let mut decoded = true;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            match line {
+                Ok(line) if line.contains(needle) => hits.push((relative.clone(), i + 1, line)),
+                Ok(_) => {}
+                Err(_) => {
+                    decoded = false;
+                    break;
+                }
+            }
+        }
+        if decoded {
+            matches.extend(hits);
+        }
+    }
+    Ok(matches)
+}
+
 /// Writes text to a file, overwriting or appending.
 pub fn write_to_file(path: &Path, text: &str, append: bool) -> io::Result<()> {
     let mut file = OpenOptions::new()
@@ -215,176 +1612,2505 @@ pub fn write_to_file(path: &Path, text: &str, append: bool) -> io::Result<()> {
     Ok(())
 }
 
-/// Recursively calculates directory size.
-pub fn dir_size(path: &Path) -> io::Result<u64> {
-    let mut size = 0;
-    if path.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let p = entry.path();
-            if p.is_dir() {
-                size += dir_size(&p)?;
-            } else {
-                size += fs::metadata(&p)?.len();
-            }
+/// Appends `text` to a log file, then rotates it once it exceeds `max_bytes`: `path` becomes
+/// `path.1`, `path.1` becomes `path.2` and so on, keeping at most `keep` old generations.
+pub fn append_rotating(path: &Path, text: &str, max_bytes: u64, keep: usize) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_data()?;
+    if file.metadata()?.len() <= max_bytes {
+        return Ok(());
+    }
+    drop(file);
+
+    let generation = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    match fs::remove_file(generation(keep.max(1))) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    for n in (1..keep).rev() {
+        if generation(n).exists() {
+            fs::rename(generation(n), generation(n + 1))?;
         }
-    } else {
-        size = fs::metadata(path)?.len();
     }
-    Ok(size)
+    fs::rename(path, generation(1))
 }
 
-/// Prints the directory tree.
-pub fn print_tree(path: &Path, prefix: String) -> io::Result<()> {
-    if path.is_dir() {
-        println!("{}{}/", prefix, path.file_name().unwrap_or_default().to_string_lossy());
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let p = entry.path();
-            print_tree(&p, format!("{}  ", prefix))?;
+/// Recursively calculates directory size without following symlinks. On Unix, a file with
+/// several hard links inside the tree is counted once.
+pub fn dir_size(path: &Path, exclude: &[String]) -> io::Result<u64> {
+    dir_size_with(path, exclude, false)
+}
+
+/// Like `dir_size`; with `follow_symlinks`, links are measured by what they point to and
+/// linked directories are descended into, each physical directory at most once.
+pub fn dir_size_with(path: &Path, exclude: &[String], follow_symlinks: bool) -> io::Result<u64> {
+    dir_size_at(path, exclude, follow_symlinks, &mut HashSet::new())
+}
+
+/// Device and inode of a file, identifying it across hard links and symlinks.
+#[cfg(unix)]
+pub(crate) fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Whether a file with several links may already have been counted under another name.
+#[cfg(unix)]
+pub(crate) fn has_other_links(meta: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink() > 1
+}
+
+#[cfg(not(unix))]
+pub(crate) fn has_other_links(_meta: &Metadata) -> bool {
+    false
+}
+
+/// `seen` holds the ids of hard-linked files already counted and, when following symlinks,
+/// of directories already entered.
+fn dir_size_at(path: &Path, exclude: &[String], follow: bool, seen: &mut HashSet<(u64, u64)>) -> io::Result<u64> {
+    let meta = if follow { fs::metadata(path)? } else { fs::symlink_metadata(path)? };
+    let first_visit = |seen: &mut HashSet<(u64, u64)>| file_id(&meta).is_none_or(|id| seen.insert(id));
+    if !meta.is_dir() {
+        if has_other_links(&meta) && !first_visit(seen) {
+            return Ok(0);
         }
-    } else {
-        println!("{}{}", prefix, path.file_name().unwrap_or_default().to_string_lossy());
+        return Ok(meta.len());
     }
-    Ok(())
+    if follow && !first_visit(seen) {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let p = entry?.path();
+        if !is_excluded(&p, exclude) {
+            size += dir_size_at(&p, exclude, follow, seen)?;
+        }
+    }
+    Ok(size)
 }
 
-/// Interactive explorer loop.
-pub fn explorer_loop() -> io::Result<()> {
-    let mut current_dir = env::current_dir()?;
-    loop {
-        print!("RuForUs:{}> ", current_dir.display());
-        io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
-        if parts.is_empty() {
-            continue;
-        }
-        match parts[0] {
-            "ls" => {
-                let rec = parts.get(1) == Some(&"-r");
-                list_dir(&current_dir, rec)?;
-            }
-            "cd" => {
-                if let Some(dir) = parts.get(1) {
-                    let new_dir = current_dir.join(dir);
-                    if new_dir.is_dir() {
-                        current_dir = new_dir.canonicalize()?;
-                    } else {
-                        println!("Not a directory: {}", dir);
-                    }
-                }
-            }
-            "pwd" => {
-                println!("{}", current_dir.display());
-            }
-            "cp" => {
-                if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
-                    let src_path = current_dir.join(src);
-                    let dst_path = current_dir.join(dst);
-                    if src_path.is_dir() {
-                        copy_dir(&src_path, &dst_path)?;
-                    } else {
-                        copy_file(&src_path, &dst_path)?;
-                    }
-                }
-            }
-            "mv" => {
-                if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
-                    let src_path = current_dir.join(src);
-                    let dst_path = current_dir.join(dst);
-                    move_path(&src_path, &dst_path)?;
-                }
-            }
-            "rm" => {
-                if let Some(target) = parts.get(1) {
-                    let target_path = current_dir.join(target);
-                    delete_path(&target_path)?;
-                }
-            }
-            "cat" => {
-                if let Some(f) = parts.get(1) {
-                    cat_file(&current_dir.join(f))?;
-                }
-            }
-            "touch" => {
-                if let Some(f) = parts.get(1) {
-                    touch_file(&current_dir.join(f))?;
-                }
-            }
-            "rename" => {
-                if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
-                    rename_path(&current_dir.join(src), &current_dir.join(dst))?;
-                }
-            }
-            "find" => {
-                if let Some(pat) = parts.get(1) {
-                    search_files(&current_dir, pat)?;
-                }
-            }
-            "stat" => {
-                if let Some(f) = parts.get(1) {
-                    stat_file(&current_dir.join(f))?;
-                }
-            }
-            "lines" => {
-                if let Some(f) = parts.get(1) {
-                    read_lines(&current_dir.join(f))?;
-                }
-            }
-            "write" => {
-                if let (Some(f), Some(txt)) = (parts.get(1), parts.get(2)) {
-                    write_to_file(&current_dir.join(f), txt, false)?;
-                }
-            }
-            "append" => {
-                if let (Some(f), Some(txt)) = (parts.get(1), parts.get(2)) {
-                    write_to_file(&current_dir.join(f), txt, true)?;
-                }
-            }
-            "du" => {
-                let size = dir_size(&current_dir)?;
-                println!("Total size: {} bytes", size);
-            }
-            "tree" => {
-                print_tree(&current_dir, "".to_string())?;
-            }
-            "exit" | "quit" => {
-                break;
+/// Trees with at least this many entries are sized in parallel by `du`, unless it is asked to
+/// exclude paths or follow symlinks.
+pub const PARALLEL_DU_MIN_ENTRIES: usize = 10_000;
+
+/// Returns true if the tree under `path` holds at least `limit` entries, stopping as soon as
+/// it has seen that many. Unreadable directories are skipped.
+fn has_many_entries(path: &Path, limit: usize) -> bool {
+    let mut count = 0;
+    let mut queue = VecDeque::from([path.to_path_buf()]);
+    while let Some(dir) = queue.pop_front() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            count += 1;
+            if count >= limit {
+                return true;
             }
-            _ => {
-                println!("Unknown command. Commands: ls, cd, pwd, cp, mv, rm, cat, touch, rename, find, stat, lines, write, append, du, tree, exit");
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                queue.push_back(entry.path());
             }
         }
     }
-    Ok(())
+    false
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_file_item_display() {
-        let temp_dir = tempfile::tempdir().unwrap();
-        let file_path = temp_dir.path().join("test.txt");
-        File::create(&file_path).unwrap();
-        let entry = fs::read_dir(temp_dir.path()).unwrap().next().unwrap().unwrap();
-        let file_item = FileItem::new(&entry).unwrap();
-        file_item.display();
+/// Computes the same total as `dir_size` (without exclusions), sizing subdirectories on the
+/// rayon thread pool. Entries that can't be read don't stop the walk; they are collected
+/// and reported together in the returned error.
+pub fn dir_size_parallel(path: &Path) -> io::Result<u64> {
+    let seen = Mutex::new(HashSet::new());
+    let errors = Mutex::new(Vec::new());
+    let size = dir_size_parallel_at(path, &seen, &errors);
+    let errors = errors.into_inner().unwrap();
+    match errors.first() {
+        None => Ok(size),
+        Some((first, e)) => Err(io::Error::new(
+            e.kind(),
+            format!("{} entries could not be read (first: {}: {})", errors.len(), first.display(), e),
+        )),
     }
+}
 
-    #[test]
-    fn test_copy_and_delete_file() {
-        let temp_dir = tempfile::tempdir().unwrap();
-        let src = temp_dir.path().join("a.txt");
-        let dst = temp_dir.path().join("b.txt");
-        fs::write(&src, b"hello").unwrap();
-        copy_file(&src, &dst).unwrap();
-        assert_eq!(fs::read(&dst).unwrap(), b"hello");
-        delete_path(&dst).unwrap();
-        assert!(!dst.exists());
+fn dir_size_parallel_at(
+    path: &Path,
+    seen: &Mutex<HashSet<(u64, u64)>>,
+    errors: &Mutex<Vec<(PathBuf, io::Error)>>,
+) -> u64 {
+    let fail = |p: &Path, e: io::Error| {
+        errors.lock().unwrap().push((p.to_path_buf(), e));
+        0
+    };
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) => return fail(path, e),
+    };
+    if !meta.is_dir() {
+        if has_other_links(&meta) && file_id(&meta).is_some_and(|id| !seen.lock().unwrap().insert(id)) {
+            return 0;
+        }
+        return meta.len();
+    }
+    let entries: Vec<PathBuf> = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.map(|e| e.path()).map_err(|e| fail(path, e)).ok())
+            .collect(),
+        Err(e) => return fail(path, e),
+    };
+    entries.par_iter().map(|p| dir_size_parallel_at(p, seen, errors)).sum()
+}
+
+/// A directory and its recursive size, with its subdirectories as children.
+#[derive(Debug)]
+pub struct SizeNode {
+    pub path: PathBuf,
+    pub size: u64,
+    pub children: Vec<SizeNode>,
+}
+
+/// Builds a size tree bottom-up, so each directory's size is summed from its children once.
+pub fn size_tree(path: &Path) -> io::Result<SizeNode> {
+    let mut size = 0;
+    let mut children = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let p = entry.path();
+        if p.is_dir() {
+            let child = size_tree(&p)?;
+            size += child.size;
+            children.push(child);
+        } else {
+            size += fs::metadata(&p)?.len();
+        }
+    }
+    children.sort_by_key(|c| std::cmp::Reverse(c.size));
+    Ok(SizeNode { path: path.to_path_buf(), size, children })
+}
+
+/// Scales each size to its share of `width` columns relative to `total`.
+pub fn proportional_widths(sizes: &[u64], total: u64, width: usize) -> Vec<usize> {
+    sizes
+        .iter()
+        .map(|&s| {
+            if total == 0 {
+                0
+            } else {
+                ((s as f64 / total as f64) * width as f64).round() as usize
+            }
+        })
+        .collect()
+}
+
+/// Prints nested proportional bars where each directory's bar is its share of its parent's bar.
+pub fn print_treemap(node: &SizeNode, width: usize, max_depth: usize) {
+    fn render(node: &SizeNode, width: usize, depth: usize, max_depth: usize) {
+        if depth >= max_depth {
+            return;
+        }
+        let sizes: Vec<u64> = node.children.iter().map(|c| c.size).collect();
+        let widths = proportional_widths(&sizes, node.size, width);
+        for (child, w) in node.children.iter().zip(widths) {
+            let pct = if node.size == 0 { 0.0 } else { child.size as f64 * 100.0 / node.size as f64 };
+            println!(
+                "{}[{}{}] {} ({} bytes, {:.1}%)",
+                "  ".repeat(depth + 1),
+                "#".repeat(w),
+                " ".repeat(width - w),
+                child.path.file_name().unwrap_or_default().to_string_lossy(),
+                child.size,
+                pct
+            );
+            render(child, w, depth + 1, max_depth);
+        }
+    }
+    println!("[{}] {} ({} bytes)", "#".repeat(width), node.path.display(), node.size);
+    render(node, width, 0, max_depth);
+}
+
+/// Prints the directory tree. With `max_depth`, only directories up to that depth below
+/// `path` have their contents shown; depth 0 shows only the top directory's entries.
+/// Entries matching an `exclude` glob are not shown or descended into. A directory that is
+/// already being listed further up (e.g. through a symlink pointing back up the tree) is
+/// marked `[cycle]` instead of being entered again.
+pub fn print_tree(
+    path: &Path,
+    prefix: String,
+    max_depth: Option<usize>,
+    exclude: &[String],
+    color: bool,
+) -> io::Result<()> {
+    print_tree_to(&mut io::stdout(), path, prefix, max_depth, exclude, color)
+}
+
+/// Like `print_tree`, writing the tree to `out`.
+pub fn print_tree_to(
+    out: &mut dyn Write,
+    path: &Path,
+    prefix: String,
+    max_depth: Option<usize>,
+    exclude: &[String],
+    color: bool,
+) -> io::Result<()> {
+    let mut walk = TreeWalk { out, max_depth, exclude, color, ancestors: HashSet::new() };
+    walk.print(path, prefix, 0)
+}
+
+/// The settings and state of one `print_tree_to` run.
+struct TreeWalk<'a> {
+    out: &'a mut dyn Write,
+    max_depth: Option<usize>,
+    exclude: &'a [String],
+    color: bool,
+    /// The canonical paths of the directories currently being listed.
+    ancestors: HashSet<PathBuf>,
+}
+
+impl TreeWalk<'_> {
+    fn print(&mut self, path: &Path, prefix: String, depth: usize) -> io::Result<()> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            let canonical = path.canonicalize()?;
+            let label = color::paint(path, &format!("{}/", name), self.color);
+            if self.ancestors.contains(&canonical) {
+                writeln!(self.out, "{}{} [cycle]", prefix, label)?;
+                return Ok(());
+            }
+            writeln!(self.out, "{}{}", prefix, label)?;
+            if self.max_depth.is_some_and(|max| depth > max) {
+                return Ok(());
+            }
+            self.ancestors.insert(canonical.clone());
+            for entry in fs::read_dir(path)? {
+                let p = entry?.path();
+                if !is_excluded(&p, self.exclude) {
+                    self.print(&p, format!("{}  ", prefix), depth + 1)?;
+                }
+            }
+            self.ancestors.remove(&canonical);
+        } else {
+            writeln!(self.out, "{}{}", prefix, color::paint(path, &name, self.color))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the argument following `flag` in a command line, if present.
+fn flag_value<'a>(parts: &[&'a str], flag: &str) -> Option<&'a str> {
+    let i = parts.iter().position(|p| *p == flag)?;
+    parts.get(i + 1).copied()
+}
+
+/// A usage problem with an explorer command. It is returned rather than printed so a script
+/// stops at the offending line instead of running later commands in the wrong state.
+fn usage(text: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Usage: {}", text))
+}
+
+fn not_a_directory(dir: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Not a directory: {}", dir))
+}
+
+/// Returns every argument following an occurrence of `flag`, for repeatable options.
+fn flag_values(parts: &[&str], flag: &str) -> Vec<String> {
+    parts
+        .windows(2)
+        .filter(|w| w[0] == flag)
+        .map(|w| w[1].to_string())
+        .collect()
+}
+
+/// Removes `flag` and its value from `args`, returning the value.
+fn take_flag<'a>(args: &mut Vec<&'a str>, flag: &str) -> Option<&'a str> {
+    let i = args.iter().position(|p| *p == flag)?;
+    let value = args.get(i + 1).copied();
+    args.drain(i..(i + 2).min(args.len()));
+    value
+}
+
+/// Expands arguments containing `*`, `?` or `[` against `cwd` the way a shell would: matches come
+/// back sorted and relative to `cwd`, hidden files only match a pattern that starts with a dot, and a
+/// pattern that matches nothing (or isn't a valid glob) is passed through unchanged. Flags are left alone.
+fn expand_wildcards(cwd: &Path, args: &[&str]) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        if arg.starts_with('-') || !arg.contains(['*', '?', '[']) {
+            expanded.push(arg.to_string());
+            continue;
+        }
+        // The glob crate's leading-dot option also rejects patterns that spell the dot out, so it
+        // is only turned on for patterns that don't.
+        let explicit_dot = arg.rsplit(['/', '\\']).next().is_some_and(|name| name.starts_with('.'));
+        let options = glob::MatchOptions { require_literal_leading_dot: !explicit_dot, ..Default::default() };
+        let pattern = if Path::new(arg).is_absolute() {
+            arg.to_string()
+        } else {
+            format!("{}/{}", glob::Pattern::escape(&cwd.to_string_lossy()), arg)
+        };
+        let matches: Vec<String> = match glob::glob_with(&pattern, options) {
+            Ok(paths) => paths
+                .flatten()
+                .map(|p| p.strip_prefix(cwd).map(Path::to_path_buf).unwrap_or(p).display().to_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if matches.is_empty() {
+            expanded.push(arg.to_string());
+        } else {
+            expanded.extend(matches);
+        }
+    }
+    expanded
+}
+
+/// Builds the matcher for `find`'s arguments, or `None` if they need the usage message. A single
+/// pattern is read by `NameMatcher::parse`, as each `--any`/`--all` pattern is, so a plain word
+/// is always a substring; `--substr` forces a substring even for a pattern with wildcards. With
+/// no pattern, `has_filter` matches everything for the attribute filter to narrow down.
+fn find_matcher(args: &[&str], case_insensitive: bool, has_filter: bool) -> Option<io::Result<NameMatcher>> {
+    match (args.first(), args.get(1)) {
+        (Some(&"--any"), Some(_)) => Some(NameMatcher::parse_list(&args[1..], case_insensitive).map(NameMatcher::Any)),
+        (Some(&"--all"), Some(_)) => Some(NameMatcher::parse_list(&args[1..], case_insensitive).map(NameMatcher::All)),
+        (Some(&"--substr"), Some(pat)) => Some(Ok(NameMatcher::Substring(pat.to_string()))),
+        (Some(pat), _) if *pat != "--substr" => Some(NameMatcher::parse(pat)),
+        (None, _) if has_filter => Some(NameMatcher::glob("*")),
+        _ => None,
+    }
+}
+
+/// Pulls `--min-size`, `--max-size`, `--newer`, `--older`, `--owner` and `--group` out of `find` arguments.
+fn take_attr_filter(args: &mut Vec<&str>) -> Result<AttrFilter, String> {
+    let mut filter = AttrFilter::default();
+    for (flag, size) in [("--min-size", &mut filter.min_size), ("--max-size", &mut filter.max_size)] {
+        if let Some(v) = take_flag(args, flag) {
+            *size = Some(parse_size(v).ok_or_else(|| format!("Invalid size for {}: {}", flag, v))?);
+        }
+    }
+    for (flag, time) in [("--newer", &mut filter.newer), ("--older", &mut filter.older)] {
+        if let Some(v) = take_flag(args, flag) {
+            *time = Some(parse_date(v).ok_or_else(|| format!("Invalid date for {} (expected YYYY-MM-DD): {}", flag, v))?);
+        }
+    }
+    if let Some(v) = take_flag(args, "--owner") {
+        filter.owner = Some(owners::resolve_user(v).ok_or_else(|| format!("Unknown user: {}", v))?);
+    }
+    if let Some(v) = take_flag(args, "--group") {
+        filter.group = Some(owners::resolve_group(v).ok_or_else(|| format!("Unknown group: {}", v))?);
+    }
+    Ok(filter)
+}
+
+/// Explorer command names, as listed for an unknown command and offered by tab completion.
+const COMMANDS: &[&str] = &[
+    "ls", "cd", "pwd", "pushd", "popd", "dirs", "cp", "cp-template", "copy-verify-attrs",
+    "effective-options", "mv", "swap", "rm", "cat", "filter", "bom", "mkdir", "touch", "rename",
+    "find", "grep", "pick", "stat", "lines", "head", "tail", "wc", "write", "append", "log", "du",
+    "count", "breakdown", "biggest", "stats", "tree", "diff", "cmp", "hash", "verify", "resume-cp",
+    "salvage-cp", "sync", "mirror", "queue", "dupes", "dupe-dirs", "portablize", "resolve",
+    "space-needed", "tag", "untag", "tagged", "treemap", "manifest", "perms", "timeline",
+    "heatmap", "freshness", "entropy", "tar", "bookmark", "go", "alias", "history", "yank",
+    "paste", "session", "exit",
+];
+
+/// Explorer commands that exist only when built with the `net` feature.
+const NET_COMMANDS: &[&str] = if cfg!(feature = "net") { &["download"] } else { &[] };
+
+/// Every explorer command name available in this build.
+fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().chain(NET_COMMANDS).copied()
+}
+
+/// Completes explorer input: command names for the first word, and file and directory names
+/// relative to `cwd` for every later word. Directories complete with a trailing slash.
+struct ExplorerHelper {
+    cwd: PathBuf,
+}
+
+/// Entries matching the partial path `token`, resolved against `cwd`. Each candidate keeps the
+/// directory part typed so far; hidden entries are only offered once the name starts with a dot.
+fn complete_path(cwd: &Path, token: &str) -> Vec<Pair> {
+    let (dir_part, prefix) = match token.rfind(['/', '\\']) {
+        Some(i) => token.split_at(i + 1),
+        None => ("", token),
+    };
+    let dir = if dir_part.is_empty() { cwd.to_path_buf() } else { cwd.join(dir_part) };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<Pair> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let display = if entry.path().is_dir() { format!("{}/", name) } else { name };
+            Some(Pair { replacement: format!("{}{}", dir_part, display), display })
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.display.cmp(&b.display));
+    candidates
+}
+
+impl Completer for ExplorerHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let token = &line[start..pos];
+        if line[..start].trim().is_empty() {
+            let commands = command_names()
+                .filter(|c| c.starts_with(token))
+                .map(|c| Pair { display: c.to_string(), replacement: c.to_string() })
+                .collect();
+            return Ok((start, commands));
+        }
+        Ok((start, complete_path(&self.cwd, token)))
+    }
+}
+
+impl Hinter for ExplorerHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ExplorerHelper {}
+
+impl Validator for ExplorerHelper {}
+
+impl Helper for ExplorerHelper {}
+
+/// State the explorer keeps between commands.
+#[derive(Debug)]
+pub struct ExplorerState {
+    pub current_dir: PathBuf,
+    /// Where `cd -` goes back to.
+    pub previous_dir: Option<PathBuf>,
+    /// Directories saved by `pushd`, most recent last.
+    pub dir_stack: Vec<PathBuf>,
+    pub queue: TransferQueue,
+    pub tags: TagIndex,
+    /// The path chosen by the last `pick`, substituted for `@`.
+    pub picked: Option<String>,
+    pub session: Session,
+    /// Set by `exit`; the interactive loop stops once it is.
+    pub exit_requested: bool,
+}
+
+impl ExplorerState {
+    /// Fresh state rooted at `dir`, loading the tag index found there.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        Ok(ExplorerState {
+            current_dir: dir.to_path_buf(),
+            previous_dir: None,
+            dir_stack: Vec::new(),
+            queue: TransferQueue::new(),
+            tags: TagIndex::load(dir)?,
+            picked: None,
+            session: Session::new(dir),
+            exit_requested: false,
+        })
+    }
+
+    /// Splits an input line into command words: aliases are expanded, `@` stands for the path
+    /// chosen by the last `pick`, and other words get `~` and variables expanded.
+    pub fn command_words(&self, input: &str) -> Vec<String> {
+        self.session
+            .expand_alias(input)
+            .split_whitespace()
+            .map(|p| match (p, &self.picked) {
+                ("@", Some(picked)) => picked.clone(),
+                ("@", None) => p.to_string(),
+                _ => expand_path(p),
+            })
+            .collect()
+    }
+}
+
+/// Runs one explorer command, `parts[0]` being the command name. Failures of the underlying
+/// operation are returned, and so are usage problems (missing arguments, unknown commands or
+/// options, a `cd` to a non-directory) as `InvalidInput` or `NotFound` errors.
+pub fn run_command(state: &mut ExplorerState, parts: &[&str]) -> io::Result<()> {
+    let ExplorerState { current_dir, previous_dir, dir_stack, queue, tags, picked, session, exit_requested } = state;
+    if parts.is_empty() {
+        return Ok(());
+    }
+    match parts[0] {
+        "ls" => {
+            let rec = parts.contains(&"-r");
+            let max_depth = flag_value(parts, "-r").and_then(|d| d.parse().ok());
+            let key = match flag_value(parts, "--sort").map(|k| (k, SortKey::parse(k))) {
+                Some((_, Some(key))) => key,
+                Some((k, None)) => {
+                    let message = format!("Unknown sort key '{}'; use name, size or modified", k);
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+                }
+                None => SortKey::Name,
+            };
+            let opts = ListOptions {
+                recursive: rec,
+                max_depth,
+                exclude: flag_values(parts, "--exclude"),
+                sort: SortOrder {
+                    key,
+                    descending: parts.contains(&"--desc"),
+                    dirs_first: parts.contains(&"--dirs-first"),
+                },
+                raw: parts.contains(&"--raw"),
+                color: ColorMode::from_args(parts).enabled(),
+                json: parts.contains(&"--json"),
+                show_hidden: parts.contains(&"-a"),
+                by_owner: parts.contains(&"--by-owner"),
+            };
+            list_dir(current_dir, &opts)?;
+        }
+        "cd" => {
+            // `cd -` returns to the previous directory and bare `cd` goes home.
+            let target = match parts.get(1) {
+                Some(&"-") => match &previous_dir {
+                    Some(dir) => dir.clone(),
+                    None => return Err(io::Error::new(io::ErrorKind::NotFound, "No previous directory")),
+                },
+                Some(dir) => current_dir.join(dir),
+                None => match home_dir() {
+                    Some(home) => home,
+                    None => return Err(io::Error::new(io::ErrorKind::NotFound, "No home directory set")),
+                },
+            };
+            if target.is_dir() {
+                let new_dir = target.canonicalize()?;
+                *previous_dir = Some(std::mem::replace(current_dir, new_dir));
+                if parts.get(1) == Some(&"-") {
+                    println!("{}", current_dir.display());
+                }
+            } else {
+                return Err(not_a_directory(parts.get(1).copied().unwrap_or("~")));
+            }
+        }
+        "pwd" => {
+            println!("{}", current_dir.display());
+        }
+        "pushd" => {
+            let Some(dir) = parts.get(1) else {
+                return Err(usage("pushd <dir>"));
+            };
+            let target = current_dir.join(dir);
+            if target.is_dir() {
+                let new_dir = target.canonicalize()?;
+                let old_dir = std::mem::replace(current_dir, new_dir);
+                *previous_dir = Some(old_dir.clone());
+                dir_stack.push(old_dir);
+                println!("{}", current_dir.display());
+            } else {
+                return Err(not_a_directory(dir));
+            }
+        }
+        "popd" => match dir_stack.pop() {
+            Some(dir) if dir.is_dir() => {
+                *previous_dir = Some(std::mem::replace(current_dir, dir));
+                println!("{}", current_dir.display());
+            }
+            Some(dir) => {
+                let message = format!("{} no longer exists; dropped it from the stack", dir.display());
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Directory stack is empty; use pushd <dir> first"));
+            }
+        },
+        "dirs" => {
+            println!("{}", current_dir.display());
+            for dir in dir_stack.iter().rev() {
+                println!("{}", dir.display());
+            }
+        }
+        "cp" => {
+            let args = expand_wildcards(current_dir, &parts[1..]);
+            let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+            let Some((dst, srcs)) = paths.split_last().filter(|(_, srcs)| !srcs.is_empty()) else {
+                return Err(usage("cp [-f|-n|-i] [--hash] [--symlinks=skip|recreate|follow] <src>... <dst>"));
+            };
+            let dst_path = current_dir.join(dst);
+            if srcs.len() > 1 && !dst_path.is_dir() {
+                let message = format!("copying {} sources needs a directory target, {} is not one", srcs.len(), dst);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            let config = PartialOptions::load(current_dir)?;
+            let mut cli = PartialOptions::from_args(parts);
+            if cli.symlinks.is_none() && parts.iter().any(|p| p.starts_with("--symlinks=")) {
+                return Err(usage("cp --symlinks=skip|recreate|follow ..."));
+            }
+            // Interactive copies ask before replacing files unless told otherwise.
+            if cli.overwrite.is_none() && config.overwrite.is_none() {
+                cli.overwrite = Some(OverwritePolicy::Prompt);
+            }
+            let opts = Options::resolve(&config, &cli);
+            for src in srcs {
+                let src_path = current_dir.join(src);
+                let dst_path = if srcs.len() > 1 {
+                    dst_path.join(src_path.file_name().unwrap_or_default())
+                } else {
+                    dst_path.clone()
+                };
+                if !src_path.is_dir() && parts.contains(&"--hash") {
+                    if may_overwrite(&dst_path, opts.overwrite, &mut confirm_overwrite) {
+                        copy_file_hashed(&src_path, &dst_path, opts.verify)?;
+                    } else {
+                        println!("Skipped existing {}", dst_path.display());
+                    }
+                } else {
+                    copy_with_options(&src_path, &dst_path, &opts)?;
+                }
+            }
+        }
+        "copy-verify-attrs" => {
+            let attrs = Attr::from_args(parts);
+            match (parts.get(1), parts.get(2)) {
+                (Some(src), Some(dst)) if !attrs.is_empty() => {
+                    let checks = copy_verify_attrs(&current_dir.join(src), &current_dir.join(dst), &attrs)?;
+                    for check in &checks {
+                        if check.preserved {
+                            println!("{:<6} preserved", check.attr);
+                        } else {
+                            println!("{:<6} NOT preserved: {}", check.attr, check.detail);
+                        }
+                    }
+                }
+                _ => return Err(usage("copy-verify-attrs <src> <dst> [--times] [--mode] [--xattr] [--owner]")),
+            }
+        }
+        "cp-template" => {
+            if let (Some(src), Some(template), Some(dest)) = (parts.get(1), parts.get(2), parts.get(3)) {
+                let template = template.trim_matches(|c| c == '\'' || c == '"');
+                let copied =
+                    copy_with_template(&current_dir.join(src), template, &current_dir.join(dest), SystemTime::now())?;
+                println!("Copied to {}", copied.display());
+            } else {
+                return Err(usage(
+                    "cp-template <src> '<template>' <destdir>\n  placeholders: {name} {ext} {size} {date} {mdate} {hash8}",
+                ));
+            }
+        }
+        "effective-options" => {
+            options::print_effective(&PartialOptions::load(current_dir)?, &PartialOptions::from_args(parts));
+        }
+        "mv" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let src_path = current_dir.join(src);
+                let dst_path = current_dir.join(dst);
+                move_tagged(&src_path, &dst_path, tags)?;
+            } else {
+                return Err(usage("mv <src> <dst>"));
+            }
+        }
+        "rm" => {
+            let force = parts.contains(&"-f");
+            let targets: Vec<String> =
+                expand_wildcards(current_dir, &parts[1..]).into_iter().filter(|p| p != "-f").collect();
+            if targets.is_empty() {
+                return Err(usage("rm [-f] <path>..."));
+            }
+            for target in &targets {
+                let target_path = current_dir.join(target);
+                if force {
+                    delete_path(&target_path)?;
+                } else {
+                    println!("Moved to {}", trash_path(&target_path)?.display());
+                }
+            }
+        }
+        "swap" => {
+            if let (Some(a), Some(b)) = (parts.get(1), parts.get(2)) {
+                swap_paths(&current_dir.join(a), &current_dir.join(b))?;
+            } else {
+                return Err(usage("swap <a> <b>"));
+            }
+        }
+        "cat" => {
+            for f in expand_wildcards(current_dir, &parts[1..]) {
+                cat_file(&current_dir.join(f))?;
+            }
+        }
+        "filter" => {
+            match parts.iter().position(|p| *p == "--") {
+                Some(sep) if sep > 1 && sep + 1 < parts.len() => {
+                    let dest = flag_value(&parts[..sep], "-o").map(|d| current_dir.join(d));
+                    let src = current_dir.join(parts[1]);
+                    let bytes = filter_file(&src, parts[sep + 1], &parts[sep + 2..], dest.as_deref())?;
+                    println!("Wrote {} bytes", bytes);
+                }
+                _ => return Err(usage("filter <file> [-o <dest>] -- <command> [args...]")),
+            }
+        }
+        "bom" => {
+            if let Some(f) = parts.get(1) {
+                let path = current_dir.join(f);
+                if parts.contains(&"--strip") {
+                    match strip_bom(&path)? {
+                        Some(b) => println!("Removed {} BOM from {}", b.name(), f),
+                        None => println!("{} has no BOM", f),
+                    }
+                } else if let Some(enc) = flag_value(parts, "--add") {
+                    if !enc.eq_ignore_ascii_case("utf-8") && !enc.eq_ignore_ascii_case("utf8") {
+                        println!("Only a UTF-8 BOM can be added");
+                    } else if add_utf8_bom(&path)? {
+                        println!("Added UTF-8 BOM to {}", f);
+                    } else {
+                        println!("{} already has a BOM", f);
+                    }
+                } else {
+                    match detect_bom(&path)? {
+                        Some(b) => println!("{}: {} BOM", f, b.name()),
+                        None => println!("{}: no BOM", f),
+                    }
+                }
+            } else {
+                return Err(usage("bom <file> [--strip | --add utf-8]"));
+            }
+        }
+        "mkdir" => {
+            let parents = parts.contains(&"-p");
+            match parts.iter().skip(1).find(|p| **p != "-p") {
+                Some(dir) => make_dir(&current_dir.join(dir), parents)?,
+                None => return Err(usage("mkdir [-p] <dir>")),
+            }
+        }
+        "touch" => {
+            if let Some(f) = parts.get(1) {
+                touch_file(&current_dir.join(f))?;
+            } else {
+                return Err(usage("touch <file>"));
+            }
+        }
+        "rename" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let (src_path, dst_path) = (current_dir.join(src), current_dir.join(dst));
+                rename_path(&src_path, &dst_path)?;
+                if tags.rename_path(&src_path, &dst_path) {
+                    tags.save()?;
+                }
+            } else {
+                return Err(usage("rename <src> <dst>"));
+            }
+        }
+        "find" => {
+            let case_insensitive = parts.contains(&"-i");
+            let mut args: Vec<&str> = parts[1..].iter().copied().filter(|p| *p != "-i").collect();
+            let filter = take_attr_filter(&mut args).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            if args.first() == Some(&"-e") {
+                match args.get(1).map(|p| build_regex(p, case_insensitive)) {
+                    Some(Ok(re)) => {
+                        let matches = search_files_regex(current_dir, &re, args.contains(&"--full-path"))?;
+                        for path in &matches {
+                            println!("{}", path.display());
+                        }
+                        println!("{} match(es)", matches.len());
+                    }
+                    Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+                    None => return Err(usage("find [-i] -e <regex> [--full-path]")),
+                }
+                return Ok(());
+            }
+            let Some(matcher) = find_matcher(&args, case_insensitive, !filter.is_empty()) else {
+                return Err(usage(
+                    "find [-i] [--substr] <pattern> | find [-i] <--any|--all> <pattern>...\n\
+                     \x20      [--min-size N[K|M|G]] [--max-size N[K|M|G]] [--newer YYYY-MM-DD] [--older YYYY-MM-DD]\n\
+                     \x20      [--owner USER|UID] [--group GROUP|GID]",
+                ));
+            };
+            let matcher = matcher.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            search_files(current_dir, &matcher, case_insensitive, &filter)?;
+        }
+        "pick" => {
+            let matcher = match parts.get(1) {
+                Some(pat) => NameMatcher::parse(pat),
+                None => NameMatcher::glob("*"),
+            };
+            let matcher = matcher.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let found = find_matches(current_dir, &matcher, false, &AttrFilter::default())?;
+            let candidates: Vec<String> = found
+                .iter()
+                .map(|p| to_portable(p.strip_prefix(&current_dir).unwrap_or(p)))
+                .collect();
+            let refs: Vec<&str> = candidates.iter().map(String::as_str).collect();
+            match fuzzy::pick(&refs, &mut io::stdin().lock())? {
+                Some(choice) => {
+                    let path = current_dir.join(&choice);
+                    println!("Picked {} (use @ to refer to it)", path.display());
+                    *picked = Some(path.to_string_lossy().into_owned());
+                }
+                None => println!("Nothing picked."),
+            }
+        }
+        "stat" => {
+            if let Some(f) = parts.get(1) {
+                stat_file(&current_dir.join(f), parts.contains(&"--json"))?;
+            } else {
+                return Err(usage("stat <path> [--json]"));
+            }
+        }
+        "lines" => {
+            if let Some(f) = parts.get(1) {
+                read_lines(&current_dir.join(f))?;
+            } else {
+                return Err(usage("lines <file>"));
+            }
+        }
+        "head" => {
+            if let Some(f) = parts.get(1) {
+                let n = parts.get(2).and_then(|n| n.parse().ok()).unwrap_or(10);
+                head_file(&current_dir.join(f), n)?;
+            } else {
+                return Err(usage("head <file> [n]"));
+            }
+        }
+        "tail" => {
+            if let Some(f) = parts.get(1) {
+                let n = parts.get(2).and_then(|n| n.parse().ok()).unwrap_or(10);
+                tail_file(&current_dir.join(f), n)?;
+            } else {
+                return Err(usage("tail <file> [n]"));
+            }
+        }
+        "grep" => {
+            if parts.len() > 1 {
+                let needle = parts[1..].join(" ");
+                let matches = grep_tree(current_dir, needle.trim_matches('"'))?;
+                for (path, line_no, line) in &matches {
+                    println!("{}:{}: {}", path.display(), line_no, line);
+                }
+                println!("{} match(es)", matches.len());
+            } else {
+                return Err(usage("grep <text>"));
+            }
+        }
+        "wc" => {
+            if let Some(f) = parts.get(1) {
+                let (lines, words, bytes) = count_file(&current_dir.join(f))?;
+                println!("{:>8} {:>8} {:>8} {}", lines, words, bytes, f);
+            } else {
+                return Err(usage("wc <file>"));
+            }
+        }
+        "write" => {
+            if let (Some(f), Some(txt)) = (parts.get(1), parts.get(2)) {
+                write_to_file(&current_dir.join(f), txt, false)?;
+            } else {
+                return Err(usage("write <file> <text>"));
+            }
+        }
+        "append" => {
+            if let (Some(f), Some(txt)) = (parts.get(1), parts.get(2)) {
+                write_to_file(&current_dir.join(f), txt, true)?;
+            } else {
+                return Err(usage("append <file> <text>"));
+            }
+        }
+        "log" => {
+            if let (Some(f), Some(_)) = (parts.get(1), parts.get(2)) {
+                let max_bytes = flag_value(parts, "--max-bytes").and_then(parse_size).unwrap_or(1 << 20);
+                let keep = flag_value(parts, "--keep").and_then(|k| k.parse().ok()).unwrap_or(5);
+                let mut words = Vec::new();
+                let mut args = parts[2..].iter();
+                while let Some(word) = args.next() {
+                    if *word == "--max-bytes" || *word == "--keep" {
+                        args.next();
+                    } else {
+                        words.push(*word);
+                    }
+                }
+                append_rotating(&current_dir.join(f), &format!("{}\n", words.join(" ")), max_bytes, keep)?;
+            } else {
+                return Err(usage("log <file> <text...> [--max-bytes N[K|M|G]] [--keep N]"));
+            }
+        }
+        "du" => {
+            let exclude = flag_values(parts, "--exclude");
+            let follow = parts.contains(&"--follow");
+            // The parallel walk neither excludes nor follows links, so those requests take the plain one.
+            let total = if exclude.is_empty() && !follow && has_many_entries(current_dir, PARALLEL_DU_MIN_ENTRIES) {
+                dir_size_parallel(current_dir)?
+            } else {
+                dir_size_with(current_dir, &exclude, follow)?
+            };
+            println!("Total size: {} bytes ({})", total, human_size(total));
+        }
+        "count" | "breakdown" | "biggest" | "stats" => {
+            let opts = ScanOptions {
+                exclude: flag_values(parts, "--exclude"),
+                largest: parts.get(1).and_then(|n| n.parse().ok()).unwrap_or(10),
+            };
+            let stats = stats::scan(current_dir, &opts)?;
+            if parts[0] == "stats" {
+                println!("Total size: {} bytes", stats.total_size);
+            }
+            if matches!(parts[0], "count" | "stats") {
+                println!("{} file(s), {} dir(s), max depth {}", stats.files, stats.dirs, stats.max_depth);
+            }
+            if matches!(parts[0], "breakdown" | "stats") {
+                let mut exts: Vec<_> = stats.by_extension.iter().collect();
+                exts.sort_by_key(|(_, e)| std::cmp::Reverse(e.size));
+                for (ext, e) in exts {
+                    let ext = if ext.is_empty() { "(none)" } else { ext.as_str() };
+                    println!("{:<12} {:>6} files  {:>10}", ext, e.files, human_size(e.size));
+                }
+            }
+            if matches!(parts[0], "biggest" | "stats") {
+                for (path, size) in &stats.largest {
+                    println!("{:>10}  {}", human_size(*size), path.display());
+                }
+            }
+            if parts[0] == "stats" {
+                if let (Some((newest, n)), Some((oldest, o))) = (&stats.newest, &stats.oldest) {
+                    println!("Newest: {}  {}", format_date(*n), newest.display());
+                    println!("Oldest: {}  {}", format_date(*o), oldest.display());
+                }
+            }
+        }
+        "tree" => {
+            let max_depth = parts.get(1).and_then(|d| d.parse().ok());
+            let exclude = flag_values(parts, "--exclude");
+            let color = ColorMode::from_args(parts).enabled();
+            print_tree(current_dir, "".to_string(), max_depth, &exclude, color)?;
+        }
+        "diff" => {
+            if let (Some(a), Some(b)) = (parts.get(1), parts.get(2)) {
+                let opts = DiffOptions {
+                    ignore_whitespace: parts.contains(&"--ignore-whitespace"),
+                    ignore_trailing_whitespace: parts.contains(&"--ignore-trailing-space"),
+                    ignore_blank_lines: parts.contains(&"--ignore-blank-lines"),
+                };
+                let (a_path, b_path) = (current_dir.join(a), current_dir.join(b));
+                if files_equal(&a_path, &b_path)? {
+                    println!("Files are identical.");
+                } else {
+                    print_diff(&diff_files(&a_path, &b_path, &opts)?);
+                }
+            } else {
+                return Err(usage("diff <a> <b> [--ignore-whitespace] [--ignore-trailing-space] [--ignore-blank-lines]"));
+            }
+        }
+        "hash" => {
+            if let Some(file) = parts.get(1) {
+                match parts.get(2).map(|a| HashAlgo::parse(a).ok_or(a)).unwrap_or(Ok(HashAlgo::default())) {
+                    Ok(algo) => println!("{}  {}  {}", algo.name(), hash_file(&current_dir.join(file), algo)?, file),
+                    Err(a) => {
+                        let message = format!("Unknown algorithm '{}'. Use sha256 or md5.", a);
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+                    }
+                }
+            } else {
+                return Err(usage("hash <file> [sha256|md5]"));
+            }
+        }
+        "cmp" => {
+            if let (Some(a), Some(b)) = (parts.get(1), parts.get(2)) {
+                if files_equal(&current_dir.join(a), &current_dir.join(b))? {
+                    println!("{} and {} are identical", a, b);
+                } else {
+                    println!("{} and {} differ", a, b);
+                }
+            } else {
+                return Err(usage("cmp <a> <b>"));
+            }
+        }
+        "verify" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let (src, dst) = (current_dir.join(src), current_dir.join(dst));
+                let chunk = flag_value(parts, "--chunk").and_then(parse_size).unwrap_or(DEFAULT_VERIFY_CHUNK);
+                let report = verify_chunks(&src, &dst, chunk)?;
+                if report.is_ok() {
+                    println!("OK: {} chunk(s) of {} bytes match", report.src_hashes.len(), chunk);
+                } else {
+                    if report.src_len != report.dst_len {
+                        println!("Size differs: source {} bytes, destination {} bytes", report.src_len, report.dst_len);
+                    }
+                    for m in &report.mismatches {
+                        println!("Chunk {} differs: bytes {}..{}", m.index, m.start, m.end);
+                    }
+                    if parts.contains(&"--repair") {
+                        let bytes = repair_chunks(&src, &dst, &report)?;
+                        println!("Re-copied {} bytes", bytes);
+                    }
+                }
+            } else {
+                return Err(usage("verify <src> <dst> [--chunk N[K|M|G]] [--repair]"));
+            }
+        }
+        "sync" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let (src, dst) = (current_dir.join(src), current_dir.join(dst));
+                let retries = flag_value(parts, "--retries").and_then(|n| n.parse().ok()).unwrap_or(2);
+                let mut outcome = sync::run_sync(&src, &dst, retries)?;
+                println!("Copied {} file(s), {} bytes", outcome.copied, outcome.bytes);
+                while !outcome.failed.is_empty() {
+                    println!("{} file(s) failed:", outcome.failed.len());
+                    for failure in &outcome.failed {
+                        println!("  {}", failure.relative.display());
+                    }
+                    outcome = sync::resolve_failures(&src, &dst, &outcome.failed, retries, &mut io::stdin().lock())?;
+                    if outcome.copied > 0 {
+                        println!("Retried {} file(s), {} bytes", outcome.copied, outcome.bytes);
+                    }
+                }
+            } else {
+                return Err(usage("sync <src> <dst> [--retries N]"));
+            }
+        }
+        "mirror" => {
+            if let Some(src) = parts.get(1).filter(|s| !s.starts_with('-')) {
+                let src = current_dir.join(src);
+                let force = parts.contains(&"--force");
+                let limit = flag_value(parts, "--max-delete")
+                    .and_then(|p| p.trim_end_matches('%').parse().ok())
+                    .unwrap_or(DEFAULT_MAX_DELETE_PERCENT);
+                let mode = CacheMode::Mirror { max_delete_percent: limit, force };
+                let report = match flag_value(parts, "--to") {
+                    Some(dest) => cache_dir(&src, &current_dir.join(dest), mode)?,
+                    None => cache_to_onedrive(&src, mode)?,
+                };
+                println!(
+                    "Copied {} file(s), deleted {}, {} failed",
+                    report.copied.len(),
+                    report.deleted.len(),
+                    report.failed.len() + report.failed_dirs.len()
+                );
+            } else {
+                return Err(usage("mirror <dir> [--to <dest>] [--max-delete PCT] [--force]"));
+            }
+        }
+        "resume-cp" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let verify = parts.contains(&"--verify");
+                let report = resume_copy_dir(&current_dir.join(src), &current_dir.join(dst), verify)?;
+                println!(
+                    "Copied {}, resumed {}, re-copied {}, already complete {}",
+                    report.copied.len(),
+                    report.resumed.len(),
+                    report.recopied.len(),
+                    report.skipped
+                );
+            } else {
+                return Err(usage("resume-cp <src> <dst> [--verify]"));
+            }
+        }
+        "salvage-cp" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let retries = flag_value(parts, "--retries").and_then(|n| n.parse().ok()).unwrap_or(3);
+                let report = salvage_copy_dir(&current_dir.join(src), &current_dir.join(dst), retries)?;
+                println!("Copied {} file(s), {} bytes", report.copied, report.bytes);
+                if !report.quarantined.is_empty() {
+                    println!("Quarantined {} file(s) (see {}):", report.quarantined.len(), QUARANTINE_LOG);
+                    for (path, error) in &report.quarantined {
+                        println!("  {}: {}", path.display(), error);
+                    }
+                }
+            } else {
+                return Err(usage("salvage-cp <src> <dst> [--retries N]"));
+            }
+        }
+        "queue" => match parts.get(1) {
+            Some(&"add") => {
+                if let (Some(src), Some(dst)) = (parts.get(2), parts.get(3)) {
+                    queue.add(&current_dir.join(src), &current_dir.join(dst));
+                } else {
+                    return Err(usage("queue add <src> <dst>"));
+                }
+            }
+            Some(&"list") => queue.list(),
+            Some(&"run") => {
+                let jobs = flag_value(parts, "--jobs").and_then(|n| n.parse().ok()).unwrap_or(1);
+                let budget = match flag_value(parts, "--max-open").and_then(|n| n.parse().ok()) {
+                    Some(n) => FdBudget::new(n),
+                    None => FdBudget::from_os_limit(),
+                };
+                let results = queue.run(jobs, &budget);
+                transfer::print_summary(&results);
+            }
+            _ => return Err(usage("queue <add SRC DST|list|run [--jobs N] [--max-open N]>")),
+        },
+        "dupes" => {
+            let root = parts.get(1).map_or(current_dir.clone(), |d| current_dir.join(d));
+            print_duplicates(&find_duplicates(&root)?);
+        }
+        "dupe-dirs" => {
+            let root = parts.get(1).map_or(current_dir.clone(), |p| current_dir.join(p));
+            let groups = find_duplicate_dirs(&root)?;
+            print_duplicate_dirs(&groups);
+        }
+        "portablize" => {
+            if let (Some(f), Some(base)) = (parts.get(1), flag_value(parts, "--base")) {
+                let flagged = portablize_file(&current_dir.join(f), &current_dir.join(base))?;
+                for path in flagged {
+                    println!("Outside base, left absolute: {}", path);
+                }
+            } else {
+                return Err(usage("portablize <file> --base <dir>"));
+            }
+        }
+        "resolve" => {
+            if let (Some(f), Some(base)) = (parts.get(1), flag_value(parts, "--base")) {
+                resolve_file(&current_dir.join(f), &current_dir.join(base))?;
+            } else {
+                return Err(usage("resolve <file> --base <dir>"));
+            }
+        }
+        "tag" => {
+            if parts.len() < 3 {
+                return Err(usage("tag <path> <tag>..."));
+            }
+            tags.tag(&current_dir.join(parts[1]), &parts[2..])?;
+            tags.save()?;
+        }
+        "untag" => {
+            if let (Some(f), Some(name)) = (parts.get(1), parts.get(2)) {
+                if tags.untag(&current_dir.join(f), name)? {
+                    tags.save()?;
+                } else {
+                    println!("{} is not tagged '{}'", f, name);
+                }
+            } else {
+                return Err(usage("untag <path> <tag>"));
+            }
+        }
+        "tagged" => {
+            if let Some(name) = parts.get(1) {
+                for path in tags.tagged(name) {
+                    println!("{}", path.display());
+                }
+            } else {
+                return Err(usage("tagged <tag>"));
+            }
+        }
+        "treemap" => {
+            let root = match parts.get(1) {
+                Some(p) if !p.starts_with("--") => current_dir.join(p),
+                _ => current_dir.clone(),
+            };
+            let depth = flag_value(parts, "--depth").and_then(|d| d.parse().ok()).unwrap_or(2);
+            print_treemap(&size_tree(&root)?, 40, depth);
+        }
+        "manifest" => match (parts.get(1), parts.get(2)) {
+            (Some(&"create"), Some(out)) => {
+                let out_path = current_dir.join(out);
+                let mut manifest = Manifest::scan_hashed(current_dir)?;
+                if let Some(rel) = relative_to(&out_path, current_dir) {
+                    manifest.remove(&to_portable(&rel));
+                }
+                manifest.save(&out_path)?;
+                println!("Recorded {} entries to {}", manifest.entries.len(), out);
+            }
+            (Some(&"verify"), Some(file)) => {
+                let manifest = Manifest::load(&current_dir.join(file))?;
+                let new_root = parts.get(3).map(|r| current_dir.join(r));
+                let mismatches = verify_manifest(&manifest, new_root.as_deref())?;
+                if mismatches.is_empty() {
+                    println!("All files match the manifest.");
+                }
+                for mismatch in mismatches {
+                    println!("{}", mismatch);
+                }
+            }
+            _ => return Err(usage("manifest <create OUT|verify MANIFEST [NEW_ROOT]>")),
+        },
+        "perms" => match (parts.get(1), parts.get(2)) {
+            (Some(&"snapshot"), Some(out)) => {
+                let out_path = current_dir.join(out);
+                let mut snapshot = Manifest::scan(current_dir)?;
+                if let Some(rel) = relative_to(&out_path, current_dir) {
+                    snapshot.remove(&to_portable(&rel));
+                }
+                snapshot.save(&out_path)?;
+                println!("Recorded {} entries to {}", snapshot.entries.len(), out);
+            }
+            (Some(&"diff"), Some(snap)) => {
+                let snap_path = current_dir.join(snap);
+                let old = Manifest::load(&snap_path)?;
+                let mut new = Manifest::scan(current_dir)?;
+                if let Some(rel) = relative_to(&snap_path, current_dir) {
+                    new.remove(&to_portable(&rel));
+                }
+                let changes = diff_permissions(&old, &new);
+                if changes.is_empty() {
+                    println!("No permission changes.");
+                }
+                for change in changes {
+                    println!("{}", change);
+                }
+            }
+            _ => return Err(usage("perms <snapshot OUT|diff SNAP>")),
+        },
+        "timeline" => {
+            let root = match parts.get(1) {
+                Some(p) if !p.starts_with("--") => current_dir.join(p),
+                _ => current_dir.clone(),
+            };
+            let days = flag_value(parts, "--days").and_then(|d| d.parse().ok());
+            for group in timeline(&root, days, SystemTime::now())? {
+                println!("{}  {:>6} files  {:>12} bytes", group.date, group.count, group.size);
+            }
+        }
+        "heatmap" => {
+            let root = parts.get(1).map_or(current_dir.clone(), |p| current_dir.join(p));
+            let mut rows: Vec<(PathBuf, Heat)> = heatmap(&root, SystemTime::now())?.into_iter().collect();
+            rows.sort_by(|a, b| {
+                let key = |h: &Heat| (h.today, h.week, h.month);
+                key(&b.1).cmp(&key(&a.1)).then_with(|| a.0.cmp(&b.0))
+            });
+            println!("{:>7} {:>7} {:>7} {:>7}  directory", "today", "week", "month", "older");
+            for (dir, h) in rows {
+                let name = if dir.as_os_str().is_empty() { ".".to_string() } else { dir.display().to_string() };
+                println!("{:>7} {:>7} {:>7} {:>7}  {}", h.today, h.week, h.month, h.older, name);
+            }
+        }
+        "freshness" => {
+            let root = match parts.get(1) {
+                Some(p) if !p.starts_with("--") => current_dir.join(p),
+                _ => current_dir.clone(),
+            };
+            let stale_days = flag_value(parts, "--stale").and_then(|d| d.parse().ok());
+            let report = freshness(&root, stale_days, SystemTime::now())?;
+            if let (Some((newest, newest_time)), Some((oldest, oldest_time))) = (&report.newest, &report.oldest) {
+                println!("Files:  {}", report.files);
+                println!("Newest: {}  {}", format_date(*newest_time), newest.display());
+                println!("Oldest: {}  {}", format_date(*oldest_time), oldest.display());
+                println!("Median age: {:.1} days", report.median_age_days.unwrap_or_default());
+            } else {
+                println!("No files found.");
+            }
+            if let Some(days) = stale_days {
+                println!("{} file(s) older than {} days:", report.stale.len(), days);
+                for path in &report.stale {
+                    println!("  {}", path.display());
+                }
+            }
+        }
+        "entropy" => {
+            if let Some(f) = parts.get(1) {
+                let bits = file_entropy(&current_dir.join(f))?;
+                let note = if bits >= HIGH_ENTROPY_THRESHOLD {
+                    " (likely compressed or encrypted; compression won't help)"
+                } else {
+                    ""
+                };
+                println!("{:.3} bits/byte{}", bits, note);
+            } else {
+                return Err(usage("entropy <file>"));
+            }
+        }
+        "tar" => {
+            if let (Some(src), Some(out)) = (parts.get(1), parts.get(2)) {
+                let reproducible = parts.contains(&"--reproducible");
+                let size = create_tar(&current_dir.join(src), &current_dir.join(out), reproducible)?;
+                println!("Wrote {} ({} bytes)", out, size);
+            } else {
+                return Err(usage("tar <src> <out.tar> [--reproducible]"));
+            }
+        }
+        "space-needed" => {
+            if let (Some(src), Some(dst)) = (parts.get(1), parts.get(2)) {
+                let estimate = sync_space_needed(&current_dir.join(src), &current_dir.join(dst))?;
+                match (estimate.available, estimate.fits()) {
+                    (Some(available), Some(fits)) => println!(
+                        "Sync needs {} bytes, {} available: {}",
+                        estimate.required,
+                        available,
+                        if fits { "fits" } else { "does NOT fit" }
+                    ),
+                    _ => println!("Sync needs {} bytes (free space unknown)", estimate.required),
+                }
+            } else {
+                return Err(usage("space-needed <src> <dst>"));
+            }
+        }
+        #[cfg(feature = "net")]
+        "download" => {
+            if let (Some(url), Some(dest)) = (parts.get(1), parts.get(2)) {
+                let expected = flag_value(parts, "--sha256");
+                let n = crate::net::download(url, &current_dir.join(dest), expected, &mut |done, total| {
+                    match total {
+                        Some(t) => print!("\rDownloading... {}/{} bytes", done, t),
+                        None => print!("\rDownloading... {} bytes", done),
+                    }
+                    io::stdout().flush().ok();
+                })?;
+                println!("\nDownloaded {} bytes to {}", n, dest);
+            } else {
+                return Err(usage("download <url> <dest> [--sha256 <digest>]"));
+            }
+        }
+        "bookmark" => match parts.get(1) {
+            Some(name) => {
+                session.bookmarks.insert(name.to_string(), current_dir.clone());
+                println!("Bookmarked {} as '{}'", current_dir.display(), name);
+            }
+            None => {
+                for (name, dir) in &session.bookmarks {
+                    println!("{:<16} {}", name, dir.display());
+                }
+            }
+        },
+        "go" => match parts.get(1).map(|name| session.bookmarks.get(*name)) {
+            Some(Some(dir)) if dir.is_dir() => *current_dir = dir.clone(),
+            Some(Some(dir)) => {
+                let message = format!("Bookmarked directory no longer exists: {}", dir.display());
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+            Some(None) => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("No bookmark named '{}'", parts[1])));
+            }
+            None => return Err(usage("go <bookmark>")),
+        },
+        "alias" => match (parts.get(1), parts.len()) {
+            (Some(name), 2) => match session.aliases.remove(*name) {
+                Some(_) => println!("Removed alias '{}'", name),
+                None => println!("No alias named '{}'", name),
+            },
+            (Some(name), _) => {
+                session.aliases.insert(name.to_string(), parts[2..].join(" "));
+            }
+            (None, _) => {
+                for (name, expansion) in &session.aliases {
+                    println!("{:<16} {}", name, expansion);
+                }
+            }
+        },
+        "history" => {
+            for (i, entry) in session.history.iter().enumerate() {
+                println!("{:>5}  {}", i + 1, entry);
+            }
+        }
+        "yank" => {
+            for p in &parts[1..] {
+                session.paste_buffer.push(current_dir.join(p));
+            }
+            println!("{} path(s) in paste buffer", session.paste_buffer.len());
+        }
+        "paste" => {
+            for src in std::mem::take(&mut session.paste_buffer) {
+                let dst = current_dir.join(src.file_name().unwrap_or_default());
+                match copy_with_options(&src, &dst, &Options::default()) {
+                    Ok(bytes) => println!("Pasted {} ({} bytes)", dst.display(), bytes),
+                    Err(e) => eprintln!("Failed to paste {}: {}", src.display(), e),
+                }
+            }
+        }
+        "session" => match (parts.get(1), parts.get(2)) {
+            (Some(&"save"), Some(file)) => {
+                session.cwd = current_dir.clone();
+                session.save(&current_dir.join(file))?;
+                println!("Session saved to {}", file);
+            }
+            (Some(&"load"), Some(file)) => {
+                let loaded = Session::load(&current_dir.join(file))?;
+                if loaded.cwd.is_dir() {
+                    *current_dir = loaded.cwd.clone();
+                } else {
+                    println!("Saved directory no longer exists: {}", loaded.cwd.display());
+                }
+                *session = loaded;
+                println!("Session loaded from {}", file);
+            }
+            _ => return Err(usage("session <save|load> <file>")),
+        },
+        "exit" | "quit" => {
+            *exit_requested = true;
+        }
+        _ => {
+            let message = format!("Unknown command '{}'. Commands: {}", parts[0], command_names().collect::<Vec<_>>().join(", "));
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+    }
+    Ok(())
+}
+
+/// Runs each line of the script at `path` as an explorer command. Blank lines and lines starting
+/// with `#` are skipped. The first failing command stops the script unless `keep_going` is set, in
+/// which case every failure is reported and the script fails at the end. Errors name the line number.
+pub fn run_script(state: &mut ExplorerState, path: &Path, keep_going: bool) -> io::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut failures = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let words = state.command_words(trimmed);
+        let parts: Vec<&str> = words.iter().map(String::as_str).collect();
+        if let Err(e) = run_command(state, &parts) {
+            let e = io::Error::new(e.kind(), format!("{}:{}: {}: {}", path.display(), index + 1, trimmed, e));
+            if !keep_going {
+                return Err(e);
+            }
+            eprintln!("{}", e);
+            failures += 1;
+        }
+        if state.exit_requested {
+            break;
+        }
+    }
+    if failures > 0 {
+        return Err(io::Error::other(format!("{} command(s) in {} failed", failures, path.display())));
+    }
+    Ok(())
+}
+
+/// Interactive explorer loop.
+pub fn explorer_loop() -> io::Result<()> {
+    let mut state = ExplorerState::new(&env::current_dir()?)?;
+    let mut editor: Editor<ExplorerHelper, DefaultHistory> = Editor::new().map_err(io::Error::other)?;
+    editor.set_helper(Some(ExplorerHelper { cwd: state.current_dir.clone() }));
+    let history_path = session::history_file();
+    if let Some(path) = &history_path {
+        // A missing history file just means a first run.
+        let _ = editor.load_history(path);
+    }
+    while !state.exit_requested {
+        if let Some(helper) = editor.helper_mut() {
+            helper.cwd = state.current_dir.clone();
+        }
+        let input = match editor.readline(&format!("RuForUs:{}> ", state.current_dir.display())) {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.trim());
+            state.session.record(input.trim());
+        }
+        let words = state.command_words(&input);
+        let parts: Vec<&str> = words.iter().map(String::as_str).collect();
+        // A failed command is reported and the session goes on.
+        if let Err(e) = run_command(&mut state, &parts) {
+            eprintln!("{}: {}", parts[0], e);
+        }
+    }
+    if let Some(path) = &history_path {
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_item_display() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.txt");
+        File::create(&file_path).unwrap();
+        let entry = fs::read_dir(temp_dir.path()).unwrap().next().unwrap().unwrap();
+        let file_item = FileItem::new(&entry).unwrap();
+        file_item.display(true);
+        file_item.display_raw();
+        assert!(!file_item.line(false, false).contains('\x1b'));
+
+        let mut out = Vec::new();
+        file_item.display_to(&mut out, true, true).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert!(line.starts_with("      "), "{:?}", line);
+        assert!(line.contains("test.txt") && line.ends_with('\n') && !line.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_list_dir_to() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("five.txt"), "12345").unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        let opts = ListOptions { raw: true, sort: SortOrder { dirs_first: true, ..Default::default() }, ..Default::default() };
+        let mut out = Vec::new();
+        list_dir_to(&mut out, temp_dir.path(), &opts).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[1].starts_with("Listing: "));
+        assert!(lines[2].starts_with("<DIR>") && lines[2].contains("sub"));
+        assert!(lines[3].contains("         5 five.txt"), "{:?}", lines[3]);
+    }
+
+    #[test]
+    fn test_file_item_json() {
+        let item = FileItem {
+            path: PathBuf::from("dir/a.txt"),
+            is_dir: false,
+            size: 42,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        };
+        let value = serde_json::to_value([item]).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([{ "path": "dir/a.txt", "is_dir": false, "size": 42, "modified": 1_700_000_000 }])
+        );
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("f.txt");
+        fs::write(&file, b"abc").unwrap();
+        let stat = serde_json::to_value(StatInfo::new(&file).unwrap()).unwrap();
+        assert_eq!(stat["size"], 3);
+        assert!(stat["modified"].is_u64());
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1_048_575), "1.0 MiB");
+        assert_eq!(human_size(1_048_576), "1.0 MiB");
+        assert_eq!(human_size(1_572_864), "1.5 MiB");
+        assert_eq!(human_size(4 * 1024 * 1024 * 1024), "4.0 GiB");
+    }
+
+    #[test]
+    fn test_collect_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::write(root.join("top.txt"), b"1").unwrap();
+        fs::write(root.join("sub/mid.txt"), b"22").unwrap();
+        fs::write(root.join("sub/deeper/low.txt"), b"333").unwrap();
+
+        let flat = collect_dir(root, false, None, &[], &SortOrder::default(), false).unwrap();
+        assert_eq!(flat.len(), 2);
+
+        let mut all: Vec<(PathBuf, u64)> = collect_dir(root, true, None, &[], &SortOrder::default(), false)
+            .unwrap()
+            .into_iter()
+            .map(|i| (i.path.strip_prefix(root).unwrap().to_path_buf(), i.size))
+            .collect();
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                (PathBuf::from("sub"), 0),
+                (PathBuf::from("sub/deeper"), 0),
+                (PathBuf::from("sub/deeper/low.txt"), 3),
+                (PathBuf::from("sub/mid.txt"), 2),
+                (PathBuf::from("top.txt"), 1),
+            ]
+        );
+        let shallow: Vec<PathBuf> = collect_dir(root, true, Some(1), &[], &SortOrder::default(), false)
+            .unwrap()
+            .into_iter()
+            .map(|i| i.path.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(shallow.len(), 4);
+        assert!(!shallow.contains(&PathBuf::from("sub/deeper/low.txt")));
+        assert_eq!(collect_dir(root, true, Some(0), &[], &SortOrder::default(), false).unwrap().len(), 2);
+        let missing = collect_dir(&root.join("missing"), true, None, &[], &SortOrder::default(), false);
+        assert!(missing.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_collect_dir_sorting() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("m_dir")).unwrap();
+        fs::write(root.join("b.txt"), vec![0u8; 30]).unwrap();
+        fs::write(root.join("a.txt"), vec![0u8; 20]).unwrap();
+        fs::write(root.join("z.txt"), vec![0u8; 10]).unwrap();
+        let names = |sort: SortOrder| -> Vec<String> {
+            collect_dir(root, false, None, &[], &sort, false)
+                .unwrap()
+                .iter()
+                .map(|i| i.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(names(SortOrder::default()), vec!["a.txt", "b.txt", "m_dir", "z.txt"]);
+        let by_size = SortOrder { key: SortKey::Size, descending: true, ..Default::default() };
+        assert_eq!(names(by_size), vec!["b.txt", "a.txt", "z.txt", "m_dir"]);
+        let dirs_first = SortOrder { dirs_first: true, ..by_size };
+        assert_eq!(names(dirs_first), vec!["m_dir", "b.txt", "a.txt", "z.txt"]);
+        assert_eq!(SortKey::parse("Size"), Some(SortKey::Size));
+        assert_eq!(SortKey::parse("colour"), None);
+    }
+
+    #[test]
+    fn test_hidden_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join(".profile"), b"x").unwrap();
+        fs::write(root.join("visible.txt"), b"x").unwrap();
+        let names = |show_hidden: bool| -> Vec<String> {
+            collect_dir(root, false, None, &[], &SortOrder::default(), show_hidden)
+                .unwrap()
+                .iter()
+                .map(|i| i.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(names(false), vec!["visible.txt"]);
+        assert_eq!(names(true), vec![".profile", "visible.txt"]);
+    }
+
+    #[test]
+    fn test_exclude_prunes_subtree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        fs::create_dir(root.join(".git")).unwrap();
+        fs::write(root.join("node_modules/pkg/index.js"), vec![0u8; 1000]).unwrap();
+        fs::write(root.join(".git/HEAD"), b"ref").unwrap();
+        fs::write(root.join("main.rs"), b"fn main() {}").unwrap();
+        fs::write(root.join("debug.log"), b"log").unwrap();
+
+        let exclude = vec!["node_modules".to_string(), ".git".to_string(), "*.log".to_string()];
+        let items = collect_dir(root, true, None, &exclude, &SortOrder::default(), false).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].path, root.join("main.rs"));
+        assert_eq!(dir_size(root, &exclude).unwrap(), 12);
+        assert_eq!(dir_size(root, &[]).unwrap(), 1018);
+    }
+
+    #[test]
+    fn test_dir_size_parallel_matches_sequential() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for d in 0..8 {
+            let dir = root.join(format!("d{}/inner", d));
+            fs::create_dir_all(&dir).unwrap();
+            for f in 0..20 {
+                fs::write(dir.join(format!("{}.bin", f)), vec![0u8; d * 100 + f]).unwrap();
+            }
+        }
+        #[cfg(unix)]
+        fs::hard_link(root.join("d7/inner/19.bin"), root.join("d0/dup.bin")).unwrap();
+        assert_eq!(dir_size_parallel(root).unwrap(), dir_size(root, &[]).unwrap());
+        assert!(has_many_entries(root, 100));
+        assert!(!has_many_entries(root, 1000));
+
+        let err = dir_size_parallel(&root.join("missing")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let locked = root.join("d3");
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+            // Running as root ignores directory permissions, so only check when they apply.
+            if fs::read_dir(&locked).is_err() {
+                assert!(dir_size_parallel(root).unwrap_err().to_string().contains("d3"));
+            }
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dir_size_hardlinks_and_symlinks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("big.bin"), vec![0u8; 1000]).unwrap();
+        fs::hard_link(root.join("big.bin"), root.join("sub/big-link.bin")).unwrap();
+        fs::write(root.join("sub/small.txt"), vec![0u8; 10]).unwrap();
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("other.bin"), vec![0u8; 500]).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("outside-link")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("sub/loop")).unwrap();
+
+        let link_len = |p: &str| fs::symlink_metadata(root.join(p)).unwrap().len();
+        let links = link_len("outside-link") + link_len("sub/loop");
+        assert_eq!(dir_size(&root, &[]).unwrap(), 1010 + links);
+        assert_eq!(dir_size_with(&root, &[], true).unwrap(), 1510);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filter_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("in.txt");
+        let out = temp_dir.path().join("out.txt");
+        let big = "hello world\n".repeat(50_000);
+        fs::write(&src, &big).unwrap();
+
+        let bytes = filter_file(&src, "tr", &["a-z", "A-Z"], Some(&out)).unwrap();
+        assert_eq!(bytes, big.len() as u64);
+        assert_eq!(fs::read_to_string(&out).unwrap(), big.to_uppercase());
+
+        filter_file(&src, "head", &["-c", "5"], None).unwrap();
+        assert_eq!(fs::read_to_string(&src).unwrap(), "hello");
+
+        assert!(filter_file(&src, "false", &[], None).is_err());
+        assert_eq!(fs::read_to_string(&src).unwrap(), "hello");
+
+        // An unwritable destination stops a command that would otherwise never finish.
+        assert!(filter_file(&src, "yes", &[], Some(&temp_dir.path().join("missing/out.txt"))).is_err());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_bom_detect_strip_add() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("bom.csv");
+        fs::write(&file, b"\xef\xbb\xbfname,size\n").unwrap();
+        assert_eq!(detect_bom(&file).unwrap(), Some(Bom::Utf8));
+
+        assert_eq!(strip_bom(&file).unwrap(), Some(Bom::Utf8));
+        assert_eq!(fs::read(&file).unwrap(), b"name,size\n");
+        assert_eq!(strip_bom(&file).unwrap(), None);
+
+        assert!(add_utf8_bom(&file).unwrap());
+        assert_eq!(fs::read(&file).unwrap(), b"\xef\xbb\xbfname,size\n");
+        assert!(!add_utf8_bom(&file).unwrap());
+
+        let utf16 = temp_dir.path().join("utf16.txt");
+        fs::write(&utf16, b"\xff\xfeh\0i\0").unwrap();
+        assert_eq!(detect_bom(&utf16).unwrap(), Some(Bom::Utf16Le));
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_copy_and_delete_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("a.txt");
+        let dst = temp_dir.path().join("b.txt");
+        fs::write(&src, b"hello").unwrap();
+        filetime::set_file_mtime(&src, filetime::FileTime::from_unix_time(1_400_000_000, 500_000_000)).unwrap();
+        copy_file(&src, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), b"hello");
+        let mtime = |p: &Path| fs::metadata(p).unwrap().modified().unwrap();
+        let drift = mtime(&src).duration_since(mtime(&dst)).unwrap_or_else(|e| e.duration());
+        assert!(drift < Duration::from_secs(1));
+        delete_path(&dst).unwrap();
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_copy_file_aborted_leaves_no_partial_file() {
+        struct FailingReader(usize);
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(io::Error::other("device removed"));
+                }
+                self.0 -= 1;
+                buf[..4].copy_from_slice(b"data");
+                Ok(4)
+            }
+        }
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dst = temp_dir.path().join("out.bin");
+        let meta = fs::metadata(temp_dir.path()).unwrap();
+        assert!(copy_atomic(&mut FailingReader(3), &meta, &dst, true).is_err());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+
+        fs::write(&dst, b"previous").unwrap();
+        assert!(copy_atomic(&mut FailingReader(3), &meta, &dst, true).is_err());
+        assert_eq!(fs::read(&dst).unwrap(), b"previous");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_copy_dir_into_new_nested_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("sub/b.txt"), "b").unwrap();
+
+        let dst = temp_dir.path().join("target/a/b/c");
+        copy_dir(&src, &dst).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("sub/b.txt")).unwrap(), "b");
+
+        fs::write(dst.join("kept.txt"), "kept").unwrap();
+        fs::write(src.join("a.txt"), "updated").unwrap();
+        copy_dir(&src, &dst).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "updated");
+        assert_eq!(fs::read_to_string(dst.join("kept.txt")).unwrap(), "kept");
+
+        // Skipping leaves existing files alone but still adds new ones.
+        fs::write(src.join("a.txt"), "newest").unwrap();
+        fs::write(src.join("sub/new.txt"), "new").unwrap();
+        copy_dir_with(&src, &dst, SymlinkPolicy::default(), OverwritePolicy::Skip).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "updated");
+        assert_eq!(fs::read_to_string(dst.join("sub/new.txt")).unwrap(), "new");
+        assert_eq!(copy_file_with(&src.join("a.txt"), &dst.join("a.txt"), OverwritePolicy::Skip, true).unwrap(), 0);
+
+        // A directory where the file should go makes that one file fail.
+        fs::remove_file(dst.join("a.txt")).unwrap();
+        fs::create_dir(dst.join("a.txt")).unwrap();
+        let err = copy_dir(&src, &dst).unwrap_err();
+        assert!(err.to_string().contains(&src.join("a.txt").display().to_string()));
+    }
+
+    #[test]
+    fn test_move_path_cross_device_fallback() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("a.bin");
+        let dst = temp_dir.path().join("b.bin");
+        fs::write(&src, b"payload").unwrap();
+        let cross_device = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::CrossesDevices));
+        move_path_with(&src, &dst, &cross_device).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst).unwrap(), b"payload");
+
+        let denied = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(move_path_with(&dst, &src, &denied).is_err());
+        assert!(dst.exists());
+
+        let dir = temp_dir.path().join("dir");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/c.txt"), "c").unwrap();
+        move_path(&dir, &temp_dir.path().join("moved")).unwrap();
+        assert!(!dir.exists());
+        assert_eq!(fs::read_to_string(temp_dir.path().join("moved/sub/c.txt")).unwrap(), "c");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_path_dir_with_symlinks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("dir");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("data/file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("data", dir.join("data-link")).unwrap();
+        std::os::unix::fs::symlink("missing", dir.join("dangling")).unwrap();
+
+        let moved = temp_dir.path().join("moved");
+        move_path(&dir, &moved).unwrap();
+        assert!(!dir.exists());
+        assert_eq!(fs::read_link(moved.join("data-link")).unwrap(), Path::new("data"));
+        assert_eq!(fs::read_link(moved.join("dangling")).unwrap(), Path::new("missing"));
+        assert_eq!(fs::read_to_string(moved.join("data-link/file.txt")).unwrap(), "content");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_dir_symlink_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir_all(src.join("data")).unwrap();
+        fs::write(src.join("data/file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("data/file.txt", src.join("link.txt")).unwrap();
+        // Points back at its own parent, which following would recurse into forever.
+        std::os::unix::fs::symlink("..", src.join("data/up")).unwrap();
+
+        let dst = temp_dir.path().join("recreated");
+        copy_dir(&src, &dst).unwrap();
+        assert!(fs::symlink_metadata(dst.join("link.txt")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(dst.join("link.txt")).unwrap(), PathBuf::from("data/file.txt"));
+        assert_eq!(fs::read_to_string(dst.join("link.txt")).unwrap(), "content");
+        assert_eq!(fs::read_link(dst.join("data/up")).unwrap(), PathBuf::from(".."));
+        copy_dir(&src, &dst).unwrap();
+
+        let skipped = temp_dir.path().join("skipped");
+        copy_dir_with(&src, &skipped, SymlinkPolicy::Skip, OverwritePolicy::Overwrite).unwrap();
+        assert!(!skipped.join("link.txt").exists());
+        assert!(skipped.join("data/file.txt").exists());
+
+        // The loop link stays in place: following stops at it instead of recursing forever
+        fs::create_dir_all(src.join("other")).unwrap();
+        fs::write(src.join("other/more.txt"), "more").unwrap();
+        std::os::unix::fs::symlink("../other", src.join("data/other")).unwrap();
+        let followed = temp_dir.path().join("followed");
+        copy_dir_with(&src, &followed, SymlinkPolicy::Follow, OverwritePolicy::Overwrite).unwrap();
+        assert!(!fs::symlink_metadata(followed.join("link.txt")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(followed.join("link.txt")).unwrap(), "content");
+        assert!(!followed.join("data/up").exists());
+        assert_eq!(fs::read_to_string(followed.join("data/other/more.txt")).unwrap(), "more");
+        assert_eq!(fs::read_to_string(followed.join("other/more.txt")).unwrap(), "more");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_print_tree_stops_at_cycle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("root");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("a/b/loop")).unwrap();
+        std::os::unix::fs::symlink(".", root.join("self")).unwrap();
+        let mut out = Vec::new();
+        print_tree_to(&mut out, &root, String::new(), None, &[], false).unwrap();
+        let tree = String::from_utf8(out).unwrap();
+        assert!(tree.contains("      loop/ [cycle]\n"), "{}", tree);
+        assert!(tree.contains("  self/ [cycle]\n"), "{}", tree);
+
+        let mut sink = io::sink();
+        let mut walk = TreeWalk { out: &mut sink, max_depth: None, exclude: &[], color: false, ancestors: HashSet::new() };
+        walk.print(&root, String::new(), 0).unwrap();
+        assert!(walk.ancestors.is_empty());
+    }
+
+    #[test]
+    fn test_trash_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let trash = temp_dir.path().join("trash");
+        let file = temp_dir.path().join("notes.txt");
+        fs::write(&file, "first").unwrap();
+        assert_eq!(trash_path_in(&file, &trash).unwrap(), trash.join("notes.txt"));
+        assert!(!file.exists());
+
+        fs::write(&file, "second").unwrap();
+        let second = trash_path_in(&file, &trash).unwrap();
+        assert_eq!(second, trash.join("notes (2).txt"));
+        assert_eq!(fs::read_to_string(second).unwrap(), "second");
+        assert_eq!(fs::read_to_string(trash.join("notes.txt")).unwrap(), "first");
+
+        let dir = temp_dir.path().join("album");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("a.jpg"), "jpeg").unwrap();
+        let moved = trash_path_in(&dir, &trash).unwrap();
+        assert!(!dir.exists());
+        assert_eq!(fs::read_to_string(moved.join("a.jpg")).unwrap(), "jpeg");
+        assert!(trash_path_in(&dir, &trash).is_err());
+    }
+
+    #[test]
+    fn test_mv_updates_tag_index() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("album")).unwrap();
+        fs::write(root.join("album/song.mp3"), b"la").unwrap();
+        let mut tags = TagIndex::load(root).unwrap();
+        tags.tag(&root.join("album/song.mp3"), &["fav"]).unwrap();
+
+        move_tagged(&root.join("album"), &root.join("best_of"), &mut tags).unwrap();
+        let reloaded = TagIndex::load(root).unwrap();
+        assert_eq!(reloaded.tagged("fav"), vec![root.join("best_of").join("song.mp3")]);
+    }
+
+    #[test]
+    fn test_treemap_proportions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("big/inner")).unwrap();
+        fs::create_dir(root.join("small")).unwrap();
+        fs::write(root.join("big/a.bin"), vec![0u8; 100]).unwrap();
+        fs::write(root.join("big/inner/b.bin"), vec![0u8; 200]).unwrap();
+        fs::write(root.join("small/c.bin"), vec![0u8; 100]).unwrap();
+
+        let tree = size_tree(root).unwrap();
+        assert_eq!(tree.size, 400);
+        let sizes: Vec<u64> = tree.children.iter().map(|c| c.size).collect();
+        assert_eq!(sizes, vec![300, 100]);
+        assert_eq!(proportional_widths(&sizes, tree.size, 40), vec![30, 10]);
+
+        let big = &tree.children[0];
+        assert_eq!(proportional_widths(&[big.children[0].size], big.size, 30), vec![20]);
+        assert_eq!(proportional_widths(&[5], 0, 40), vec![0]);
+    }
+
+    #[test]
+    fn test_files_equal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let a = temp_dir.path().join("a.bin");
+        let b = temp_dir.path().join("b.bin");
+        let c = temp_dir.path().join("c.bin");
+        let mut data = vec![7u8; 300 * 1024];
+        fs::write(&a, &data).unwrap();
+        fs::write(&b, &data).unwrap();
+        data[10] = 8;
+        fs::write(&c, &data).unwrap();
+
+        assert!(files_equal(&a, &b).unwrap());
+
+        let mut read = 0;
+        assert!(!files_equal_counting(&a, &c, &mut read).unwrap());
+        assert_eq!(read, 64 * 1024);
+
+        let short = temp_dir.path().join("short.bin");
+        fs::write(&short, b"abc").unwrap();
+        let mut read = 0;
+        assert!(!files_equal_counting(&a, &short, &mut read).unwrap());
+        assert_eq!(read, 0);
+    }
+
+    #[test]
+    fn test_timeline_groups_by_day() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        let day = 86_400;
+        let now = 1_700_000_000; // 2023-11-14 22:13:20 UTC
+        let files = [
+            ("a.txt", 10, now),
+            ("sub/b.txt", 5, now - 3600),
+            ("c.txt", 7, now - day),
+            ("old.txt", 1, now - 30 * day),
+        ];
+        for (name, len, mtime) in files {
+            let path = root.join(name);
+            fs::write(&path, vec![0u8; len]).unwrap();
+            filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(mtime, 0)).unwrap();
+        }
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(now as u64);
+
+        let groups = timeline(root, None, now).unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                DayGroup { date: "2023-11-14".into(), count: 2, size: 15 },
+                DayGroup { date: "2023-11-13".into(), count: 1, size: 7 },
+                DayGroup { date: "2023-10-15".into(), count: 1, size: 1 },
+            ]
+        );
+        assert_eq!(timeline(root, Some(2), now).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_heatmap_buckets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("docs/old")).unwrap();
+        let day = 86_400;
+        let now = 1_700_000_000; // 2023-11-14 22:13:20 UTC
+        let files = [
+            ("a.txt", now - 60),
+            ("b.txt", now - 3 * day),
+            ("docs/c.txt", now - day),
+            ("docs/d.txt", now - 10 * day),
+            ("docs/e.txt", now - 2 * day),
+            ("docs/old/f.txt", now - 400 * day),
+        ];
+        for (name, mtime) in files {
+            let path = root.join(name);
+            fs::write(&path, "x").unwrap();
+            filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(mtime, 0)).unwrap();
+        }
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(now as u64);
+
+        let heat = heatmap(root, now).unwrap();
+        assert_eq!(heat.len(), 3);
+        assert_eq!(heat[Path::new("")], Heat { today: 1, week: 1, month: 0, older: 0 });
+        assert_eq!(heat[Path::new("docs")], Heat { today: 0, week: 2, month: 1, older: 0 });
+        assert_eq!(heat[Path::new("docs/old")], Heat { today: 0, week: 0, month: 0, older: 1 });
+    }
+
+    #[test]
+    fn test_freshness() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        let day = 86_400;
+        let now = 1_700_000_000;
+        let files = [("new.txt", now - day), ("sub/mid.txt", now - 10 * day), ("old.txt", now - 40 * day)];
+        for (name, mtime) in files {
+            let path = root.join(name);
+            fs::write(&path, b"x").unwrap();
+            filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(mtime, 0)).unwrap();
+        }
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(now as u64);
+
+        let report = freshness(root, Some(7), now).unwrap();
+        assert_eq!(report.files, 3);
+        assert_eq!(report.newest.unwrap().0, PathBuf::from("new.txt"));
+        let (oldest, oldest_time) = report.oldest.unwrap();
+        assert_eq!(oldest, PathBuf::from("old.txt"));
+        assert_eq!(format_date(oldest_time), "2023-10-05");
+        assert_eq!(report.median_age_days, Some(10.0));
+        assert_eq!(report.stale, vec![PathBuf::from("old.txt"), PathBuf::from("sub/mid.txt")]);
+        assert!(freshness(root, Some(60), now).unwrap().stale.is_empty());
+    }
+
+    #[test]
+    fn test_resume_copy_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(src.join("done.txt"), b"complete").unwrap();
+        fs::write(src.join("short.txt"), b"0123456789").unwrap();
+        fs::write(src.join("big.txt"), b"abc").unwrap();
+        fs::write(src.join("sub/missing.txt"), b"new").unwrap();
+        fs::write(dst.join("done.txt"), b"complete").unwrap();
+        fs::write(dst.join("short.txt"), b"01234").unwrap();
+        fs::write(dst.join("big.txt"), b"abcdef").unwrap();
+
+        let report = resume_copy_dir(&src, &dst, true).unwrap();
+        assert_eq!(report.copied, vec![PathBuf::from("sub/missing.txt")]);
+        assert_eq!(report.resumed, vec![PathBuf::from("short.txt")]);
+        assert_eq!(report.recopied, vec![PathBuf::from("big.txt")]);
+        assert_eq!(report.skipped, 1);
+        for name in ["done.txt", "short.txt", "big.txt", "sub/missing.txt"] {
+            assert_eq!(fs::read(src.join(name)).unwrap(), fs::read(dst.join(name)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_file_entropy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let zeros = temp_dir.path().join("zeros.bin");
+        fs::write(&zeros, vec![0u8; 64 * 1024]).unwrap();
+        assert!(file_entropy(&zeros).unwrap() < 0.01);
+
+        // xorshift keeps the test free of extra dependencies
+        let mut state: u32 = 0x9E37_79B9;
+        let random: Vec<u8> = (0..64 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect();
+        let noise = temp_dir.path().join("noise.bin");
+        fs::write(&noise, random).unwrap();
+        assert!(file_entropy(&noise).unwrap() > 7.9);
+    }
+
+    #[test]
+    fn test_copy_with_options() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::create_dir(src.join("empty")).unwrap();
+        fs::create_dir(&dst).unwrap();
+        fs::write(src.join("new.txt"), b"new").unwrap();
+        fs::write(src.join("sub/kept.txt"), b"source").unwrap();
+        fs::create_dir(dst.join("sub")).unwrap();
+        fs::write(dst.join("sub/kept.txt"), b"existing").unwrap();
+        filetime::set_file_mtime(src.join("new.txt"), filetime::FileTime::from_unix_time(1_000_000, 0)).unwrap();
+
+        let opts = Options { overwrite: OverwritePolicy::Skip, preserve_times: true, verify: true, ..Default::default() };
+        assert_eq!(copy_with_options(&src, &dst, &opts).unwrap(), 3);
+        assert_eq!(fs::read(dst.join("sub/kept.txt")).unwrap(), b"existing");
+        assert!(dst.join("empty").is_dir());
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(dst.join("new.txt")).unwrap());
+        assert_eq!(mtime.unix_seconds(), 1_000_000);
+
+        let fresh = temp_dir.path().join("fresh.txt");
+        copy_with_options(&src.join("new.txt"), &fresh, &Options { preserve_times: false, ..Default::default() }).unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(&fresh).unwrap());
+        assert!(mtime.unix_seconds() > 1_000_000);
+
+        // Prompting asks only about files that already exist, and a "no" skips just that file.
+        fs::write(src.join("new.txt"), b"newer").unwrap();
+        let prompt = Options { overwrite: OverwritePolicy::Prompt, ..Default::default() };
+        let mut asked = Vec::new();
+        let mut decline_kept = |p: &Path| {
+            asked.push(p.to_path_buf());
+            !p.ends_with("kept.txt")
+        };
+        assert_eq!(copy_with_options_confirming(&src, &dst, &prompt, &mut decline_kept).unwrap(), 5);
+        asked.sort();
+        assert_eq!(asked, vec![dst.join("new.txt"), dst.join("sub/kept.txt")]);
+        assert_eq!(fs::read(dst.join("new.txt")).unwrap(), b"newer");
+        assert_eq!(fs::read(dst.join("sub/kept.txt")).unwrap(), b"existing");
+
+        copy_with_options(&src, &dst, &Options::default()).unwrap();
+        assert_eq!(fs::read(dst.join("sub/kept.txt")).unwrap(), b"source");
+
+        // A link to a directory is recreated rather than copied as a file.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("sub", src.join("sub-link")).unwrap();
+            copy_with_options(&src, &dst, &Options::default()).unwrap();
+            assert_eq!(fs::read_link(dst.join("sub-link")).unwrap(), Path::new("sub"));
+
+            let skipped = temp_dir.path().join("no-links");
+            copy_with_options(&src, &skipped, &Options { symlinks: SymlinkPolicy::Skip, ..Default::default() }).unwrap();
+            assert!(skipped.join("sub/kept.txt").exists());
+            assert!(fs::symlink_metadata(skipped.join("sub-link")).is_err());
+        }
+    }
+
+    #[test]
+    fn test_salvage_copy_quarantines_unreadable_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("good.txt"), b"fine").unwrap();
+        fs::write(src.join("sub/bad.bin"), b"bad sector").unwrap();
+        fs::write(src.join("sub/also_good.txt"), b"ok").unwrap();
+
+        let attempts = std::cell::Cell::new(0);
+        let flaky = |from: &Path, to: &Path| {
+            if from.ends_with("bad.bin") {
+                attempts.set(attempts.get() + 1);
+                return Err(io::Error::other("read error"));
+            }
+            copy_file(from, to)
+        };
+        let report = salvage_copy_dir_with(&src, &dst, 2, &flaky).unwrap();
+
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(report.copied, 2);
+        assert_eq!(report.quarantined.len(), 1);
+        assert_eq!(report.quarantined[0].0, PathBuf::from("sub/bad.bin"));
+        assert_eq!(fs::read(dst.join("good.txt")).unwrap(), b"fine");
+        assert_eq!(fs::read(dst.join("sub/also_good.txt")).unwrap(), b"ok");
+        assert!(!dst.join("sub/bad.bin").exists());
+        let log = fs::read_to_string(dst.join(QUARANTINE_LOG)).unwrap();
+        assert!(log.starts_with("sub/bad.bin\tread error"));
+    }
+
+    #[test]
+    fn test_swap_paths() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let a = temp_dir.path().join("a.txt");
+        let b = temp_dir.path().join("b.txt");
+        fs::write(&a, b"first").unwrap();
+        fs::write(&b, b"second").unwrap();
+        swap_paths(&a, &b).unwrap();
+        assert_eq!(fs::read(&a).unwrap(), b"second");
+        assert_eq!(fs::read(&b).unwrap(), b"first");
+
+        swap_paths_with_temp(&a, &b).unwrap();
+        assert_eq!(fs::read(&a).unwrap(), b"first");
+        assert_eq!(fs::read(&b).unwrap(), b"second");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+
+        assert!(swap_paths(&a, &temp_dir.path().join("missing")).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_swap_paths_atomic_linux() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let a = temp_dir.path().join("dir_a");
+        let b = temp_dir.path().join("file_b");
+        fs::create_dir(&a).unwrap();
+        fs::write(&b, b"b").unwrap();
+        match exchange_paths_atomic(&a, &b) {
+            Ok(()) => {
+                assert!(b.is_dir());
+                assert_eq!(fs::read(&a).unwrap(), b"b");
+            }
+            // Filesystems without RENAME_EXCHANGE support can't exercise the syscall
+            Err(e) => assert_eq!(e.raw_os_error(), Some(libc::EINVAL)),
+        }
+    }
+
+    #[test]
+    fn test_name_matcher() {
+        let rs = NameMatcher::glob("*.rs").unwrap();
+        assert!(rs.matches(Path::new("src/main.rs"), false));
+        assert!(!rs.matches(Path::new("src/main.rso"), false));
+
+        let numbered = NameMatcher::glob("test_??.txt").unwrap();
+        assert!(numbered.matches(Path::new("test_01.txt"), false));
+        assert!(!numbered.matches(Path::new("test_1.txt"), false));
+
+        let nested = NameMatcher::glob("src/*.rs").unwrap();
+        assert!(nested.matches(Path::new("src/lib.rs"), false));
+        assert!(!nested.matches(Path::new("src/bin/tool.rs"), false));
+        assert!(!nested.matches(Path::new("lib.rs"), false));
+
+        let substr = NameMatcher::Substring("main".into());
+        assert!(substr.matches(Path::new("src/main.rs"), false));
+
+        let upper = Path::new("PHOTO.JPG");
+        assert!(!NameMatcher::glob("*.jpg").unwrap().matches(upper, false));
+        assert!(NameMatcher::glob("*.jpg").unwrap().matches(upper, true));
+        assert!(NameMatcher::Substring("photo".into()).matches(upper, true));
+        let re = NameMatcher::parse_list(&["-e", "^photo"], true).unwrap().remove(0);
+        assert!(re.matches(upper, true));
+
+        // A plain word means the same in every form of `find`
+        let report = Path::new("report_2024.txt");
+        for args in [&["report"][..], &["--substr", "report"], &["--any", "report", "x"], &["--all", "report"]] {
+            assert!(find_matcher(args, false, false).unwrap().unwrap().matches(report, false), "{:?}", args);
+        }
+        assert!(find_matcher(&["*.txt"], false, false).unwrap().unwrap().matches(report, false));
+        assert!(find_matcher(&[], false, true).is_some());
+        assert!(find_matcher(&[], false, false).is_none());
+        assert!(find_matcher(&["--substr"], false, false).is_none());
+
+        let err = NameMatcher::glob("[unclosed").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("Invalid glob"));
+    }
+
+    #[test]
+    fn test_find_any_all() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for name in ["a.jpg", "b.png", "report.pdf", "report.txt", "other.pdf"] {
+            fs::write(root.join(name), b"").unwrap();
+        }
+        let names = |m: NameMatcher| {
+            let mut found: Vec<String> = find_matches(root, &m, false, &AttrFilter::default())
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            found.sort();
+            found
+        };
+
+        let any = NameMatcher::Any(NameMatcher::parse_list(&["\"*.jpg\"", "*.png"], false).unwrap());
+        assert_eq!(names(any), vec!["a.jpg", "b.png"]);
+
+        let all = NameMatcher::All(NameMatcher::parse_list(&["report", "*.pdf"], false).unwrap());
+        assert_eq!(names(all), vec!["report.pdf"]);
+
+        let mixed = NameMatcher::Any(NameMatcher::parse_list(&["-e", "^other", "*.png"], false).unwrap());
+        assert_eq!(names(mixed), vec!["b.png", "other.pdf"]);
+    }
+
+    #[test]
+    fn test_find_size_and_date_filters() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("small.mkv"), vec![0u8; 100]).unwrap();
+        fs::write(root.join("big.mkv"), vec![0u8; 3 * 1024]).unwrap();
+        fs::write(root.join("old.mkv"), vec![0u8; 3 * 1024]).unwrap();
+        let old = parse_date("2020-06-01").unwrap();
+        filetime::set_file_mtime(root.join("old.mkv"), filetime::FileTime::from_system_time(old)).unwrap();
+
+        assert_eq!(parse_size("10M"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("2k"), Some(2048));
+        assert_eq!(parse_size("12"), Some(12));
+        assert_eq!(parse_size("M"), None);
+
+        let all = NameMatcher::glob("*").unwrap();
+        let names = |filter: AttrFilter| {
+            let mut found: Vec<String> = find_matches(root, &all, false, &filter)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            found.sort();
+            found
+        };
+        let large = AttrFilter { min_size: parse_size("2K"), ..Default::default() };
+        assert_eq!(names(large), vec!["big.mkv", "old.mkv"]);
+        let recent = AttrFilter { newer: parse_date("2024-01-01"), ..large };
+        assert_eq!(names(recent), vec!["big.mkv"]);
+        let stale = AttrFilter { older: parse_date("2024-01-01"), ..Default::default() };
+        assert_eq!(names(stale), vec!["old.mkv"]);
+        let tiny = AttrFilter { max_size: Some(100), ..Default::default() };
+        assert_eq!(names(tiny), vec!["small.mkv"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owner_filter() {
+        let files = [("a.txt", 1000, 100), ("b.txt", 1001, 100), ("c.txt", 1000, 200), ("d.txt", 0, 0)];
+        let pick = |filter: AttrFilter| -> Vec<&str> {
+            files.iter().filter(|(_, uid, gid)| filter.matches_ids(*uid, *gid)).map(|f| f.0).collect()
+        };
+        let alice = AttrFilter { owner: Some(1000), ..Default::default() };
+        assert!(!alice.is_empty());
+        assert_eq!(pick(alice), vec!["a.txt", "c.txt"]);
+        assert_eq!(pick(AttrFilter { group: Some(100), ..alice }), vec!["a.txt"]);
+        assert_eq!(pick(AttrFilter { group: Some(100), ..Default::default() }), vec!["a.txt", "b.txt"]);
+
+        let mut args = vec!["find", "--owner", "1000", "--group", "100"];
+        let parsed = take_attr_filter(&mut args).unwrap();
+        assert_eq!((parsed.owner, parsed.group), (Some(1000), Some(100)));
+        assert_eq!(args, vec!["find"]);
+        assert!(take_attr_filter(&mut vec!["--owner", "no-such-user-here"]).is_err());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("mine.txt"), "x").unwrap();
+        let (uid, _) = owners::owner_ids(&fs::metadata(temp_dir.path().join("mine.txt")).unwrap()).unwrap();
+        let all = NameMatcher::glob("*").unwrap();
+        let mine = AttrFilter { owner: Some(uid), ..Default::default() };
+        assert_eq!(find_matches(temp_dir.path(), &all, false, &mine).unwrap().len(), 1);
+        let other = AttrFilter { owner: Some(uid.wrapping_add(1)), ..Default::default() };
+        assert!(find_matches(temp_dir.path(), &all, false, &other).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_files_regex() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("logs")).unwrap();
+        fs::write(root.join("logs/app-2024.log"), b"").unwrap();
+        fs::write(root.join("app-notes.txt"), b"").unwrap();
+
+        let by_name = search_files_regex(root, &Regex::new(r"^app-\d+\.log$").unwrap(), false).unwrap();
+        assert_eq!(by_name, vec![root.join("logs/app-2024.log")]);
+
+        let dir_only = Regex::new(r"logs/").unwrap();
+        assert!(search_files_regex(root, &dir_only, false).unwrap().is_empty());
+        assert_eq!(search_files_regex(root, &dir_only, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_first_lines_stops_early() {
+        let text = "one\ntwo\nthree\n";
+        assert_eq!(first_lines(text.as_bytes(), 2).unwrap(), vec!["one", "two"]);
+        assert_eq!(first_lines(text.as_bytes(), 10).unwrap(), vec!["one", "two", "three"]);
+        assert!(first_lines(text.as_bytes(), 0).unwrap().is_empty());
+
+        let mut reader = io::Cursor::new(b"ok\nrest\n".to_vec());
+        assert_eq!(first_lines(&mut reader, 1).unwrap(), vec!["ok"]);
+        assert_eq!(reader.position(), 3);
+    }
+
+    #[test]
+    fn test_last_lines() {
+        let lines = |text: &str, n| last_lines(&mut io::Cursor::new(text.as_bytes()), n).unwrap();
+        assert_eq!(lines("a\nb\nc\n", 2), vec!["b", "c"]);
+        assert_eq!(lines("a\nb\nc", 2), vec!["b", "c"]);
+        assert_eq!(lines("a\nb\n", 5), vec!["a", "b"]);
+        assert_eq!(lines("only", 1), vec!["only"]);
+        assert!(lines("", 3).is_empty());
+        assert!(lines("a\n", 0).is_empty());
+
+        // Spans several chunks: the answer must come from the end, not the first chunk read
+        let big: String = (0..5000).map(|i| format!("line {}\n", i)).collect();
+        assert_eq!(lines(&big, 3), vec!["line 4997", "line 4998", "line 4999"]);
+        assert_eq!(lines(&big, 5000).len(), 5000);
+        let long = format!("{}\nend\n", "x".repeat(8 * 1024 - 4));
+        assert_eq!(lines(&long, 2), vec!["x".repeat(8 * 1024 - 4), "end".to_string()]);
+    }
+
+    #[test]
+    fn test_append_rotating() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let log = temp_dir.path().join("sync.log");
+        let generation = |n: usize| temp_dir.path().join(format!("sync.log.{}", n));
+        for i in 0..7 {
+            append_rotating(&log, &format!("run {:02}\n", i), 10, 2).unwrap();
+        }
+        // Every second 7-byte line pushes the file past 10 bytes and rotates it
+        assert_eq!(fs::read_to_string(&log).unwrap(), "run 06\n");
+        assert_eq!(fs::read_to_string(generation(1)).unwrap(), "run 04\nrun 05\n");
+        assert_eq!(fs::read_to_string(generation(2)).unwrap(), "run 02\nrun 03\n");
+        assert!(!generation(3).exists());
+    }
+
+    #[test]
+    fn test_count_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let text = temp_dir.path().join("text.txt");
+        fs::write(&text, "the quick brown\nfox\u{2003}jumps\n\nover the dog\n").unwrap();
+        assert_eq!(count_file(&text).unwrap(), (4, 8, 42));
+
+        let trailing = temp_dir.path().join("trailing.txt");
+        fs::write(&trailing, "one two   \n  three \t").unwrap();
+        assert_eq!(count_file(&trailing).unwrap(), (1, 3, 20));
+    }
+
+    #[test]
+    fn test_grep_tree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {\n    todo!(\"later\");\n}\n").unwrap();
+        fs::write(root.join("notes.txt"), "nothing here\nTODO: case differs\nanother todo\n").unwrap();
+        fs::write(root.join("blob.bin"), b"todo\n\xff\xfe\x00todo\n").unwrap();
+
+        let mut matches = grep_tree(root, "todo").unwrap();
+        matches.sort();
+        assert_eq!(
+            matches,
+            vec![
+                (PathBuf::from("notes.txt"), 3, "another todo".to_string()),
+                (PathBuf::from("src/main.rs"), 2, "    todo!(\"later\");".to_string()),
+            ]
+        );
     }
 
     #[test]
@@ -392,6 +4118,131 @@ mod tests {
         let temp_dir = tempfile::tempdir().unwrap();
         let file = temp_dir.path().join("touch.txt");
         touch_file(&file).unwrap();
-        stat_file(&file).unwrap();
+        stat_file(&file, false).unwrap();
+
+        let mut out = Vec::new();
+        stat_file_to(&mut out, &file, false).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("Is directory: false\nSize: 0\n"), "{}", text);
+        let mut out = Vec::new();
+        stat_file_to(&mut out, &file, true).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["size"], 0);
+    }
+
+    #[test]
+    fn test_make_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let plain = temp_dir.path().join("plain");
+        make_dir(&plain, false).unwrap();
+        assert!(plain.is_dir());
+
+        let nested = temp_dir.path().join("a/b/c");
+        let err = make_dir(&nested, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains(&nested.display().to_string()));
+        make_dir(&nested, true).unwrap();
+        assert!(nested.is_dir());
+        assert!(make_dir(&plain, false).is_err());
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("docs")).unwrap();
+        fs::write(temp_dir.path().join("docs/readme.md"), "").unwrap();
+        fs::write(temp_dir.path().join("data.csv"), "").unwrap();
+        fs::write(temp_dir.path().join(".hidden"), "").unwrap();
+
+        let replacements = |token: &str| -> Vec<String> {
+            complete_path(temp_dir.path(), token).into_iter().map(|p| p.replacement).collect()
+        };
+        assert_eq!(replacements("d"), vec!["data.csv", "docs/"]);
+        assert_eq!(replacements("docs/r"), vec!["docs/readme.md"]);
+        assert_eq!(replacements(".h"), vec![".hidden"]);
+        assert!(replacements("missing/").is_empty());
+    }
+
+    #[test]
+    fn test_expand_wildcards() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cwd = temp_dir.path();
+        for name in ["a.txt", "b.txt", ".c.txt", "d.log"] {
+            fs::write(cwd.join(name), "").unwrap();
+        }
+        fs::create_dir(cwd.join("sub")).unwrap();
+        fs::write(cwd.join("sub/e.txt"), "").unwrap();
+
+        let sub_e = Path::new("sub").join("e.txt").display().to_string();
+        assert_eq!(expand_wildcards(cwd, &["-f", "*.txt", "backup/"]), vec!["-f", "a.txt", "b.txt", "backup/"]);
+        assert_eq!(expand_wildcards(cwd, &[".*.txt", "sub/*"]), vec![".c.txt".to_string(), sub_e]);
+        assert_eq!(expand_wildcards(cwd, &["*.none", "[x"]), vec!["*.none", "[x"]);
+    }
+
+    #[test]
+    fn test_run_command_navigation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let mut state = ExplorerState::new(&root).unwrap();
+
+        run_command(&mut state, &["mkdir", "sub"]).unwrap();
+        assert!(root.join("sub").is_dir());
+        run_command(&mut state, &["cd", "sub"]).unwrap();
+        assert_eq!(state.current_dir, root.join("sub"));
+        run_command(&mut state, &["cd", "-"]).unwrap();
+        assert_eq!(state.current_dir, root);
+        assert_eq!(state.previous_dir, Some(root.join("sub")));
+
+        run_command(&mut state, &["pushd", "sub"]).unwrap();
+        assert_eq!(state.dir_stack, vec![root.clone()]);
+        run_command(&mut state, &["popd"]).unwrap();
+        assert_eq!(state.current_dir, root);
+        assert!(state.dir_stack.is_empty());
+
+        assert!(run_command(&mut state, &["cat", "missing.txt"]).is_err());
+        assert!(!state.exit_requested);
+        run_command(&mut state, &["exit"]).unwrap();
+        assert!(state.exit_requested);
+    }
+
+    #[test]
+    fn test_run_script() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let script = root.join("commands.txt");
+        fs::write(&script, "# set up\nmkdir out\n\ncat missing.txt\nmkdir out/after\n").unwrap();
+
+        let mut state = ExplorerState::new(root).unwrap();
+        let err = run_script(&mut state, &script, false).unwrap_err();
+        assert!(err.to_string().contains(":4: cat missing.txt"), "{}", err);
+        assert!(root.join("out").is_dir());
+        assert!(!root.join("out/after").exists());
+
+        fs::remove_dir(root.join("out")).unwrap();
+        assert!(run_script(&mut state, &script, true).is_err());
+        assert!(root.join("out/after").is_dir());
+    }
+
+    #[test]
+    fn test_run_script_stops_on_failed_cd() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("keep")).unwrap();
+        fs::write(root.join("keep/important.txt"), "keep me").unwrap();
+        let script = root.join("cleanup.txt");
+        fs::write(&script, "cd nosuchdir\nrm -f keep/important.txt\n").unwrap();
+
+        let mut state = ExplorerState::new(root).unwrap();
+        let err = run_script(&mut state, &script, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains(":1: cd nosuchdir"), "{}", err);
+        assert!(root.join("keep/important.txt").exists());
+
+        fs::write(&script, "bogus-command\nrm -f keep/important.txt\n").unwrap();
+        let err = run_script(&mut state, &script, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains(":1: bogus-command"), "{}", err);
+        assert!(run_command(&mut state, &["mv", "only-one-arg"]).is_err());
+        assert!(root.join("keep/important.txt").exists());
     }
 }
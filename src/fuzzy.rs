@@ -0,0 +1,115 @@
+use std::io::{self, BufRead, Write};
+
+/// Number of ranked candidates shown per prompt in `pick`.
+const PICK_SHOWN: usize = 10;
+
+/// Scores `candidate` against a fuzzy `query`, or returns `None` if the query's characters
+/// do not all appear in order. Matches are case-insensitive; consecutive matches and matches
+/// at the start of a path segment or word score higher, and skipped characters cost a little.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut qi = 0;
+    let mut prev: Option<char> = None;
+    let mut last_match: Option<usize> = None;
+    for (i, c) in candidate.chars().enumerate() {
+        if qi == query.len() {
+            break;
+        }
+        if c.to_lowercase().eq(std::iter::once(query[qi])) {
+            score += 1;
+            if last_match.is_some_and(|m| m + 1 == i) {
+                score += 5;
+            } else if let Some(m) = last_match {
+                score -= (i - m - 1).min(5) as i64;
+            }
+            if prev.is_none_or(|p| matches!(p, '/' | '\\' | '.' | '_' | '-' | ' ')) {
+                score += 8;
+            }
+            last_match = Some(i);
+            qi += 1;
+        }
+        prev = Some(c);
+    }
+    (qi == query.len()).then_some(score)
+}
+
+/// Filters `candidates` down to those matching `query`, best score first.
+/// Ties are broken by shorter candidate, then alphabetically. Because a longer query only ever
+/// matches a subset, the result of one call can be fed back in as the candidates for the next.
+pub fn fuzzy_filter<'a>(query: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+    let mut scored: Vec<(i64, &str)> = candidates
+        .iter()
+        .filter_map(|c| fuzzy_score(query, c).map(|s| (s, *c)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())).then(a.1.cmp(b.1)));
+    scored.into_iter().map(|(_, c)| c).collect()
+}
+
+/// Interactively narrows `candidates` with a fuzzy query read line by line from `input`.
+/// Typed text extends the query, a number picks that entry, an empty line picks the top entry,
+/// `:clear` resets the query and `:q` cancels.
+pub fn pick<R: BufRead>(candidates: &[&str], input: &mut R) -> io::Result<Option<String>> {
+    let mut query = String::new();
+    let mut current: Vec<&str> = fuzzy_filter("", candidates);
+    loop {
+        for (i, c) in current.iter().take(PICK_SHOWN).enumerate() {
+            println!("[{}] {}", i, c);
+        }
+        println!("{} of {} candidates", current.len(), candidates.len());
+        print!("pick '{}'> ", query);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(current.first().map(|c| c.to_string()));
+        }
+        if line == ":q" {
+            return Ok(None);
+        }
+        if line == ":clear" {
+            query.clear();
+            current = fuzzy_filter("", candidates);
+            continue;
+        }
+        if let Ok(n) = line.parse::<usize>() {
+            match current.get(n) {
+                Some(c) => return Ok(Some(c.to_string())),
+                None => println!("No entry [{}]", n),
+            }
+            continue;
+        }
+        query.push_str(line);
+        current = fuzzy_filter(&query, &current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_ranking() {
+        // Every candidate matches "mnrs"; only the quality of the match differs.
+        let candidates = ["src/domain/mono_rust_script.txt", "src/domain.rs", "src/main.rs", "src/mono_rust_script.txt"];
+        assert!(candidates.iter().all(|c| fuzzy_score("mnrs", c).is_some()));
+        let ranked = fuzzy_filter("mnrs", &candidates);
+        assert_eq!(ranked, ["src/main.rs", "src/mono_rust_script.txt", "src/domain.rs", "src/domain/mono_rust_script.txt"]);
+        assert_eq!(fuzzy_score("MAIN", "src/main.rs"), fuzzy_score("main", "src/main.rs"));
+        assert_eq!(fuzzy_score("xyz", "src/main.rs"), None);
+    }
+
+    #[test]
+    fn test_pick_narrows_incrementally() {
+        let candidates = ["docs/manual.md", "src/main.rs", "src/lib.rs"];
+        let mut input = io::Cursor::new("ma\nrs\n\n");
+        assert_eq!(pick(&candidates, &mut input).unwrap().as_deref(), Some("src/main.rs"));
+
+        let mut input = io::Cursor::new("md\n:clear\n:q\n");
+        assert_eq!(pick(&candidates, &mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,309 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// Formats bytes as a lowercase hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Digest algorithms supported by `hash_file`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Md5,
+}
+
+impl HashAlgo {
+    pub fn parse(s: &str) -> Option<HashAlgo> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Some(HashAlgo::Sha256),
+            "md5" => Some(HashAlgo::Md5),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Md5 => "md5",
+        }
+    }
+}
+
+/// Feeds a reader through `D` in 8 KiB chunks and returns the hex digest.
+fn digest_reader<D: Digest, R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut hasher = D::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Computes the lowercase hex digest of a file with `algo`, streaming it in chunks.
+pub fn hash_file(path: &Path, algo: HashAlgo) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    match algo {
+        HashAlgo::Sha256 => digest_reader::<Sha256, _>(&mut file),
+        HashAlgo::Md5 => digest_reader::<Md5, _>(&mut file),
+    }
+}
+
+/// Computes the SHA-256 digest of a file, streaming it in chunks.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    hash_file(path, HashAlgo::Sha256)
+}
+
+/// Computes the SHA-256 digest of the first `len` bytes of a file, streaming it in chunks.
+pub fn sha256_prefix(path: &Path, len: u64) -> io::Result<String> {
+    digest_reader::<Sha256, _>(&mut File::open(path)?.take(len))
+}
+
+/// Copies `src` to `dst`, hashing the source as it is read, and returns its SHA-256 digest.
+/// With `verify`, the destination is read back and its digest must match the source's.
+pub fn copy_file_hashed(src: &Path, dst: &Path, verify: bool) -> io::Result<String> {
+    let mut src_file = File::open(src)?;
+    let mut dst_file = File::create(dst)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = src_file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        dst_file.write_all(&buffer[..n])?;
+    }
+    dst_file.sync_all()?;
+    fs::set_permissions(dst, fs::metadata(src)?.permissions())?;
+    let digest = to_hex(&hasher.finalize());
+    println!("source      sha256 {}", digest);
+
+    if verify {
+        let copied = sha256_file(dst)?;
+        println!("destination sha256 {}", copied);
+        if copied != digest {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Verification failed for {}: hashes differ", dst.display()),
+            ));
+        }
+        println!("Verified: hashes match.");
+    }
+    Ok(digest)
+}
+
+/// Default chunk size for `verify_chunks`.
+pub const DEFAULT_VERIFY_CHUNK: u64 = 1024 * 1024;
+
+/// A chunk whose hash differs between source and destination, as a byte range `start..end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkMismatch {
+    pub index: usize,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Per-chunk SHA-256 hashes of a source and destination file and the chunks that differ.
+#[derive(Debug)]
+pub struct ChunkReport {
+    pub chunk_size: u64,
+    pub src_len: u64,
+    pub dst_len: u64,
+    pub src_hashes: Vec<String>,
+    pub dst_hashes: Vec<String>,
+    pub mismatches: Vec<ChunkMismatch>,
+}
+
+impl ChunkReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.src_len == self.dst_len
+    }
+}
+
+/// Hashes a file in `chunk_size` pieces, returning the hex digests and the file length. Each
+/// piece is streamed through the hasher, so a large `chunk_size` costs no extra memory.
+fn chunk_hashes(path: &Path, chunk_size: u64) -> io::Result<(Vec<String>, u64)> {
+    let mut file = File::open(path)?;
+    let mut hashes = Vec::new();
+    let mut len = 0;
+    let mut buffer = [0u8; 8192];
+    loop {
+        let mut chunk = (&mut file).take(chunk_size);
+        let mut hasher = Sha256::new();
+        let mut filled = 0;
+        loop {
+            let n = chunk.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            filled += n as u64;
+        }
+        if filled == 0 {
+            break;
+        }
+        len += filled;
+        hashes.push(to_hex(&hasher.finalize()));
+        if filled < chunk_size {
+            break;
+        }
+    }
+    Ok((hashes, len))
+}
+
+/// Compares two files chunk by chunk, recording each chunk's hash and the byte ranges that
+/// differ, so a bad region can be re-copied on its own instead of the whole file.
+pub fn verify_chunks(src: &Path, dst: &Path, chunk_size: u64) -> io::Result<ChunkReport> {
+    if chunk_size == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Chunk size must be positive"));
+    }
+    let (src_hashes, src_len) = chunk_hashes(src, chunk_size)?;
+    let (dst_hashes, dst_len) = chunk_hashes(dst, chunk_size)?;
+    let total = src_len.max(dst_len);
+    let mismatches = (0..src_hashes.len().max(dst_hashes.len()))
+        .filter(|&i| src_hashes.get(i) != dst_hashes.get(i))
+        .map(|i| ChunkMismatch {
+            index: i,
+            start: (i as u64).saturating_mul(chunk_size),
+            end: (i as u64 + 1).saturating_mul(chunk_size).min(total),
+        })
+        .collect();
+    Ok(ChunkReport { chunk_size, src_len, dst_len, src_hashes, dst_hashes, mismatches })
+}
+
+/// Re-copies only the mismatched chunks from `src` into `dst` and fixes its length,
+/// returning the number of bytes rewritten.
+pub fn repair_chunks(src: &Path, dst: &Path, report: &ChunkReport) -> io::Result<u64> {
+    let mut src_file = File::open(src)?;
+    let mut dst_file = fs::OpenOptions::new().write(true).open(dst)?;
+    let mut written = 0;
+    for m in &report.mismatches {
+        if m.start >= report.src_len {
+            continue;
+        }
+        src_file.seek(SeekFrom::Start(m.start))?;
+        dst_file.seek(SeekFrom::Start(m.start))?;
+        written += io::copy(&mut (&mut src_file).take(m.end.min(report.src_len) - m.start), &mut dst_file)?;
+    }
+    dst_file.set_len(report.src_len)?;
+    dst_file.sync_all()?;
+    Ok(written)
+}
+
+/// Content hash and rolled-up size of a directory.
+#[derive(Debug, Clone)]
+pub struct DirHash {
+    pub path: PathBuf,
+    pub hash: String,
+    pub size: u64,
+    pub entries: usize,
+}
+
+/// Computes a hash of a directory's entire recursive content (names, types and file contents).
+/// Every directory visited is recorded in `out`, children before their parents.
+pub fn tree_hash(path: &Path, out: &mut Vec<DirHash>) -> io::Result<DirHash> {
+    let mut entries: Vec<_> = fs::read_dir(path)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    for entry in &entries {
+        let file_type = entry.file_type()?;
+        let (kind, digest) = if file_type.is_symlink() {
+            ("link", fs::read_link(entry.path())?.to_string_lossy().into_owned())
+        } else if file_type.is_dir() {
+            let child = tree_hash(&entry.path(), out)?;
+            size += child.size;
+            ("dir", child.hash)
+        } else {
+            size += entry.metadata()?.len();
+            ("file", sha256_file(&entry.path())?)
+        };
+        hasher.update(entry.file_name().to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(kind.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(digest.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    let dir_hash = DirHash {
+        path: path.to_path_buf(),
+        hash: to_hex(&hasher.finalize()),
+        size,
+        entries: entries.len(),
+    };
+    out.push(dir_hash.clone());
+    Ok(dir_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("abc.txt");
+        fs::write(&file, b"abc").unwrap();
+        assert_eq!(
+            sha256_file(&file).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash_file(&file, HashAlgo::Md5).unwrap(), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hash_file(&file, HashAlgo::default()).unwrap(), sha256_file(&file).unwrap());
+        assert_eq!(HashAlgo::parse("MD5"), Some(HashAlgo::Md5));
+        assert_eq!(HashAlgo::parse("crc32"), None);
+    }
+
+    #[test]
+    fn test_copy_file_hashed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src.bin");
+        let dst = temp_dir.path().join("dst.bin");
+        fs::write(&src, vec![7u8; 20_000]).unwrap();
+
+        let digest = copy_file_hashed(&src, &dst, true).unwrap();
+        assert_eq!(digest, sha256_file(&src).unwrap());
+        assert_eq!(fs::read(&dst).unwrap(), fs::read(&src).unwrap());
+    }
+
+    #[test]
+    fn test_verify_chunks_pinpoints_bad_chunk() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src.img");
+        let dst = temp_dir.path().join("dst.img");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data).unwrap();
+        let mut corrupt = data.clone();
+        corrupt[5_000] ^= 0xff;
+        fs::write(&dst, &corrupt).unwrap();
+
+        let report = verify_chunks(&src, &dst, 4096).unwrap();
+        assert_eq!(report.src_hashes.len(), 3);
+        assert_eq!(report.mismatches, vec![ChunkMismatch { index: 1, start: 4096, end: 8192 }]);
+        assert_eq!(report.src_hashes[0], report.dst_hashes[0]);
+        assert!(!report.is_ok());
+
+        assert_eq!(repair_chunks(&src, &dst, &report).unwrap(), 4096);
+        assert!(verify_chunks(&src, &dst, 4096).unwrap().is_ok());
+
+        fs::write(&dst, &data[..9_000]).unwrap();
+        let short = verify_chunks(&src, &dst, 4096).unwrap();
+        assert_eq!(short.mismatches, vec![ChunkMismatch { index: 2, start: 8192, end: 10_000 }]);
+
+        let huge = verify_chunks(&src, &dst, u64::MAX).unwrap();
+        assert_eq!(huge.src_hashes, vec![sha256_file(&src).unwrap()]);
+        assert_eq!(huge.mismatches, vec![ChunkMismatch { index: 0, start: 0, end: 10_000 }]);
+    }
+}
@@ -0,0 +1,27 @@
+//! Caching files to USB drives (with Rufus), OneDrive Personal and the local file explorer.
+//! The binary is a thin command-line front end over these modules.
+
+pub mod usb;
+pub mod onedrive;
+pub mod file_explorer;
+pub mod diff;
+pub mod transfer;
+pub mod hash;
+pub mod dupes;
+pub mod paths;
+pub mod sync;
+pub mod tags;
+pub mod manifest;
+pub mod dates;
+pub mod archive;
+pub mod color;
+pub mod fuzzy;
+pub mod options;
+pub mod session;
+pub mod stats;
+pub mod owners;
+pub mod template;
+pub mod attrs;
+pub mod error;
+#[cfg(feature = "net")]
+pub mod net;
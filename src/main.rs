@@ -1,15 +1,16 @@
-mod usb;
-mod onedrive;
-mod file_explorer;
-
 use std::env;
-use std::process::Command;
+use std::path::Path;
+use std::process::{self, Command};
+
+use ruforus_datrain::file_explorer::{run_command, run_script, ExplorerState};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
         eprintln!("Usage: datrain <usb|onedrive|explorer> [options]");
+        eprintln!("       datrain explorer <command> [args...]");
+        eprintln!("       datrain explorer --script <file> [--keep-going]");
         return;
     }
 
@@ -32,6 +33,32 @@ fn main() {
                 eprintln!("OneDrive sync operation failed");
             }
         }
+        "explorer" if args.get(2).map(String::as_str) == Some("--script") => {
+            // Run each line of a script file as an explorer command.
+            let Some(script) = args.get(3) else {
+                eprintln!("Usage: datrain explorer --script <file> [--keep-going]");
+                process::exit(2);
+            };
+            let keep_going = args[4..].iter().any(|a| a == "--keep-going");
+            let result = env::current_dir()
+                .and_then(|dir| ExplorerState::new(&dir))
+                .and_then(|mut state| run_script(&mut state, Path::new(script), keep_going));
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        "explorer" if args.len() > 2 => {
+            // Run a single explorer command non-interactively and exit.
+            let parts: Vec<&str> = args[2..].iter().map(String::as_str).collect();
+            let result = env::current_dir()
+                .and_then(|dir| ExplorerState::new(&dir))
+                .and_then(|mut state| run_command(&mut state, &parts));
+            if let Err(e) = result {
+                eprintln!("explorer {}: {}", args[2], e);
+                process::exit(1);
+            }
+        }
         "explorer" => {
             // Call the Perl script for file copy
             let status = Command::new("perl")
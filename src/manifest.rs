@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hash::sha256_file;
+use crate::paths::to_portable;
+
+/// One file or directory recorded in a manifest, keyed by its path relative to the scanned root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Content hash of a file, recorded by `Manifest::scan_hashed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl ManifestEntry {
+    fn from_metadata(path: String, metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(metadata.mode() & 0o7777), Some(metadata.uid()), Some(metadata.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        ManifestEntry {
+            path,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            mode,
+            uid,
+            gid,
+            sha256: None,
+        }
+    }
+}
+
+/// A snapshot of a directory tree.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Where the tree was when it was scanned; entry paths are relative to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Records every file and directory under `root`, sorted by path.
+    pub fn scan(root: &Path) -> io::Result<Manifest> {
+        let mut entries = Vec::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(root.to_path_buf());
+        while let Some(dir) = queue.pop_front() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                let relative = to_portable(entry.path().strip_prefix(root).unwrap());
+                if metadata.is_dir() {
+                    queue.push_back(entry.path());
+                }
+                entries.push(ManifestEntry::from_metadata(relative, &metadata));
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Manifest { root: Some(root.to_path_buf()), entries })
+    }
+
+    /// Like `scan`, also recording the SHA-256 of every file so `verify_manifest` can check content.
+    pub fn scan_hashed(root: &Path) -> io::Result<Manifest> {
+        let mut manifest = Manifest::scan(root)?;
+        for entry in manifest.entries.iter_mut().filter(|e| !e.is_dir) {
+            entry.sha256 = Some(sha256_file(&root.join(&entry.path))?);
+        }
+        Ok(manifest)
+    }
+
+    /// Drops the entry for a path, e.g. the manifest file itself when saved inside the tree.
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|e| e.path != path);
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A file that no longer matches its manifest entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestMismatch {
+    Missing(String),
+    Size { path: String, expected: u64, actual: u64 },
+    Hash { path: String, expected: String, actual: String },
+}
+
+impl std::fmt::Display for ManifestMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManifestMismatch::Missing(path) => write!(f, "missing  {}", path),
+            ManifestMismatch::Size { path, expected, actual } => {
+                write!(f, "size {} -> {}  {}", expected, actual, path)
+            }
+            ManifestMismatch::Hash { path, expected, actual } => {
+                write!(f, "sha256 {} -> {}  {}", expected, actual, path)
+            }
+        }
+    }
+}
+
+/// Checks that every file in `manifest` still exists with the same size and, when recorded,
+/// the same hash. The tree is looked up under `new_root` if given, so a manifest made before a
+/// move can validate the relocated tree; otherwise under the root it was scanned at.
+pub fn verify_manifest(manifest: &Manifest, new_root: Option<&Path>) -> io::Result<Vec<ManifestMismatch>> {
+    let root = new_root.or(manifest.root.as_deref()).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Manifest has no root; give the tree's location")
+    })?;
+    let mut mismatches = Vec::new();
+    for entry in manifest.entries.iter().filter(|e| !e.is_dir) {
+        let path = root.join(&entry.path);
+        let Ok(metadata) = fs::metadata(&path) else {
+            mismatches.push(ManifestMismatch::Missing(entry.path.clone()));
+            continue;
+        };
+        if metadata.len() != entry.size {
+            mismatches.push(ManifestMismatch::Size {
+                path: entry.path.clone(),
+                expected: entry.size,
+                actual: metadata.len(),
+            });
+            continue;
+        }
+        if let Some(expected) = &entry.sha256 {
+            let actual = sha256_file(&path)?;
+            if actual != *expected {
+                mismatches.push(ManifestMismatch::Hash { path: entry.path.clone(), expected: expected.clone(), actual });
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// A permission or ownership difference between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermChange {
+    Mode { path: String, old: u32, new: u32 },
+    Owner { path: String, old: (u32, u32), new: (u32, u32) },
+    Added(String),
+    Removed(String),
+}
+
+impl std::fmt::Display for PermChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PermChange::Mode { path, old, new } => write!(f, "mode  {:o} -> {:o}  {}", old, new, path),
+            PermChange::Owner { path, old, new } => {
+                write!(f, "owner {}:{} -> {}:{}  {}", old.0, old.1, new.0, new.1, path)
+            }
+            PermChange::Added(path) => write!(f, "added  {}", path),
+            PermChange::Removed(path) => write!(f, "removed  {}", path),
+        }
+    }
+}
+
+/// Compares the permission fields of two snapshots of the same tree.
+pub fn diff_permissions(old: &Manifest, new: &Manifest) -> Vec<PermChange> {
+    let mut current: BTreeMap<&str, &ManifestEntry> = new.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut changes = Vec::new();
+    for before in &old.entries {
+        let Some(after) = current.remove(before.path.as_str()) else {
+            changes.push(PermChange::Removed(before.path.clone()));
+            continue;
+        };
+        if let (Some(old_mode), Some(new_mode)) = (before.mode, after.mode) {
+            if old_mode != new_mode {
+                changes.push(PermChange::Mode { path: before.path.clone(), old: old_mode, new: new_mode });
+            }
+        }
+        if let (Some(ou), Some(og), Some(nu), Some(ng)) = (before.uid, before.gid, after.uid, after.gid) {
+            if (ou, og) != (nu, ng) {
+                changes.push(PermChange::Owner { path: before.path.clone(), old: (ou, og), new: (nu, ng) });
+            }
+        }
+    }
+    changes.extend(current.into_keys().map(|p| PermChange::Added(p.to_string())));
+    changes
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_perms_diff_reports_mode_change() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("tree");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        fs::write(root.join("sub/b.txt"), b"b").unwrap();
+        fs::set_permissions(root.join("sub/b.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let snapshot_file = temp_dir.path().join("perms.json");
+        Manifest::scan(&root).unwrap().save(&snapshot_file).unwrap();
+
+        fs::set_permissions(root.join("sub/b.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        let old = Manifest::load(&snapshot_file).unwrap();
+        let new = Manifest::scan(&root).unwrap();
+        assert_eq!(
+            diff_permissions(&old, &new),
+            vec![PermChange::Mode { path: "sub/b.txt".into(), old: 0o644, new: 0o600 }]
+        );
+    }
+
+    #[test]
+    fn test_verify_manifest_after_move() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("backup");
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::write(root.join("a.txt"), b"alpha").unwrap();
+        fs::write(root.join("photos/b.jpg"), b"jpeg").unwrap();
+        let manifest_file = temp_dir.path().join("backup.json");
+        Manifest::scan_hashed(&root).unwrap().save(&manifest_file).unwrap();
+
+        let moved = temp_dir.path().join("moved/backup");
+        fs::create_dir_all(moved.parent().unwrap()).unwrap();
+        fs::rename(&root, &moved).unwrap();
+        let manifest = Manifest::load(&manifest_file).unwrap();
+        assert_eq!(manifest.root.as_deref(), Some(root.as_path()));
+        assert_eq!(verify_manifest(&manifest, None).unwrap().len(), 2);
+        assert!(verify_manifest(&manifest, Some(&moved)).unwrap().is_empty());
+
+        fs::write(moved.join("photos/b.jpg"), b"JPEG").unwrap();
+        let mismatches = verify_manifest(&manifest, Some(&moved)).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(matches!(&mismatches[0], ManifestMismatch::Hash { path, .. } if path == "photos/b.jpg"));
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::hash::sha256_file;
+
+/// Number of attempts made before a download is given up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Streams an HTTP(S) download to `dest`, calling `cb(downloaded, total)` as data arrives.
+/// An existing partial `dest` (or a failed attempt) is resumed with a `Range` request.
+/// When `expected_sha256` is given, the finished file is verified against it and removed on a
+/// mismatch, so the next call starts over instead of resuming a corrupt file.
+pub fn download(
+    url: &str,
+    dest: &Path,
+    expected_sha256: Option<&str>,
+    cb: &mut dyn FnMut(u64, Option<u64>),
+) -> io::Result<u64> {
+    let mut attempt = 1;
+    loop {
+        match download_attempt(url, dest, cb) {
+            Ok(()) => break,
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                eprintln!("Download interrupted ({}), retrying", e);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(expected) = expected_sha256 {
+        let actual = sha256_file(dest)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            fs::remove_file(dest)?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Checksum mismatch: expected {}, got {}", expected, actual),
+            ));
+        }
+    }
+    Ok(fs::metadata(dest)?.len())
+}
+
+/// Performs a single request, resuming from the current length of `dest`.
+fn download_attempt(url: &str, dest: &Path, cb: &mut dyn FnMut(u64, Option<u64>)) -> io::Result<()> {
+    let offset = fs::metadata(dest).map(|m| m.len()).unwrap_or(0);
+    let mut request = ureq::get(url);
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={}-", offset));
+    }
+    let response = match request.call() {
+        Ok(r) => r,
+        // The partial file is already complete
+        Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(()),
+        Err(e) => return Err(io::Error::other(e.to_string())),
+    };
+
+    let resumed = response.status() == 206;
+    let length: Option<u64> = response.header("Content-Length").and_then(|v| v.parse().ok());
+    let mut downloaded = if resumed { offset } else { 0 };
+    let total = length.map(|l| l + downloaded);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(dest)?;
+    let mut reader = response.into_reader();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buffer[..n])?;
+        downloaded += n as u64;
+        cb(downloaded, total);
+    }
+    file.flush()?;
+    if let Some(total) = total {
+        if downloaded < total {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Connection closed after {} of {} bytes", downloaded, total),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::to_hex;
+    use sha2::{Digest, Sha256};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves `body` to `requests` connections, honoring simple `Range: bytes=N-` headers.
+    fn serve(body: &'static [u8], requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut start = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(range) = line.strip_prefix("range: bytes=").or(line.strip_prefix("Range: bytes=")) {
+                        start = range.trim().trim_end_matches('-').parse().unwrap();
+                    }
+                }
+                let status = if start > 0 { "206 Partial Content" } else { "200 OK" };
+                let part = &body[start..];
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    part.len()
+                )
+                .unwrap();
+                stream.write_all(part).unwrap();
+            }
+        });
+        format!("http://{}/image.iso", addr)
+    }
+
+    #[test]
+    fn test_download_writes_and_verifies() {
+        let body: &'static [u8] = b"pretend this is a bootable image";
+        let url = serve(body, 1);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dest = temp_dir.path().join("image.iso");
+        let expected = to_hex(&Sha256::digest(body));
+
+        let mut last = (0, None);
+        let n = download(&url, &dest, Some(&expected), &mut |d, t| last = (d, t)).unwrap();
+        assert_eq!(n, body.len() as u64);
+        assert_eq!(fs::read(&dest).unwrap(), body);
+        assert_eq!(last, (body.len() as u64, Some(body.len() as u64)));
+    }
+
+    #[test]
+    fn test_download_resumes_partial_file() {
+        let body: &'static [u8] = b"0123456789abcdef";
+        let url = serve(body, 1);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dest = temp_dir.path().join("image.iso");
+        fs::write(&dest, &body[..6]).unwrap();
+
+        download(&url, &dest, None, &mut |_, _| {}).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), body);
+    }
+
+    #[test]
+    fn test_download_rejects_bad_checksum() {
+        let url = serve(b"data", 2);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dest = temp_dir.path().join("image.iso");
+        let err = download(&url, &dest, Some("00"), &mut |_, _| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!dest.exists());
+
+        let expected = to_hex(&Sha256::digest(b"data"));
+        assert_eq!(download(&url, &dest, Some(&expected), &mut |_, _| {}).unwrap(), 4);
+        assert_eq!(fs::read(&dest).unwrap(), b"data");
+    }
+}
@@ -1,8 +1,13 @@
+use std::collections::{HashSet, VecDeque};
 use std::env;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 
+use crate::error::RuError;
+use crate::file_explorer::walk_files;
+use crate::sync::plan_sync;
+
 /// Attempts to find the user's OneDrive Personal folder on Windows.
 fn get_onedrive_path() -> Option<PathBuf> {
     // Check the environment variable provided by OneDrive on Windows
@@ -23,54 +28,437 @@ fn get_onedrive_path() -> Option<PathBuf> {
     None
 }
 
-/// Caches files from the provided source directory to the user's OneDrive Personal directory.
-pub fn cache_to_onedrive() {
-    let source_dir = "./cache_to_onedrive"; // You can change this as needed
+/// Cloud storage quota of the signed-in OneDrive account, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+}
 
-    let onedrive_path = match get_onedrive_path() {
-        Some(path) => path,
-        None => {
-            eprintln!("Could not locate OneDrive Personal directory. Is OneDrive installed and set up?");
-            return;
-        }
+/// Registry key where the OneDrive client keeps the personal account's state.
+#[cfg(windows)]
+const ACCOUNT_KEY: &str = r"HKCU\Software\Microsoft\OneDrive\Accounts\Personal";
+
+/// Reads the account quota the OneDrive client last recorded, if it exposes one.
+/// Returns `None` when the client isn't installed, isn't signed in or doesn't store a quota.
+#[cfg(windows)]
+pub fn quota() -> Option<Quota> {
+    let output = std::process::Command::new("reg").args(["query", ACCOUNT_KEY]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_quota(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The OneDrive client only records its quota on Windows.
+#[cfg(not(windows))]
+pub fn quota() -> Option<Quota> {
+    None
+}
+
+/// Parses `reg query` output for the `QuotaTotal` and `QuotaUsed` values (and
+/// `QuotaRemaining` when present), which may be decimal or `0x` hex numbers.
+#[cfg(any(windows, test))]
+fn parse_quota(text: &str) -> Option<Quota> {
+    let value = |name: &str| {
+        text.lines().find_map(|line| {
+            let mut fields = line.split_whitespace();
+            if fields.next()? != name {
+                return None;
+            }
+            let raw = fields.nth(1)?;
+            match raw.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => raw.parse().ok(),
+            }
+        })
     };
+    let total = value("QuotaTotal")?;
+    let used = value("QuotaUsed")?;
+    let available = value("QuotaRemaining").unwrap_or(total.saturating_sub(used));
+    Some(Quota { total, used, available })
+}
 
-    let src = Path::new(source_dir);
-    if !src.exists() || !src.is_dir() {
-        eprintln!(
-            "Source directory '{}' does not exist. Place files to sync to OneDrive here.",
-            source_dir
-        );
-        return;
+/// Outcome of comparing planned upload bytes with the cloud quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaCheck {
+    Fits,
+    Exceeds { needed: u64, available: u64 },
+    /// The quota couldn't be read, so the upload may or may not fit.
+    Unknown,
+}
+
+/// Checks whether `planned` bytes fit in the remaining quota.
+pub fn check_quota(planned: u64, quota: Option<Quota>) -> QuotaCheck {
+    match quota {
+        Some(q) if planned > q.available => QuotaCheck::Exceeds { needed: planned, available: q.available },
+        Some(_) => QuotaCheck::Fits,
+        None => QuotaCheck::Unknown,
+    }
+}
+
+/// Folder `cache_to_onedrive` was historically pointed at, cached by `cache_to_onedrive_default`.
+pub const DEFAULT_CACHE_DIR: &str = "./cache_to_onedrive";
+
+/// Files a cache run copied and deleted, and the files and directories it couldn't, relative to
+/// the source root. `cache_to_onedrive` puts that root at `OneDrive/<source folder name>` in
+/// both modes, so the paths are also relative to that folder.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub copied: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, io::Error)>,
+    /// Directories that couldn't be read or created; their contents were skipped.
+    pub failed_dirs: Vec<(PathBuf, io::Error)>,
+    /// Destination files removed by a mirror because the source no longer has them.
+    pub deleted: Vec<PathBuf>,
+}
+
+/// Largest share of destination files, in percent, a mirror may delete without `force`.
+pub const DEFAULT_MAX_DELETE_PERCENT: u32 = 20;
+
+/// What a cache run does with destination files that aren't in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Leave them alone.
+    Add,
+    /// Delete them, refusing if more than `max_delete_percent` of the destination's files would
+    /// go unless `force` is set, so a mistakenly empty source can't wipe a backup.
+    Mirror { max_delete_percent: u32, force: bool },
+}
+
+/// Caches `source_dir` and everything below it to the user's OneDrive Personal directory,
+/// recreating its folders. The upload is refused if it is known not to fit the remaining quota.
+/// Files and directories that fail are recorded in the report and don't stop the rest.
+/// Both modes write into a OneDrive folder named after the source, so adding and mirroring the
+/// same source share one copy and a mirror's deletions stay inside it.
+pub fn cache_to_onedrive(source_dir: &Path, mode: CacheMode) -> Result<SyncReport, RuError> {
+    let mut onedrive_path = get_onedrive_path().ok_or(RuError::OneDriveNotFound)?;
+    let name = source_dir
+        .canonicalize()?
+        .file_name()
+        .map(|n| n.to_os_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Source has no folder name"))?;
+    onedrive_path.push(name);
+    let report = cache_dir(source_dir, &onedrive_path, mode)?;
+    println!(
+        "Sync to OneDrive requested. OneDrive client will upload files automatically if running."
+    );
+    Ok(report)
+}
+
+/// Caches `DEFAULT_CACHE_DIR`, adding files only, as `cache_to_onedrive` did before it took a
+/// source folder. The files land in `OneDrive/cache_to_onedrive`.
+pub fn cache_to_onedrive_default() -> Result<SyncReport, RuError> {
+    cache_to_onedrive(Path::new(DEFAULT_CACHE_DIR), CacheMode::Add)
+}
+
+/// Copies the tree under `source_dir` into `dest`, keeping relative paths, after checking the
+/// bytes it adds against the OneDrive quota. In mirror mode the planned deletions are printed before
+/// anything is copied, and carried out after.
+pub fn cache_dir(source_dir: &Path, dest: &Path, mode: CacheMode) -> Result<SyncReport, RuError> {
+    if !source_dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Source directory '{}' does not exist", source_dir.display()),
+        )
+        .into());
     }
 
-    // Iterate files in the source directory and copy them to OneDrive
-    let entries = match fs::read_dir(src) {
-        Ok(entries) => entries,
-        Err(e) => {
-            eprintln!("Failed to read source directory: {}", e);
-            return;
+    let mut report = SyncReport::default();
+    let SourceTree { dirs, files } = collect_tree(source_dir, &mut report.failed_dirs)?;
+    let extra = match mode {
+        CacheMode::Add => Vec::new(),
+        CacheMode::Mirror { max_delete_percent, force } => {
+            let existing = walk_files(dest)?;
+            let extra = mirror_extras(&existing, &files, &report.failed_dirs);
+            for path in &extra {
+                println!("Will delete {}", path.display());
+            }
+            if !force && extra.len() as u64 * 100 > max_delete_percent as u64 * existing.len() as u64 {
+                return Err(io::Error::other(format!(
+                    "Mirror would delete {} of {} destination files (limit {}%); use --force to proceed",
+                    extra.len(),
+                    existing.len(),
+                    max_delete_percent
+                ))
+                .into());
+            }
+            extra
         }
     };
+    match check_quota(planned_growth(source_dir, dest, &files), quota()) {
+        QuotaCheck::Fits => {}
+        QuotaCheck::Exceeds { needed, available } => {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!("{} bytes planned but only {} bytes of OneDrive quota remain", needed, available),
+            )
+            .into());
+        }
+        QuotaCheck::Unknown => {
+            eprintln!("Warning: OneDrive quota unknown; only local disk space is guaranteed.");
+        }
+    }
 
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let file_type = match entry.file_type() {
-                Ok(ft) => ft,
-                Err(_) => continue,
-            };
-            if file_type.is_file() {
-                let file_name = entry.file_name();
-                let dest_path = onedrive_path.join(&file_name);
-                match fs::copy(entry.path(), &dest_path) {
-                    Ok(_) => println!("Copied {:?} to {:?}", entry.path(), dest_path),
-                    Err(e) => eprintln!("Failed to copy {:?}: {}", entry.path(), e),
+    fs::create_dir_all(dest)?;
+    let mut skipped = Vec::new();
+    for relative in dirs {
+        if skipped.iter().any(|s| relative.starts_with(s)) {
+            continue;
+        }
+        if let Err(e) = fs::create_dir_all(dest.join(&relative)) {
+            eprintln!("Failed to create {:?}: {}", dest.join(&relative), e);
+            skipped.push(relative.clone());
+            report.failed_dirs.push((relative, e));
+        }
+    }
+    for (relative, _) in files {
+        if skipped.iter().any(|s| relative.starts_with(s)) {
+            continue;
+        }
+        let (from, to) = (source_dir.join(&relative), dest.join(&relative));
+        match fs::copy(&from, &to) {
+            Ok(_) => {
+                println!("Copied {:?} to {:?}", from, to);
+                report.copied.push(relative);
+            }
+            Err(e) => {
+                eprintln!("Failed to copy {:?}: {}", from, e);
+                report.failed.push((relative, e));
+            }
+        }
+    }
+    for relative in extra {
+        let path = dest.join(&relative);
+        if let Err(e) = fs::remove_file(&path) {
+            eprintln!("Failed to delete {:?}: {}", path, e);
+            report.failed.push((relative, e));
+            continue;
+        }
+        // Prune directories the deletion left empty; `remove_dir` fails on the first non-empty one.
+        for dir in path.ancestors().skip(1).take_while(|d| *d != dest) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+        report.deleted.push(relative);
+    }
+    Ok(report)
+}
+
+/// How much a cache run grows `dest`: the bytes `plan_sync` would copy less the sizes of the
+/// destination files they replace, so re-caching files already there costs nothing. If the
+/// source can't be planned (an unreadable folder), every source file is counted.
+fn planned_growth(source_dir: &Path, dest: &Path, files: &[(PathBuf, u64)]) -> u64 {
+    let Ok(plan) = plan_sync(source_dir, dest) else {
+        return files.iter().map(|(_, size)| size).sum();
+    };
+    let replaced: u64 = plan
+        .copies
+        .iter()
+        .filter_map(|entry| fs::metadata(dest.join(&entry.relative)).ok())
+        .map(|meta| meta.len())
+        .sum();
+    plan.bytes_to_copy().saturating_sub(replaced)
+}
+
+/// Destination files a mirror should delete: those missing from the source, except under source
+/// folders that couldn't be read, whose files are unknown rather than gone.
+fn mirror_extras(
+    existing: &[(PathBuf, fs::Metadata)],
+    source_files: &[(PathBuf, u64)],
+    unread_dirs: &[(PathBuf, io::Error)],
+) -> Vec<PathBuf> {
+    let source_files: HashSet<&PathBuf> = source_files.iter().map(|(relative, _)| relative).collect();
+    existing
+        .iter()
+        .map(|(relative, _)| relative.clone())
+        .filter(|relative| !source_files.contains(relative))
+        .filter(|relative| !unread_dirs.iter().any(|(dir, _)| relative.starts_with(dir)))
+        .collect()
+}
+
+/// Subdirectories (parents first) and files with their sizes, relative to a source root.
+struct SourceTree {
+    dirs: Vec<PathBuf>,
+    files: Vec<(PathBuf, u64)>,
+}
+
+/// Walks `root` without following symlinks. Subdirectories that can't be read are added to
+/// `failures` and skipped; only an unreadable `root` is an error.
+fn collect_tree(root: &Path, failures: &mut Vec<(PathBuf, io::Error)>) -> io::Result<SourceTree> {
+    let (mut dirs, mut files) = (Vec::new(), Vec::new());
+    let mut queue = VecDeque::from([PathBuf::new()]);
+    while let Some(relative) = queue.pop_front() {
+        let entries = match fs::read_dir(root.join(&relative)) {
+            Ok(entries) => entries,
+            Err(e) if relative.as_os_str().is_empty() => return Err(e),
+            Err(e) => {
+                eprintln!("Failed to read {:?}: {}", root.join(&relative), e);
+                failures.push((relative, e));
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = relative.join(entry.file_name());
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => {
+                    dirs.push(path.clone());
+                    queue.push_back(path);
                 }
+                Ok(t) if t.is_file() => files.push((path, entry.metadata().map(|m| m.len()).unwrap_or(0))),
+                _ => {}
             }
         }
     }
+    Ok(SourceTree { dirs, files })
+}
 
-    println!(
-        "Sync to OneDrive requested. OneDrive client will upload files automatically if running."
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quota_and_check_plan() {
+        let sample = "\r
+HKEY_CURRENT_USER\\Software\\Microsoft\\OneDrive\\Accounts\\Personal\r
+    UserFolder    REG_SZ    C:\\Users\\me\\OneDrive\r
+    QuotaTotal    REG_QWORD    0x140000000\r
+    QuotaUsed    REG_QWORD    0x100000000\r
+";
+        let quota = parse_quota(sample).unwrap();
+        assert_eq!(quota, Quota { total: 5 << 30, used: 4 << 30, available: 1 << 30 });
+
+        assert_eq!(check_quota(512 << 20, Some(quota)), QuotaCheck::Fits);
+        assert_eq!(
+            check_quota(2 << 30, Some(quota)),
+            QuotaCheck::Exceeds { needed: 2 << 30, available: 1 << 30 }
+        );
+        assert_eq!(check_quota(1, None), QuotaCheck::Unknown);
+        assert_eq!(parse_quota("    UserFolder    REG_SZ    C:\\x"), None);
+    }
+
+    #[test]
+    fn test_cache_dir_reports_copies_and_failures() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (temp_dir.path().join("src"), temp_dir.path().join("dst"));
+        fs::create_dir_all(src.join("photos/2024")).unwrap();
+        fs::create_dir_all(src.join("notes")).unwrap();
+        fs::create_dir_all(dst.join("b.txt")).unwrap();
+        fs::write(dst.join("notes"), "a file where a folder should go").unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("b.txt"), "b").unwrap();
+        fs::write(src.join("photos/2024/c.jpg"), "c").unwrap();
+        fs::write(src.join("notes/d.txt"), "d").unwrap();
+
+        let mut report = cache_dir(&src, &dst, CacheMode::Add).unwrap();
+        report.copied.sort();
+        assert_eq!(report.copied, vec![PathBuf::from("a.txt"), PathBuf::from("photos/2024/c.jpg")]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, PathBuf::from("b.txt"));
+        assert_eq!(report.failed_dirs.len(), 1);
+        assert_eq!(report.failed_dirs[0].0, PathBuf::from("notes"));
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dst.join("photos/2024/c.jpg")).unwrap(), "c");
+        assert!(cache_dir(&temp_dir.path().join("missing"), &dst, CacheMode::Add).is_err());
+    }
+
+    #[test]
+    fn test_quota_counts_only_growth() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (temp_dir.path().join("src"), temp_dir.path().join("dst"));
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("big.bin"), vec![0u8; 1000]).unwrap();
+        fs::write(src.join("sub/small.txt"), "small").unwrap();
+        let files = collect_tree(&src, &mut Vec::new()).unwrap().files;
+        assert_eq!(planned_growth(&src, &dst, &files), 1005);
+
+        cache_dir(&src, &dst, CacheMode::Add).unwrap();
+        assert_eq!(planned_growth(&src, &dst, &files), 0);
+
+        fs::write(src.join("sub/small.txt"), "not so small").unwrap();
+        fs::write(src.join("new.txt"), "new").unwrap();
+        let files = collect_tree(&src, &mut Vec::new()).unwrap().files;
+        assert_eq!(planned_growth(&src, &dst, &files), 7 + 3);
+    }
+
+    #[test]
+    fn test_mirror_propagates_deletions_within_limit() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (temp_dir.path().join("src"), temp_dir.path().join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        for i in 0..5 {
+            fs::write(src.join(format!("{}.txt", i)), i.to_string()).unwrap();
+        }
+        let mirror = CacheMode::Mirror { max_delete_percent: DEFAULT_MAX_DELETE_PERCENT, force: false };
+        cache_dir(&src, &dst, mirror).unwrap();
+        fs::create_dir_all(dst.join("old")).unwrap();
+        fs::write(dst.join("old/stale.txt"), "stale").unwrap();
+
+        let report = cache_dir(&src, &dst, mirror).unwrap();
+        assert_eq!(report.copied.len(), 5);
+        assert_eq!(report.deleted, vec![PathBuf::from("old/stale.txt")]);
+        assert!(!dst.join("old").exists());
+        assert_eq!(fs::read_dir(&dst).unwrap().count(), 5);
+
+        // Without mirror mode nothing is deleted.
+        fs::write(dst.join("extra.txt"), "extra").unwrap();
+        assert!(cache_dir(&src, &dst, CacheMode::Add).unwrap().deleted.is_empty());
+        assert!(dst.join("extra.txt").exists());
+    }
+
+    #[test]
+    fn test_mirror_keeps_files_under_unreadable_source_dirs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (temp_dir.path().join("src"), temp_dir.path().join("dst"));
+        fs::create_dir_all(src.join("locked")).unwrap();
+        for i in 0..10 {
+            fs::write(src.join(format!("{}.txt", i)), i.to_string()).unwrap();
+        }
+        fs::write(src.join("locked/kept.txt"), "kept").unwrap();
+        let mirror = CacheMode::Mirror { max_delete_percent: DEFAULT_MAX_DELETE_PERCENT, force: false };
+        cache_dir(&src, &dst, mirror).unwrap();
+        fs::write(dst.join("stale.txt"), "stale").unwrap();
+
+        let existing = walk_files(&dst).unwrap();
+        let source: Vec<(PathBuf, u64)> = (0..10).map(|i| (PathBuf::from(format!("{}.txt", i)), 1)).collect();
+        let unread = vec![(PathBuf::from("locked"), io::Error::from(io::ErrorKind::PermissionDenied))];
+        assert_eq!(mirror_extras(&existing, &source, &unread), vec![PathBuf::from("stale.txt")]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(src.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+            // Running as root ignores directory permissions, so only check when they apply.
+            if fs::read_dir(src.join("locked")).is_err() {
+                let report = cache_dir(&src, &dst, mirror).unwrap();
+                assert_eq!(report.deleted, vec![PathBuf::from("stale.txt")]);
+                assert_eq!(report.failed_dirs.len(), 1);
+                assert!(dst.join("locked/kept.txt").exists());
+            }
+            fs::set_permissions(src.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mirror_blocks_mass_deletion_without_force() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (temp_dir.path().join("src"), temp_dir.path().join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        for i in 0..4 {
+            fs::write(dst.join(format!("{}.txt", i)), "backup").unwrap();
+        }
+
+        let mirror = CacheMode::Mirror { max_delete_percent: DEFAULT_MAX_DELETE_PERCENT, force: false };
+        let err = cache_dir(&src, &dst, mirror).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(fs::read_dir(&dst).unwrap().count(), 4);
+
+        let forced = CacheMode::Mirror { max_delete_percent: DEFAULT_MAX_DELETE_PERCENT, force: true };
+        let report = cache_dir(&src, &dst, forced).unwrap();
+        assert_eq!(report.deleted.len(), 4);
+        assert_eq!(fs::read_dir(&dst).unwrap().count(), 0);
+    }
 }
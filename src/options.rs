@@ -0,0 +1,237 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::file_explorer::SymlinkPolicy;
+
+/// Name of the optional per-directory file holding default copy options.
+pub const OPTIONS_FILE: &str = ".ruforus_options.json";
+
+/// What a copy does when a destination file already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    /// Ask for each existing file.
+    Prompt,
+}
+
+impl OverwritePolicy {
+    pub fn parse(s: &str) -> Option<OverwritePolicy> {
+        match s {
+            "overwrite" => Some(OverwritePolicy::Overwrite),
+            "skip" => Some(OverwritePolicy::Skip),
+            "prompt" => Some(OverwritePolicy::Prompt),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OverwritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::Skip => "skip",
+            OverwritePolicy::Prompt => "prompt",
+        })
+    }
+}
+
+/// Accepts a policy name, or `true`/`false` for `overwrite`/`skip` as older options files use.
+impl<'de> Deserialize<'de> for OverwritePolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Name(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(true) => Ok(OverwritePolicy::Overwrite),
+            Raw::Bool(false) => Ok(OverwritePolicy::Skip),
+            Raw::Name(name) => OverwritePolicy::parse(&name).ok_or_else(|| {
+                serde::de::Error::custom(format!("unknown overwrite policy '{}', expected overwrite, skip or prompt", name))
+            }),
+        }
+    }
+}
+
+/// Copy options after merging command-line flags, the options file and built-in defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Compare each destination file with its source after copying.
+    pub verify: bool,
+    /// Whether existing destination files are replaced, skipped or asked about.
+    pub overwrite: OverwritePolicy,
+    /// Copy access and modification times from the source; on by default, as with `copy_file`.
+    pub preserve_times: bool,
+    /// Echo the effective options and each copied file.
+    pub verbose: bool,
+    /// What a directory copy does with the symbolic links inside it.
+    pub symlinks: SymlinkPolicy,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            verify: false,
+            overwrite: OverwritePolicy::Overwrite,
+            preserve_times: true,
+            verbose: false,
+            symlinks: SymlinkPolicy::Recreate,
+        }
+    }
+}
+
+/// Options as requested by one source; unset fields fall through to the next source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PartialOptions {
+    pub verify: Option<bool>,
+    pub overwrite: Option<OverwritePolicy>,
+    pub preserve_times: Option<bool>,
+    pub verbose: Option<bool>,
+    pub symlinks: Option<SymlinkPolicy>,
+}
+
+impl PartialOptions {
+    /// Reads `--verify`, `--preserve-times` and `--verbose` (or `-v`) from a command line.
+    /// Each flag also has a `--no-` form that explicitly turns it off. The overwrite policy is
+    /// set by `-f`/`--overwrite`, `-n`/`--no-overwrite` (skip) or `-i`/`--prompt`, and the
+    /// symlink policy by `--symlinks=skip|recreate|follow`.
+    pub fn from_args(parts: &[&str]) -> PartialOptions {
+        let flag = |name: &str| {
+            if parts.contains(&format!("--{}", name).as_str()) {
+                Some(true)
+            } else if parts.contains(&format!("--no-{}", name).as_str()) {
+                Some(false)
+            } else {
+                None
+            }
+        };
+        PartialOptions {
+            verify: flag("verify"),
+            overwrite: if parts.contains(&"-i") || parts.contains(&"--prompt") {
+                Some(OverwritePolicy::Prompt)
+            } else if parts.contains(&"-n") {
+                Some(OverwritePolicy::Skip)
+            } else if parts.contains(&"-f") {
+                Some(OverwritePolicy::Overwrite)
+            } else {
+                flag("overwrite").map(|o| if o { OverwritePolicy::Overwrite } else { OverwritePolicy::Skip })
+            },
+            preserve_times: flag("preserve-times"),
+            verbose: if parts.contains(&"-v") { Some(true) } else { flag("verbose") },
+            symlinks: parts.iter().find_map(|p| p.strip_prefix("--symlinks=")).and_then(SymlinkPolicy::parse),
+        }
+    }
+
+    /// Loads the options file in `dir`, or returns no settings if there is none.
+    pub fn load(dir: &Path) -> io::Result<PartialOptions> {
+        let file = dir.join(OPTIONS_FILE);
+        if !file.exists() {
+            return Ok(PartialOptions::default());
+        }
+        serde_json::from_str(&fs::read_to_string(&file)?).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file.display(), e))
+        })
+    }
+}
+
+impl Options {
+    /// Merges the sources in priority order: command line, then config, then defaults.
+    pub fn resolve(config: &PartialOptions, cli: &PartialOptions) -> Options {
+        let defaults = Options::default();
+        Options {
+            verify: cli.verify.or(config.verify).unwrap_or(defaults.verify),
+            overwrite: cli.overwrite.or(config.overwrite).unwrap_or(defaults.overwrite),
+            preserve_times: cli.preserve_times.or(config.preserve_times).unwrap_or(defaults.preserve_times),
+            verbose: cli.verbose.or(config.verbose).unwrap_or(defaults.verbose),
+            symlinks: cli.symlinks.or(config.symlinks).unwrap_or(defaults.symlinks),
+        }
+    }
+}
+
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "verify={} overwrite={} preserve_times={} verbose={} symlinks={}",
+            self.verify, self.overwrite, self.preserve_times, self.verbose, self.symlinks
+        )
+    }
+}
+
+/// Prints each option as requested on the command line, as set in the config file,
+/// and the value that actually applies.
+pub fn print_effective(config: &PartialOptions, cli: &PartialOptions) {
+    let effective = Options::resolve(config, cli);
+    fn show<T: fmt::Display>(v: Option<T>) -> String {
+        v.map_or("-".to_string(), |b| b.to_string())
+    }
+    println!("{:<16} {:>9} {:>9} {:>10}", "option", "cli", "config", "effective");
+    let rows = [
+        ("verify", show(cli.verify), show(config.verify), effective.verify.to_string()),
+        ("overwrite", show(cli.overwrite), show(config.overwrite), effective.overwrite.to_string()),
+        (
+            "preserve_times",
+            show(cli.preserve_times),
+            show(config.preserve_times),
+            effective.preserve_times.to_string(),
+        ),
+        ("verbose", show(cli.verbose), show(config.verbose), effective.verbose.to_string()),
+        ("symlinks", show(cli.symlinks), show(config.symlinks), effective.symlinks.to_string()),
+    ];
+    for (name, cli, config, value) in rows {
+        println!("{:<16} {:>9} {:>9} {:>10}", name, cli, config, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_cli_over_config_over_defaults() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            temp_dir.path().join(OPTIONS_FILE),
+            r#"{ "verify": true, "overwrite": false, "preserve_times": true, "symlinks": "skip" }"#,
+        )
+        .unwrap();
+        let config = PartialOptions::load(temp_dir.path()).unwrap();
+        let cli = PartialOptions::from_args(&["cp", "a", "b", "--no-preserve-times", "-v", "--symlinks=follow"]);
+
+        let opts = Options::resolve(&config, &cli);
+        assert_eq!(
+            opts,
+            Options {
+                verify: true,
+                overwrite: OverwritePolicy::Skip,
+                preserve_times: false,
+                verbose: true,
+                symlinks: SymlinkPolicy::Follow,
+            }
+        );
+        assert_eq!(config.symlinks, Some(SymlinkPolicy::Skip));
+        assert_eq!(PartialOptions::from_args(&["cp", "--symlinks=sometimes"]).symlinks, None);
+        assert_eq!(Options::resolve(&PartialOptions::default(), &PartialOptions::default()), Options::default());
+
+        fs::write(temp_dir.path().join(OPTIONS_FILE), r#"{ "verfy": true }"#).unwrap();
+        assert!(PartialOptions::load(temp_dir.path()).is_err());
+
+        fs::write(temp_dir.path().join(OPTIONS_FILE), r#"{ "overwrite": "prompt" }"#).unwrap();
+        let config = PartialOptions::load(temp_dir.path()).unwrap();
+        assert_eq!(config.overwrite, Some(OverwritePolicy::Prompt));
+        let forced = Options::resolve(&config, &PartialOptions::from_args(&["cp", "-f", "a", "b"]));
+        assert_eq!(forced.overwrite, OverwritePolicy::Overwrite);
+        let skip = PartialOptions::from_args(&["cp", "-n", "a", "b"]);
+        assert_eq!(skip.overwrite, Some(OverwritePolicy::Skip));
+        fs::write(temp_dir.path().join(OPTIONS_FILE), r#"{ "overwrite": "sometimes" }"#).unwrap();
+        assert!(PartialOptions::load(temp_dir.path()).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::{self, Metadata};
+use std::path::PathBuf;
+
+const PASSWD: &str = "/etc/passwd";
+const GROUP: &str = "/etc/group";
+
+/// Finds the id of `name` in `/etc/passwd` or `/etc/group` formatted text (`name:x:id:...`).
+fn id_for_name(db: &str, name: &str) -> Option<u32> {
+    db.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+/// Finds the name for `id` in `/etc/passwd` or `/etc/group` formatted text.
+fn name_for_id(db: &str, id: u32) -> Option<String> {
+    db.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() > 2 && fields[2].parse() == Ok(id)).then(|| fields[0].to_string())
+    })
+}
+
+/// Finds the home directory of `name` in `/etc/passwd` formatted text.
+fn home_for_name(db: &str, name: &str) -> Option<PathBuf> {
+    db.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() > 5 && fields[0] == name).then(|| PathBuf::from(fields[5]))
+    })
+}
+
+/// Resolves a user given by name or numeric uid.
+pub fn resolve_user(spec: &str) -> Option<u32> {
+    spec.parse().ok().or_else(|| id_for_name(&fs::read_to_string(PASSWD).ok()?, spec))
+}
+
+/// Resolves a group given by name or numeric gid.
+pub fn resolve_group(spec: &str) -> Option<u32> {
+    spec.parse().ok().or_else(|| id_for_name(&fs::read_to_string(GROUP).ok()?, spec))
+}
+
+/// The user name for `uid`, or the number itself when it has no entry.
+pub fn user_name(uid: u32) -> String {
+    fs::read_to_string(PASSWD)
+        .ok()
+        .and_then(|db| name_for_id(&db, uid))
+        .unwrap_or_else(|| uid.to_string())
+}
+
+/// The group name for `gid`, or the number itself when it has no entry.
+pub fn group_name(gid: u32) -> String {
+    fs::read_to_string(GROUP)
+        .ok()
+        .and_then(|db| name_for_id(&db, gid))
+        .unwrap_or_else(|| gid.to_string())
+}
+
+/// The home directory of the user `name`, if the passwd database lists one.
+pub fn user_home(name: &str) -> Option<PathBuf> {
+    home_for_name(&fs::read_to_string(PASSWD).ok()?, name)
+}
+
+/// The owning uid and gid of a file.
+#[cfg(unix)]
+pub fn owner_ids(meta: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+/// Files have no Unix owner ids outside Unix.
+#[cfg(not(unix))]
+pub fn owner_ids(_meta: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_lookups() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000::/home/alice:/bin/sh\n";
+        assert_eq!(id_for_name(passwd, "alice"), Some(1000));
+        assert_eq!(id_for_name(passwd, "bob"), None);
+        assert_eq!(name_for_id(passwd, 0).as_deref(), Some("root"));
+        assert_eq!(name_for_id(passwd, 42), None);
+        assert_eq!(home_for_name(passwd, "alice"), Some(PathBuf::from("/home/alice")));
+        assert_eq!(home_for_name(passwd, "bob"), None);
+        assert_eq!(resolve_user("1234"), Some(1234));
+        assert_eq!(user_name(4_000_000_000), "4000000000");
+    }
+}
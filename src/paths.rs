@@ -0,0 +1,209 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::owners;
+
+/// Lexically normalizes a path, resolving `.` and `..` without touching the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push("..");
+                }
+            }
+            other => out.push(other.as_os_str()),
+        }
+    }
+    out
+}
+
+/// Returns `path` relative to `base`, or `None` if it lies outside `base`.
+pub fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    normalize(path)
+        .strip_prefix(normalize(base))
+        .ok()
+        .map(Path::to_path_buf)
+}
+
+/// Converts a relative path to a portable string using `/` separators.
+pub fn to_portable(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The user's home directory from `HOME`, or `USERPROFILE` on Windows.
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+/// Expands a leading `~` (or `~user`) to a home directory and substitutes `$VAR`, `${VAR}` and
+/// `%VAR%` from the environment. References that can't be resolved are left as written.
+pub fn expand_path(token: &str) -> String {
+    expand_path_with(token, &|name| env::var(name).ok(), &owners::user_home)
+}
+
+fn expand_path_with(
+    token: &str,
+    var: &dyn Fn(&str) -> Option<String>,
+    user_home: &dyn Fn(&str) -> Option<PathBuf>,
+) -> String {
+    let mut out = String::new();
+    let mut rest = token;
+    if let Some(after) = token.strip_prefix('~') {
+        let (user, tail) = after.split_at(after.find(['/', '\\']).unwrap_or(after.len()));
+        let home = if user.is_empty() {
+            var("HOME").or_else(|| var("USERPROFILE")).map(PathBuf::from)
+        } else {
+            user_home(user)
+        };
+        if let Some(home) = home {
+            out.push_str(&home.to_string_lossy());
+            rest = tail;
+        }
+    }
+    while let Some(i) = rest.find(['$', '%']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match variable_reference(rest).and_then(|(name, len)| Some((var(name)?, len))) {
+            Some((value, len)) => {
+                out.push_str(&value);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses a `$NAME`, `${NAME}` or `%NAME%` reference at the start of `s`, returning the name and
+/// the length of the whole reference.
+fn variable_reference(s: &str) -> Option<(&str, usize)> {
+    let ident_len = |s: &str| s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(s.len());
+    if let Some(braced) = s.strip_prefix("${") {
+        let end = braced.find('}')?;
+        return (end > 0).then(|| (&braced[..end], end + 3));
+    }
+    if let Some(name) = s.strip_prefix('$') {
+        let len = ident_len(name);
+        return (len > 0 && !name.starts_with(|c: char| c.is_ascii_digit())).then(|| (&name[..len], len + 1));
+    }
+    let name = s.strip_prefix('%')?;
+    let len = ident_len(name);
+    (len > 0 && name[len..].starts_with('%')).then(|| (&name[..len], len + 2))
+}
+
+/// Rewrites each absolute path in a newline-delimited list relative to `base`.
+/// Paths outside `base` are left unchanged and returned as the second element.
+pub fn portablize(list: &str, base: &Path) -> (Vec<String>, Vec<String>) {
+    let mut lines = Vec::new();
+    let mut outside = Vec::new();
+    for line in list.lines().filter(|l| !l.trim().is_empty()) {
+        match relative_to(Path::new(line), base) {
+            Some(rel) => lines.push(to_portable(&rel)),
+            None => {
+                outside.push(line.to_string());
+                lines.push(line.to_string());
+            }
+        }
+    }
+    (lines, outside)
+}
+
+/// Re-absolutizes a portable path list against `base`. Absolute entries are kept as-is.
+pub fn resolve(list: &str, base: &Path) -> Vec<PathBuf> {
+    list.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| {
+            if Path::new(line).is_absolute() {
+                PathBuf::from(line)
+            } else {
+                normalize(&line.split('/').fold(base.to_path_buf(), |p, c| p.join(c)))
+            }
+        })
+        .collect()
+}
+
+/// Rewrites a path list file in place to be relative to `base`, returning flagged paths.
+pub fn portablize_file(file: &Path, base: &Path) -> io::Result<Vec<String>> {
+    let (lines, outside) = portablize(&fs::read_to_string(file)?, base);
+    fs::write(file, lines.join("\n") + "\n")?;
+    Ok(outside)
+}
+
+/// Rewrites a portable path list file in place to absolute paths under `base`.
+pub fn resolve_file(file: &Path, base: &Path) -> io::Result<()> {
+    let paths = resolve(&fs::read_to_string(file)?, base);
+    let text: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    fs::write(file, text.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_and_relative_to() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+        assert_eq!(relative_to(Path::new("/a/b/c"), Path::new("/a")), Some(PathBuf::from("b/c")));
+        assert_eq!(relative_to(Path::new("/a/../x"), Path::new("/a")), None);
+    }
+
+    #[test]
+    fn test_portablize_resolve_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let old_base = temp_dir.path().join("old");
+        let new_base = temp_dir.path().join("new");
+        let list_file = temp_dir.path().join("list.txt");
+        let original = [
+            old_base.join("docs/a.txt"),
+            old_base.join("b.txt"),
+            temp_dir.path().join("elsewhere.txt"),
+        ];
+        let text: Vec<String> = original.iter().map(|p| p.display().to_string()).collect();
+        fs::write(&list_file, text.join("\n")).unwrap();
+
+        let flagged = portablize_file(&list_file, &old_base).unwrap();
+        assert_eq!(flagged, vec![original[2].display().to_string()]);
+        let portable = fs::read_to_string(&list_file).unwrap();
+        assert!(portable.starts_with("docs/a.txt\nb.txt\n"));
+
+        resolve_file(&list_file, &new_base).unwrap();
+        let resolved = resolve(&fs::read_to_string(&list_file).unwrap(), &new_base);
+        assert_eq!(
+            resolved,
+            vec![new_base.join("docs/a.txt"), new_base.join("b.txt"), original[2].clone()]
+        );
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "PROJ" => Some("work".to_string()),
+            _ => None,
+        };
+        let user_home = |user: &str| (user == "alice").then(|| PathBuf::from("/home/alice"));
+        let expand = |token: &str| expand_path_with(token, &var, &user_home);
+
+        assert_eq!(expand("~"), "/home/me");
+        assert_eq!(expand("~/Documents"), "/home/me/Documents");
+        assert_eq!(expand("~alice/notes"), "/home/alice/notes");
+        assert_eq!(expand("~bob/notes"), "~bob/notes");
+        assert_eq!(expand("$HOME/.bashrc"), "/home/me/.bashrc");
+        assert_eq!(expand("${PROJ}_old/%PROJ%"), "work_old/work");
+        assert_eq!(expand("$UNDEFINED/x"), "$UNDEFINED/x");
+        assert_eq!(expand("%UNDEFINED%"), "%UNDEFINED%");
+        assert_eq!(expand("50%off$"), "50%off$");
+    }
+}
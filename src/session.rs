@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of command lines kept in the session history.
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// The file the explorer's line-editor history persists to: `ruforus/history` under the
+/// user's config directory (`%APPDATA%` on Windows, `$XDG_CONFIG_HOME` or `~/.config` elsewhere).
+pub fn history_file() -> Option<PathBuf> {
+    let config = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?
+    };
+    Some(config.join("ruforus").join("history"))
+}
+
+/// Explorer state that can be saved to and restored from a single JSON file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub cwd: PathBuf,
+    /// Named directories for `go`.
+    pub bookmarks: BTreeMap<String, PathBuf>,
+    /// Command names that expand to a longer command line.
+    pub aliases: BTreeMap<String, String>,
+    /// Command lines entered so far, oldest first.
+    pub history: Vec<String>,
+    /// Paths collected by `yank`, copied by `paste`.
+    pub paste_buffer: Vec<PathBuf>,
+}
+
+impl Session {
+    pub fn new(cwd: &Path) -> Self {
+        Session { cwd: cwd.to_path_buf(), ..Default::default() }
+    }
+
+    /// Writes the session to `path` as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads a session written by `save`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Appends a command line to the history, dropping the oldest entries past `HISTORY_LIMIT`.
+    pub fn record(&mut self, line: &str) {
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_LIMIT {
+            let excess = self.history.len() - HISTORY_LIMIT;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Replaces the first word of `line` with its alias, if it has one. Aliases are not
+    /// expanded recursively.
+    pub fn expand_alias(&self, line: &str) -> String {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match self.aliases.get(command) {
+            Some(expansion) if rest.is_empty() => expansion.clone(),
+            Some(expansion) => format!("{} {}", expansion, rest.trim_start()),
+            None => line.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("session.json");
+        let mut session = Session::new(Path::new("/data/photos"));
+        session.bookmarks.insert("music".into(), PathBuf::from("/data/music"));
+        session.aliases.insert("ll".into(), "ls -r 1 --raw".into());
+        session.record("ls");
+        session.record("cd 2024");
+        session.paste_buffer.push(PathBuf::from("/data/photos/a.jpg"));
+        session.save(&file).unwrap();
+
+        let fresh = Session::new(Path::new("/"));
+        assert_ne!(fresh, session);
+        let loaded = Session::load(&file).unwrap();
+        assert_eq!(loaded.cwd, PathBuf::from("/data/photos"));
+        assert_eq!(loaded.bookmarks["music"], PathBuf::from("/data/music"));
+        assert_eq!(loaded.aliases["ll"], "ls -r 1 --raw");
+        assert_eq!(loaded, session);
+    }
+
+    #[test]
+    fn test_expand_alias_and_history_limit() {
+        let mut session = Session::default();
+        session.aliases.insert("ll".into(), "ls -r 1".into());
+        assert_eq!(session.expand_alias("ll --raw"), "ls -r 1 --raw");
+        assert_eq!(session.expand_alias("ll"), "ls -r 1");
+        assert_eq!(session.expand_alias("cat ll"), "cat ll");
+
+        for i in 0..HISTORY_LIMIT + 5 {
+            session.record(&i.to_string());
+        }
+        assert_eq!(session.history.len(), HISTORY_LIMIT);
+        assert_eq!(session.history[0], "5");
+    }
+}
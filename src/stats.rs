@@ -0,0 +1,184 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::file_explorer::{file_id, has_other_links, is_excluded};
+
+/// What `scan` skips and how many of the largest files it keeps.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// File name globs whose entries (and subtrees) are skipped.
+    pub exclude: Vec<String>,
+    /// Number of largest files to report.
+    pub largest: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions { exclude: Vec::new(), largest: 10 }
+    }
+}
+
+/// File count and total size of one extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtStats {
+    pub files: usize,
+    pub size: u64,
+}
+
+/// Statistics gathered by a single walk of a directory tree. Paths are relative to the root.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    pub files: usize,
+    pub dirs: usize,
+    pub total_size: u64,
+    /// Keyed by lowercased extension; files without one use the empty string.
+    pub by_extension: BTreeMap<String, ExtStats>,
+    /// Deepest level below the root that holds an entry; the root's own entries are level 1.
+    pub max_depth: usize,
+    /// The largest files, biggest first.
+    pub largest: Vec<(PathBuf, u64)>,
+    pub newest: Option<(PathBuf, SystemTime)>,
+    pub oldest: Option<(PathBuf, SystemTime)>,
+}
+
+/// Walks `root` once, collecting counts, sizes, extensions, depth, largest files and the
+/// newest and oldest modification times. Symlinks are counted as themselves rather than
+/// followed, and a file with several hard links is counted once. Entries that can't be read
+/// are skipped with a warning.
+pub fn scan(root: &Path, opts: &ScanOptions) -> io::Result<TreeStats> {
+    let mut stats = TreeStats::default();
+    let mut largest: BinaryHeap<Reverse<(u64, PathBuf)>> = BinaryHeap::new();
+    let mut seen_links = HashSet::new();
+    let mut stack = vec![(root.to_path_buf(), 1)];
+    while let Some((dir, depth)) = stack.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("Cannot access {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    eprintln!("Cannot read an entry of {:?}: {}", dir, e);
+                    continue;
+                }
+            };
+            if is_excluded(&path, &opts.exclude) {
+                continue;
+            }
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(m) => m,
+                Err(e) => {
+                    eprintln!("Cannot access {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            if has_other_links(&metadata) && file_id(&metadata).is_some_and(|id| !seen_links.insert(id)) {
+                continue;
+            }
+            stats.max_depth = stats.max_depth.max(depth);
+            if metadata.is_dir() {
+                stats.dirs += 1;
+                stack.push((path, depth + 1));
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            let size = metadata.len();
+            stats.files += 1;
+            stats.total_size += size;
+            let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            let ext_stats = stats.by_extension.entry(ext).or_default();
+            ext_stats.files += 1;
+            ext_stats.size += size;
+            if let Ok(modified) = metadata.modified() {
+                if stats.newest.as_ref().is_none_or(|(_, t)| modified > *t) {
+                    stats.newest = Some((relative.clone(), modified));
+                }
+                if stats.oldest.as_ref().is_none_or(|(_, t)| modified < *t) {
+                    stats.oldest = Some((relative.clone(), modified));
+                }
+            }
+            if opts.largest > 0 {
+                largest.push(Reverse((size, relative)));
+                if largest.len() > opts.largest {
+                    largest.pop();
+                }
+            }
+        }
+    }
+    let mut largest: Vec<(PathBuf, u64)> = largest.into_iter().map(|Reverse((size, p))| (p, size)).collect();
+    largest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    stats.largest = largest;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_explorer::{dir_size, freshness, walk_files};
+
+    #[test]
+    fn test_scan_matches_individual_walks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("photos/2024")).unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        let files = [
+            ("photos/2024/a.JPG", 300, 1_600_000_000),
+            ("photos/2024/b.jpg", 200, 1_700_000_000),
+            ("photos/c.png", 50, 1_650_000_000),
+            ("docs/readme", 10, 1_500_000_000),
+            ("notes.txt", 25, 1_680_000_000),
+        ];
+        for (name, len, mtime) in files {
+            let path = root.join(name);
+            fs::write(&path, vec![0u8; len]).unwrap();
+            filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(mtime, 0)).unwrap();
+        }
+
+        let stats = scan(root, &ScanOptions { largest: 2, ..Default::default() }).unwrap();
+        assert_eq!(stats.files, walk_files(root).unwrap().len());
+        assert_eq!(stats.dirs, 3);
+        assert_eq!(stats.total_size, dir_size(root, &[]).unwrap());
+        assert_eq!(stats.by_extension["jpg"], ExtStats { files: 2, size: 500 });
+        assert_eq!(stats.by_extension["png"], ExtStats { files: 1, size: 50 });
+        assert_eq!(stats.by_extension[""], ExtStats { files: 1, size: 10 });
+        assert_eq!(stats.by_extension.len(), 4);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(
+            stats.largest,
+            vec![(PathBuf::from("photos/2024/a.JPG"), 300), (PathBuf::from("photos/2024/b.jpg"), 200)]
+        );
+        let fresh = freshness(root, None, SystemTime::now()).unwrap();
+        assert_eq!(stats.newest, fresh.newest);
+        assert_eq!(stats.oldest, fresh.oldest);
+
+        let excluded = scan(root, &ScanOptions { exclude: vec!["photos".into()], largest: 0 }).unwrap();
+        assert_eq!(excluded.files, 2);
+        assert!(excluded.largest.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_skips_links_it_cannot_follow_and_counts_hard_links_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("data.bin"), vec![0u8; 100]).unwrap();
+        fs::hard_link(root.join("data.bin"), root.join("sub/again.bin")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+
+        let stats = scan(root, &ScanOptions::default()).unwrap();
+        assert_eq!(stats.dirs, 1);
+        assert_eq!(stats.by_extension["bin"], ExtStats { files: 1, size: 100 });
+        assert_eq!(stats.total_size, dir_size(root, &[]).unwrap());
+    }
+}
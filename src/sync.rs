@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::file_explorer::{copy_file, walk_files, with_retries};
+
+/// File in the source root listing paths, one per line, that `run_sync` never copies.
+pub const SYNC_IGNORE_FILE: &str = ".ruforus_sync_ignore";
+
+/// Why a file is part of a sync plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncReason {
+    New,
+    Changed,
+}
+
+/// A file the sync would copy, relative to the source root.
+#[derive(Debug, Clone)]
+pub struct SyncEntry {
+    pub relative: PathBuf,
+    pub size: u64,
+    pub reason: SyncReason,
+}
+
+/// The delta between a source tree and its destination.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    pub copies: Vec<SyncEntry>,
+    pub unchanged: Vec<PathBuf>,
+    /// Files present in the destination but not in the source.
+    pub extra: Vec<PathBuf>,
+}
+
+impl SyncPlan {
+    /// Total bytes that would be copied.
+    pub fn bytes_to_copy(&self) -> u64 {
+        self.copies.iter().map(|e| e.size).sum()
+    }
+}
+
+/// Modification time in whole seconds, used so filesystems with coarse timestamps compare equal.
+fn mtime_secs(metadata: &Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+/// Compares `source` against `dest` and plans which files need copying.
+/// A file is copied when it is missing from `dest` or its size or mtime differs.
+pub fn plan_sync(source: &Path, dest: &Path) -> io::Result<SyncPlan> {
+    plan_sync_with_tolerance(source, dest, 0)
+}
+
+/// Like `plan_sync`, treating mtimes up to `mtime_tolerance` seconds apart as equal, for
+/// destinations that store times coarsely.
+pub fn plan_sync_with_tolerance(source: &Path, dest: &Path, mtime_tolerance: u64) -> io::Result<SyncPlan> {
+    let mtimes_match = |a: &Metadata, b: &Metadata| match (mtime_secs(a), mtime_secs(b)) {
+        (Some(a), Some(b)) => a.abs_diff(b) <= mtime_tolerance,
+        (a, b) => a == b,
+    };
+    let mut dest_files: HashMap<PathBuf, Metadata> = walk_files(dest)?.into_iter().collect();
+    let mut plan = SyncPlan::default();
+    for (relative, src_meta) in walk_files(source)? {
+        let reason = match dest_files.remove(&relative) {
+            None => Some(SyncReason::New),
+            Some(dst_meta) if dst_meta.len() != src_meta.len() || !mtimes_match(&dst_meta, &src_meta) => {
+                Some(SyncReason::Changed)
+            }
+            Some(_) => None,
+        };
+        match reason {
+            Some(reason) => plan.copies.push(SyncEntry { relative, size: src_meta.len(), reason }),
+            None => plan.unchanged.push(relative),
+        }
+    }
+    plan.extra = dest_files.into_keys().collect();
+    plan.extra.sort();
+    Ok(plan)
+}
+
+/// Space required by a planned sync compared with what the destination has free.
+#[derive(Debug, Clone, Copy)]
+pub struct SpaceEstimate {
+    pub required: u64,
+    pub available: Option<u64>,
+}
+
+impl SpaceEstimate {
+    /// Returns `Some(true)` if the sync fits, or `None` if free space is unknown.
+    pub fn fits(&self) -> Option<bool> {
+        self.available.map(|a| self.required <= a)
+    }
+}
+
+/// Returns the free space of the filesystem holding `path` (or its nearest existing ancestor).
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    fs2::available_space(existing).ok()
+}
+
+/// Sums the sizes of only the files a sync from `source` to `dest` would copy,
+/// alongside the space available at `dest`.
+pub fn sync_space_needed(source: &Path, dest: &Path) -> io::Result<SpaceEstimate> {
+    let plan = plan_sync(source, dest)?;
+    Ok(SpaceEstimate {
+        required: plan.bytes_to_copy(),
+        available: available_space(dest),
+    })
+}
+
+/// A file a sync could not copy, relative to the source root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncFailure {
+    pub relative: PathBuf,
+    pub error: String,
+}
+
+/// Result of running a sync: how much was copied and which files failed.
+#[derive(Debug, Default)]
+pub struct SyncOutcome {
+    pub copied: usize,
+    pub bytes: u64,
+    pub failed: Vec<SyncFailure>,
+}
+
+/// Reads the ignore list in `source`, or an empty one if there is none.
+pub fn load_ignore_list(source: &Path) -> io::Result<HashSet<PathBuf>> {
+    match fs::read_to_string(source.join(SYNC_IGNORE_FILE)) {
+        Ok(text) => Ok(text.lines().filter(|l| !l.trim().is_empty()).map(PathBuf::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Appends paths to the ignore list in `source`.
+pub fn add_to_ignore_list(source: &Path, paths: &[PathBuf]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(source.join(SYNC_IGNORE_FILE))?;
+    for path in paths {
+        writeln!(file, "{}", path.display())?;
+    }
+    Ok(())
+}
+
+/// Copies one file of a sync, creating its parent directories and retrying failures.
+fn sync_one(source: &Path, dest: &Path, relative: &Path, retries: u32) -> io::Result<u64> {
+    let to = dest.join(relative);
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    with_retries(retries, || copy_file(&source.join(relative), &to))
+}
+
+/// Copies every new or changed file from `source` to `dest`, skipping the ignore list.
+/// A file that still fails after `retries` retries is recorded and the sync carries on.
+pub fn run_sync(source: &Path, dest: &Path, retries: u32) -> io::Result<SyncOutcome> {
+    let ignored = load_ignore_list(source)?;
+    let plan = plan_sync(source, dest)?;
+    let mut outcome = SyncOutcome::default();
+    for entry in plan.copies {
+        if ignored.contains(&entry.relative) || entry.relative == Path::new(SYNC_IGNORE_FILE) {
+            continue;
+        }
+        match sync_one(source, dest, &entry.relative, retries) {
+            Ok(bytes) => {
+                outcome.copied += 1;
+                outcome.bytes += bytes;
+            }
+            Err(e) => outcome.failed.push(SyncFailure { relative: entry.relative, error: e.to_string() }),
+        }
+    }
+    Ok(outcome)
+}
+
+/// What the user chose to do about the failed files of a sync.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Resolution {
+    pub retry: Vec<PathBuf>,
+    pub skip: Vec<PathBuf>,
+    /// Skipped now and added to the ignore list for later syncs.
+    pub ignore: Vec<PathBuf>,
+}
+
+/// Asks about each failure in turn: `r` retry, `s` skip, `i` skip permanently, `e` show the
+/// error and ask again. Running out of input skips the remaining files.
+pub fn choose_actions<R: BufRead>(failures: &[SyncFailure], input: &mut R) -> io::Result<Resolution> {
+    let mut resolution = Resolution::default();
+    for failure in failures {
+        loop {
+            print!("{}: [r]etry, [s]kip, [i]gnore always, show [e]rror? ", failure.relative.display());
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                resolution.skip.push(failure.relative.clone());
+                break;
+            }
+            match line.trim() {
+                "r" => resolution.retry.push(failure.relative.clone()),
+                "s" => resolution.skip.push(failure.relative.clone()),
+                "i" => resolution.ignore.push(failure.relative.clone()),
+                "e" => {
+                    println!("  {}", failure.error);
+                    continue;
+                }
+                _ => continue,
+            }
+            break;
+        }
+    }
+    Ok(resolution)
+}
+
+/// Walks through a sync's failures with `choose_actions`, records the ignored files and
+/// re-attempts the ones chosen for retry. Returns the outcome of the retries.
+pub fn resolve_failures<R: BufRead>(
+    source: &Path,
+    dest: &Path,
+    failures: &[SyncFailure],
+    retries: u32,
+    input: &mut R,
+) -> io::Result<SyncOutcome> {
+    let resolution = choose_actions(failures, input)?;
+    if !resolution.ignore.is_empty() {
+        add_to_ignore_list(source, &resolution.ignore)?;
+    }
+    let mut outcome = SyncOutcome::default();
+    for relative in resolution.retry {
+        match sync_one(source, dest, &relative, retries) {
+            Ok(bytes) => {
+                outcome.copied += 1;
+                outcome.bytes += bytes;
+            }
+            Err(e) => outcome.failed.push(SyncFailure { relative, error: e.to_string() }),
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_space_needed_excludes_unchanged_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::create_dir_all(dst.join("sub")).unwrap();
+        fs::write(src.join("same.bin"), vec![0u8; 100]).unwrap();
+        fs::write(src.join("sub/new.bin"), vec![0u8; 30]).unwrap();
+        fs::write(src.join("changed.bin"), vec![0u8; 7]).unwrap();
+        fs::write(dst.join("changed.bin"), vec![0u8; 5]).unwrap();
+        copy_file(&src.join("same.bin"), &dst.join("same.bin")).unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(src.join("same.bin")).unwrap());
+        filetime::set_file_mtime(dst.join("same.bin"), mtime).unwrap();
+
+        let plan = plan_sync(&src, &dst).unwrap();
+        assert_eq!(plan.unchanged, vec![PathBuf::from("same.bin")]);
+        assert_eq!(plan.copies.len(), 2);
+
+        let estimate = sync_space_needed(&src, &dst).unwrap();
+        assert_eq!(estimate.required, 37);
+        assert!(estimate.available.is_some());
+    }
+
+    #[test]
+    fn test_mtime_tolerance_for_coarse_timestamps() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(src.join("odd.txt"), "odd").unwrap();
+        fs::write(dst.join("odd.txt"), "odd").unwrap();
+        // FAT rounds an odd-second mtime to an even one.
+        filetime::set_file_mtime(src.join("odd.txt"), filetime::FileTime::from_unix_time(1_000_001, 0)).unwrap();
+        filetime::set_file_mtime(dst.join("odd.txt"), filetime::FileTime::from_unix_time(1_000_002, 0)).unwrap();
+
+        assert_eq!(plan_sync(&src, &dst).unwrap().copies.len(), 1);
+        assert_eq!(plan_sync_with_tolerance(&src, &dst, 2).unwrap().unchanged, vec![PathBuf::from("odd.txt")]);
+
+        filetime::set_file_mtime(dst.join("odd.txt"), filetime::FileTime::from_unix_time(1_000_004, 0)).unwrap();
+        assert_eq!(plan_sync_with_tolerance(&src, &dst, 2).unwrap().copies.len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_failures_with_scripted_choices() {
+        let failure = |p: &str| SyncFailure { relative: PathBuf::from(p), error: "Input/output error".into() };
+        let failures = vec![failure("a.txt"), failure("b.txt"), failure("c.txt"), failure("d.txt")];
+        let mut input = io::Cursor::new("r\ni\ne\ns\nx\nr\n");
+        let resolution = choose_actions(&failures, &mut input).unwrap();
+        assert_eq!(resolution.retry, vec![PathBuf::from("a.txt"), PathBuf::from("d.txt")]);
+        assert_eq!(resolution.ignore, vec![PathBuf::from("b.txt")]);
+        assert_eq!(resolution.skip, vec![PathBuf::from("c.txt")]);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("sub/b.txt"), "b").unwrap();
+        let failures = vec![failure("a.txt"), failure("sub/b.txt")];
+        let outcome = resolve_failures(&src, &dst, &failures, 0, &mut io::Cursor::new("r\ni\n")).unwrap();
+        assert_eq!(outcome.copied, 1);
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "a");
+        assert!(!dst.join("sub/b.txt").exists());
+        assert!(load_ignore_list(&src).unwrap().contains(Path::new("sub/b.txt")));
+
+        fs::write(src.join("new.txt"), "new").unwrap();
+        let outcome = run_sync(&src, &dst, 0).unwrap();
+        assert_eq!(outcome.copied, 1);
+        assert!(outcome.failed.is_empty());
+        assert!(!dst.join("sub/b.txt").exists());
+        assert!(!dst.join(SYNC_IGNORE_FILE).exists());
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths::{relative_to, to_portable};
+
+/// Name of the sidecar file holding the tag index, stored in the index root.
+pub const TAG_INDEX_FILE: &str = ".ruforus_tags.json";
+
+/// Tags attached to files, keyed by their path relative to the index root.
+#[derive(Debug)]
+pub struct TagIndex {
+    root: PathBuf,
+    entries: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl TagIndex {
+    /// Loads the index stored under `root`, or starts an empty one if none exists.
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let file = root.join(TAG_INDEX_FILE);
+        let entries = if file.exists() {
+            serde_json::from_str(&fs::read_to_string(&file)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(TagIndex { root: root.to_path_buf(), entries })
+    }
+
+    /// Writes the index back to its sidecar file.
+    pub fn save(&self) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.entries)?;
+        fs::write(self.root.join(TAG_INDEX_FILE), json)
+    }
+
+    fn key(&self, path: &Path) -> io::Result<String> {
+        relative_to(path, &self.root).map(|rel| to_portable(&rel)).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is outside the tag root {}; only files under it can be tagged",
+                    path.display(),
+                    self.root.display()
+                ),
+            )
+        })
+    }
+
+    /// Adds one or more tags to a file.
+    pub fn tag(&mut self, path: &Path, names: &[&str]) -> io::Result<()> {
+        let key = self.key(path)?;
+        let tags = self.entries.entry(key).or_default();
+        tags.extend(names.iter().map(|n| n.to_string()));
+        Ok(())
+    }
+
+    /// Removes a tag from a file, returning whether it was present.
+    pub fn untag(&mut self, path: &Path, name: &str) -> io::Result<bool> {
+        let key = self.key(path)?;
+        let removed = match self.entries.get_mut(&key) {
+            Some(tags) => tags.remove(name),
+            None => false,
+        };
+        if self.entries.get(&key).is_some_and(|t| t.is_empty()) {
+            self.entries.remove(&key);
+        }
+        Ok(removed)
+    }
+
+    /// Lists the files carrying a tag.
+    pub fn tagged(&self, name: &str) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|(_, tags)| tags.contains(name))
+            .map(|(key, _)| key.split('/').fold(self.root.clone(), |p, c| p.join(c)))
+            .collect()
+    }
+
+    /// Updates the index after `from` was moved to `to`, including anything beneath a moved directory.
+    /// Returns true if any entry changed.
+    pub fn rename_path(&mut self, from: &Path, to: &Path) -> bool {
+        let (Ok(from_key), Ok(to_key)) = (self.key(from), self.key(to)) else {
+            return false;
+        };
+        let prefix = format!("{}/", from_key);
+        let moved: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|k| **k == from_key || k.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for old in &moved {
+            let tags = self.entries.remove(old).unwrap();
+            let new = format!("{}{}", to_key, &old[from_key.len()..]);
+            self.entries.insert(new, tags);
+        }
+        !moved.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_untag_and_query() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let mut index = TagIndex::load(root).unwrap();
+        index.tag(&root.join("a.mp4"), &["video", "holiday"]).unwrap();
+        index.tag(&root.join("music/b.mp3"), &["holiday"]).unwrap();
+        index.save().unwrap();
+
+        let mut index = TagIndex::load(root).unwrap();
+        assert_eq!(index.tagged("holiday"), vec![root.join("a.mp4"), root.join("music").join("b.mp3")]);
+        assert!(index.untag(&root.join("a.mp4"), "holiday").unwrap());
+        assert!(!index.untag(&root.join("a.mp4"), "holiday").unwrap());
+        assert_eq!(index.tagged("holiday"), vec![root.join("music").join("b.mp3")]);
+        assert_eq!(index.tagged("video"), vec![root.join("a.mp4")]);
+        let err = index.tag(Path::new("/elsewhere/x"), &["nope"]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains(&format!("outside the tag root {}", root.display())));
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::dates::format_date;
+use crate::file_explorer::copy_file;
+use crate::hash::sha256_file;
+
+/// Renders a file name template for `src`. Placeholders:
+/// `{name}` file stem, `{ext}` extension without the dot, `{size}` size in bytes,
+/// `{date}` the date of `now`, `{mdate}` the modification date, `{hash8}` the first
+/// eight hex digits of the SHA-256. The file is only hashed if `{hash8}` is used.
+pub fn render_template(template: &str, src: &Path, now: SystemTime) -> io::Result<String> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| invalid(format!("Unclosed placeholder in template '{}'", template)))?;
+        let value = match &rest[open + 1..open + close] {
+            "name" => src.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+            "ext" => src.extension().unwrap_or_default().to_string_lossy().into_owned(),
+            "size" => fs::metadata(src)?.len().to_string(),
+            "date" => format_date(now),
+            "mdate" => format_date(fs::metadata(src)?.modified()?),
+            "hash8" => sha256_file(src)?[..8].to_string(),
+            other => return Err(invalid(format!("Unknown placeholder {{{}}}", other))),
+        };
+        out.push_str(&value);
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    if out.is_empty() || out.contains(['/', '\\']) {
+        return Err(invalid(format!("Template '{}' does not give a plain file name: '{}'", template, out)));
+    }
+    Ok(out)
+}
+
+/// Copies `src` into `dest_dir` under the name rendered from `template`, creating the directory
+/// if needed. An existing file of that name is left alone and reported as an error.
+pub fn copy_with_template(src: &Path, template: &str, dest_dir: &Path, now: SystemTime) -> io::Result<PathBuf> {
+    let dst = dest_dir.join(render_template(template, src, now)?);
+    if dst.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dst.display()),
+        ));
+    }
+    fs::create_dir_all(dest_dir)?;
+    copy_file(src, &dst)?;
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::parse_date;
+
+    #[test]
+    fn test_render_template() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("IMG.jpg");
+        fs::write(&src, b"abc").unwrap();
+        let now = parse_date("2024-03-09").unwrap();
+
+        assert_eq!(render_template("{date}-{hash8}.{ext}", &src, now).unwrap(), "2024-03-09-ba7816bf.jpg");
+        assert_eq!(render_template("{name}_{size}", &src, now).unwrap(), "IMG_3");
+        assert!(render_template("{nope}.jpg", &src, now).is_err());
+        assert!(render_template("{name", &src, now).is_err());
+        assert!(render_template("../{name}", &src, now).is_err());
+
+        let dest = temp_dir.path().join("archive/2024");
+        let copied = copy_with_template(&src, "{date}-{hash8}.{ext}", &dest, now).unwrap();
+        assert_eq!(copied, dest.join("2024-03-09-ba7816bf.jpg"));
+        assert_eq!(fs::read(&copied).unwrap(), b"abc");
+        assert!(copy_with_template(&src, "{date}-{hash8}.{ext}", &dest, now).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::file_explorer::{copy_dir, copy_file, dir_size};
+
+/// Runs `task` over every item with at most `jobs` worker threads.
+/// Results are returned in the same order as the input items.
+pub fn run_pool<T, R, F>(items: &[T], jobs: usize, task: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+    let workers = jobs.max(1).min(items.len().max(1));
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= items.len() {
+                    break;
+                }
+                let result = task(&items[i]);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("worker finished without a result"))
+        .collect()
+}
+
+/// Open files assumed when the OS soft limit can't be read.
+pub const DEFAULT_FD_LIMIT: usize = 256;
+
+/// The soft limit on open file descriptors for this process.
+#[cfg(target_os = "linux")]
+fn soft_fd_limit() -> Option<usize> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit only writes to the struct we pass.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+    usize::try_from(limit.rlim_cur).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn soft_fd_limit() -> Option<usize> {
+    None
+}
+
+/// A counting semaphore bounding how many files the pool's workers hold open at once,
+/// so wide parallel copies don't fail with "too many open files".
+#[derive(Debug)]
+pub struct FdBudget {
+    limit: usize,
+    in_use: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// Open files reserved from an `FdBudget`, returned when dropped.
+pub struct FdPermit<'a> {
+    budget: &'a FdBudget,
+    count: usize,
+}
+
+impl FdBudget {
+    pub fn new(limit: usize) -> Self {
+        FdBudget { limit: limit.max(1), in_use: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    /// A budget of half the OS soft limit, leaving the rest for stdio, directory handles
+    /// and the rest of the program.
+    pub fn from_os_limit() -> Self {
+        FdBudget::new(soft_fd_limit().unwrap_or(DEFAULT_FD_LIMIT) / 2)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Blocks until `count` files may be opened. Requests above the limit are capped to it.
+    pub fn acquire(&self, count: usize) -> FdPermit<'_> {
+        let count = count.min(self.limit);
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use + count > self.limit {
+            in_use = self.freed.wait(in_use).unwrap();
+        }
+        *in_use += count;
+        FdPermit { budget: self, count }
+    }
+}
+
+impl Drop for FdPermit<'_> {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock().unwrap() -= self.count;
+        self.budget.freed.notify_all();
+    }
+}
+
+/// A queued copy from `src` to `dst`.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+}
+
+/// Outcome of a single queued transfer.
+#[derive(Debug)]
+pub struct TransferResult {
+    pub transfer: Transfer,
+    pub result: io::Result<u64>,
+}
+
+/// A list of pending copies that can be executed with a concurrency limit.
+#[derive(Debug, Default)]
+pub struct TransferQueue {
+    items: Vec<Transfer>,
+}
+
+impl TransferQueue {
+    pub fn new() -> Self {
+        TransferQueue { items: Vec::new() }
+    }
+
+    /// Adds a copy to the end of the queue.
+    pub fn add(&mut self, src: &Path, dst: &Path) {
+        self.items.push(Transfer {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+        });
+    }
+
+    pub fn items(&self) -> &[Transfer] {
+        &self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Prints the queued transfers.
+    pub fn list(&self) {
+        if self.items.is_empty() {
+            println!("Transfer queue is empty.");
+            return;
+        }
+        for (i, t) in self.items.iter().enumerate() {
+            println!("[{}] {} -> {}", i, t.src.display(), t.dst.display());
+        }
+    }
+
+    /// Runs every queued transfer with at most `jobs` running at once, then clears the queue.
+    /// Each transfer holds two files (source and destination) from `budget` while it runs.
+    /// Failed transfers do not stop the others; each outcome is reported in the result list.
+    pub fn run(&mut self, jobs: usize, budget: &FdBudget) -> Vec<TransferResult> {
+        let items = std::mem::take(&mut self.items);
+        let total = items.len();
+        let done = AtomicUsize::new(0);
+        let results = run_pool(&items, jobs, |t| {
+            let permit = budget.acquire(2);
+            let result = run_transfer(t);
+            drop(permit);
+            let n = done.fetch_add(1, Ordering::SeqCst) + 1;
+            match &result {
+                Ok(bytes) => println!("[{}/{}] OK   {} -> {} ({} bytes)", n, total, t.src.display(), t.dst.display(), bytes),
+                Err(e) => println!("[{}/{}] FAIL {} -> {}: {}", n, total, t.src.display(), t.dst.display(), e),
+            }
+            result
+        });
+        items
+            .into_iter()
+            .zip(results)
+            .map(|(transfer, result)| TransferResult { transfer, result })
+            .collect()
+    }
+}
+
+/// Copies a single file or directory, returning the number of bytes copied.
+fn run_transfer(t: &Transfer) -> io::Result<u64> {
+    if t.src.is_dir() {
+        copy_dir(&t.src, &t.dst)?;
+        dir_size(&t.dst, &[])
+    } else {
+        copy_file(&t.src, &t.dst)
+    }
+}
+
+/// Prints a summary of a finished queue run.
+pub fn print_summary(results: &[TransferResult]) {
+    let failed = results.iter().filter(|r| r.result.is_err()).count();
+    let bytes: u64 = results.iter().filter_map(|r| r.result.as_ref().ok()).sum();
+    println!(
+        "Queue finished: {} succeeded, {} failed, {} bytes copied",
+        results.len() - failed,
+        failed,
+        bytes
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_queue_runs_with_job_limit() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut queue = TransferQueue::new();
+        for i in 0..5 {
+            let src = temp_dir.path().join(format!("src{}.txt", i));
+            fs::write(&src, format!("file {}", i)).unwrap();
+            queue.add(&src, &temp_dir.path().join(format!("dst{}.txt", i)));
+        }
+        queue.add(&temp_dir.path().join("missing.txt"), &temp_dir.path().join("dst_missing.txt"));
+
+        let results = queue.run(2, &FdBudget::from_os_limit());
+        assert!(queue.is_empty());
+        assert_eq!(results.len(), 6);
+        for (i, r) in results.iter().take(5).enumerate() {
+            assert_eq!(*r.result.as_ref().unwrap(), 6);
+            assert_eq!(
+                fs::read_to_string(temp_dir.path().join(format!("dst{}.txt", i))).unwrap(),
+                format!("file {}", i)
+            );
+        }
+        assert!(results[5].result.is_err());
+    }
+
+    #[test]
+    fn test_run_pool_preserves_order() {
+        let items: Vec<u32> = (0..20).collect();
+        let doubled = run_pool(&items, 3, |n| n * 2);
+        assert_eq!(doubled, (0..20).map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_fd_budget_bounds_open_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..40)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("{}.txt", i));
+                fs::write(&path, i.to_string()).unwrap();
+                path
+            })
+            .collect();
+        let budget = FdBudget::new(3);
+        let open = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let contents = run_pool(&files, 8, |path| {
+            let _permit = budget.acquire(1);
+            let file = fs::File::open(path);
+            peak.fetch_max(open.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(std::time::Duration::from_millis(1));
+            let text = io::read_to_string(file.unwrap()).unwrap();
+            open.fetch_sub(1, Ordering::SeqCst);
+            text
+        });
+        assert_eq!(contents[39], "39");
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        assert!(budget.acquire(10).count == 3);
+
+        let mut queue = TransferQueue::new();
+        for (i, src) in files.iter().enumerate() {
+            queue.add(src, &temp_dir.path().join(format!("copy{}.txt", i)));
+        }
+        let results = queue.run(8, &FdBudget::new(2));
+        assert!(results.iter().all(|r| r.result.is_ok()));
+    }
+}
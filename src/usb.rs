@@ -1,9 +1,19 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::{self, Write, Read, BufReader, BufWriter};
+use std::io::{self, Write, Read, BufReader, BufWriter, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Mutex;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use crate::error::RuError;
+use crate::file_explorer::{collect_dir, human_size, walk_files, SortOrder};
+use crate::hash::{sha256_file, sha256_prefix};
+use crate::sync::{plan_sync_with_tolerance, SyncPlan};
+use rayon::prelude::*;
 
 /// Represents a USB device (very basic, for demonstration).
 #[derive(Debug, Clone)]
@@ -13,40 +23,824 @@ pub struct UsbDevice {
     pub label: Option<String>,
     pub total_space: Option<u64>,
     pub free_space: Option<u64>,
+    pub read_only: bool,
+}
+
+/// PowerShell listing removable volumes that have a drive letter as a JSON array.
+#[cfg(windows)]
+const GET_VOLUME_SCRIPT: &str = "ConvertTo-Json -Compress -InputObject @(Get-Volume \
+    | Where-Object { $_.DriveType -eq 'Removable' -and $_.DriveLetter } \
+    | Select-Object @{n='DriveLetter';e={[string]$_.DriveLetter}},FileSystemLabel,Size,SizeRemaining,\
+    @{n='ReadOnly';e={[bool](Get-Partition -DriveLetter $_.DriveLetter | Get-Disk).IsReadOnly}})";
+
+/// Lists removable drives with PowerShell's `Get-Volume`, falling back to `wmic` (deprecated
+/// and missing on newer installs) when PowerShell isn't available or its output can't be parsed.
+#[cfg(windows)]
+pub fn list_usb_devices() -> Result<Vec<UsbDevice>, RuError> {
+    let from_powershell = Command::new("powershell")
+        .args(["-NoProfile", "-Command", GET_VOLUME_SCRIPT])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| parse_get_volume_json(&String::from_utf8_lossy(&output.stdout)));
+    let usb_devices = match from_powershell {
+        Some(devices) => devices,
+        None => {
+            let output = Command::new("wmic")
+                .args(["logicaldisk", "where", "DriveType=2", "get", "DeviceID,VolumeName,Size,FreeSpace,Access", "/format:csv"])
+                .output()?;
+            parse_wmic_csv(&String::from_utf8_lossy(&output.stdout))
+        }
+    };
+    Ok(usb_devices.into_iter().filter(|usb| usb.mount_point.exists()).collect())
+}
+
+/// A device for drive `letter` (`E` or `E:`), mounted at the drive's root.
+#[cfg(any(windows, test))]
+fn drive_device(
+    letter: &str,
+    label: Option<String>,
+    total_space: Option<u64>,
+    free_space: Option<u64>,
+    read_only: bool,
+) -> UsbDevice {
+    let device_id = format!("{}:", letter.trim_end_matches(':'));
+    UsbDevice {
+        mount_point: PathBuf::from(format!("{}\\", device_id)),
+        device_id,
+        label: label.filter(|l| !l.is_empty()),
+        total_space,
+        free_space,
+        read_only,
+    }
+}
+
+/// Parses the JSON array printed by `GET_VOLUME_SCRIPT`. Entries without a drive letter are
+/// skipped; a missing label or size is left as `None`. Returns `None` if the output isn't JSON.
+#[cfg(any(windows, test))]
+fn parse_get_volume_json(output: &str) -> Option<Vec<UsbDevice>> {
+    let volumes: Vec<serde_json::Value> = serde_json::from_str(output.trim()).ok()?;
+    Some(
+        volumes
+            .iter()
+            .filter_map(|v| {
+                let letter = v.get("DriveLetter")?.as_str().filter(|l| !l.is_empty())?;
+                Some(drive_device(
+                    letter,
+                    v.get("FileSystemLabel").and_then(|l| l.as_str()).map(str::to_string),
+                    v.get("Size").and_then(|n| n.as_u64()),
+                    v.get("SizeRemaining").and_then(|n| n.as_u64()),
+                    v.get("ReadOnly").and_then(|r| r.as_bool()).unwrap_or(false),
+                ))
+            })
+            .collect(),
+    )
 }
 
-/// Lists removable drives (Windows only, basic implementation).
-pub fn list_usb_devices() -> io::Result<Vec<UsbDevice>> {
+/// Parses `wmic logicaldisk ... /format:csv` output, locating columns by the header line since
+/// wmic orders them itself. Missing `VolumeName`, `Size` or `FreeSpace` values are left as `None`.
+/// An `Access` of 1 (readable only) marks the drive read-only.
+#[cfg(any(windows, test))]
+fn parse_wmic_csv(output: &str) -> Vec<UsbDevice> {
+    let mut lines = output.lines().map(str::trim).filter(|l| !l.is_empty());
+    let Some(header) = lines.next() else {
+        return Vec::new();
+    };
+    let columns: Vec<&str> = header.split(',').collect();
+    let column = |name: &str| columns.iter().position(|c| *c == name);
+    let (id, label, size, free) = (column("DeviceID"), column("VolumeName"), column("Size"), column("FreeSpace"));
+    let access = column("Access");
+    lines
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let field = |i: Option<usize>| i.and_then(|i| fields.get(i)).copied().filter(|f| !f.is_empty());
+            Some(drive_device(
+                field(id)?,
+                field(label).map(str::to_string),
+                field(size).and_then(|n| n.parse().ok()),
+                field(free).and_then(|n| n.parse().ok()),
+                field(access) == Some("1"),
+            ))
+        })
+        .collect()
+}
+
+/// Lists mounted removable drives: disks whose `/sys/block/<disk>/removable` flag is set, and
+/// their partitions, matched against `/proc/mounts`. Labels come from `/dev/disk/by-label` and
+/// space from `statvfs` (through fs2).
+#[cfg(target_os = "linux")]
+pub fn list_usb_devices() -> Result<Vec<UsbDevice>, RuError> {
+    let mut nodes = HashSet::new();
+    for entry in fs::read_dir("/sys/block")? {
+        let disk = entry?.path();
+        let removable = fs::read_to_string(disk.join("removable")).ok().and_then(|f| parse_sys_removable(&f));
+        if removable != Some(true) {
+            continue;
+        }
+        let name = disk.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        nodes.insert(format!("/dev/{}", name));
+        for part in fs::read_dir(&disk)?.flatten() {
+            if part.path().join("partition").exists() {
+                nodes.insert(format!("/dev/{}", part.file_name().to_string_lossy()));
+            }
+        }
+    }
+    let labels = read_links(Path::new("/dev/disk/by-label")).unwrap_or_default();
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    Ok(removable_mounts(&mounts, &nodes)
+        .into_iter()
+        .map(|(device_id, mount_point, read_only)| UsbDevice {
+            label: link_for_device(&labels, Path::new(&device_id)).map(|l| unescape_udev(&l)),
+            total_space: fs2::total_space(&mount_point).ok(),
+            free_space: fs2::available_space(&mount_point).ok(),
+            device_id,
+            mount_point,
+            read_only,
+        })
+        .collect())
+}
+
+/// Lists the volumes under `/Volumes` that `diskutil info` reports as removable or external
+/// USB media. Volumes whose details can't be read are skipped. Space comes from `statvfs`
+/// (through fs2).
+#[cfg(target_os = "macos")]
+pub fn list_usb_devices() -> Result<Vec<UsbDevice>, RuError> {
     let mut usb_devices = Vec::new();
-    // Query WMIC for removable drives and capture their device id and label
-    let output = Command::new("wmic")
-        .args(["logicaldisk", "where", "DriveType=2", "get", "DeviceID,VolumeName,Size,FreeSpace", "/format:csv"])
+    for entry in fs::read_dir("/Volumes")? {
+        let mount_point = entry?.path();
+        let Ok(output) = Command::new("diskutil").arg("info").arg(&mount_point).output() else {
+            continue;
+        };
+        if let Some(mut usb) = parse_diskutil_volume(&String::from_utf8_lossy(&output.stdout), &mount_point) {
+            usb.total_space = fs2::total_space(&mount_point).ok();
+            usb.free_space = fs2::available_space(&mount_point).ok();
+            usb_devices.push(usb);
+        }
+    }
+    Ok(usb_devices)
+}
+
+/// Removable drive enumeration isn't implemented for this platform; no devices are reported.
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn list_usb_devices() -> Result<Vec<UsbDevice>, RuError> {
+    Ok(Vec::new())
+}
+
+/// Finds the connected USB device whose volume label matches `label`, ignoring case.
+pub fn find_usb_by_label(label: &str) -> Result<Option<UsbDevice>, RuError> {
+    Ok(usb_with_label(list_usb_devices()?, label))
+}
+
+/// Finds the connected USB device mounted at `path`, or whose mount holds `path`.
+pub fn find_usb_by_mount(path: &Path) -> Result<Option<UsbDevice>, RuError> {
+    Ok(usb_at_mount(list_usb_devices()?, path))
+}
+
+fn usb_with_label(devices: Vec<UsbDevice>, label: &str) -> Option<UsbDevice> {
+    let label = label.to_lowercase();
+    devices.into_iter().find(|usb| usb.label.as_ref().is_some_and(|l| l.to_lowercase() == label))
+}
+
+/// Picks the device with the deepest mount point containing `path`, comparing canonical paths
+/// so `/media/stick/` and a symlink to it both match.
+fn usb_at_mount(devices: Vec<UsbDevice>, path: &Path) -> Option<UsbDevice> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    devices
+        .into_iter()
+        .filter_map(|usb| {
+            let mount = fs::canonicalize(&usb.mount_point).unwrap_or_else(|_| usb.mount_point.clone());
+            path.starts_with(&mount).then(|| (mount.components().count(), usb))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, usb)| usb)
+}
+
+/// Asks the OS whether the device is really removable media: the sysfs `removable` flag of the
+/// disk holding its device node, which also covers devices built by hand rather than listed.
+#[cfg(target_os = "linux")]
+pub fn is_truly_removable(usb: &UsbDevice) -> Result<bool, RuError> {
+    let source = device_node(usb)?;
+    let name = source.trim_start_matches("/dev/");
+    // Partitions live under their parent disk in sysfs, which carries the removable flag
+    let sys_path = fs::canonicalize(Path::new("/sys/class/block").join(name))?;
+    let disk_path = if sys_path.join("partition").exists() {
+        sys_path.parent().unwrap_or(&sys_path).to_path_buf()
+    } else {
+        sys_path
+    };
+    let flag = fs::read_to_string(disk_path.join("removable"))?;
+    parse_sys_removable(&flag)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unexpected sysfs removable flag").into())
+}
+
+/// Asks the OS whether the device is really removable media, which is stricter than
+/// the DriveType=2 heuristic used during enumeration.
+#[cfg(windows)]
+pub fn is_truly_removable(usb: &UsbDevice) -> Result<bool, RuError> {
+    let script = format!(
+        "Get-Partition -DriveLetter {} | Get-Disk | ForEach-Object {{ \"$($_.BusType),$($_.IsSystem),$($_.IsBoot)\" }}",
+        usb.device_id.trim_end_matches(':')
+    );
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .output()?;
+    parse_msft_disk(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Could not query MSFT_Disk for the device").into())
+}
+
+/// Asks the OS whether the device is really removable media: `diskutil info` must report
+/// removable media or an external USB device.
+#[cfg(target_os = "macos")]
+pub fn is_truly_removable(usb: &UsbDevice) -> Result<bool, RuError> {
+    let output = Command::new("diskutil").arg("info").arg(&usb.mount_point).output()?;
+    parse_diskutil_removable(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Could not parse diskutil info output").into())
+}
+
+/// Removability can't be confirmed on this platform, so nothing is treated as removable.
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn is_truly_removable(_usb: &UsbDevice) -> Result<bool, RuError> {
+    let message = "Checking for removable media is not supported on this platform";
+    Err(io::Error::new(io::ErrorKind::Unsupported, message).into())
+}
+
+/// Returns an identifier for the volume that survives replugging and remounting,
+/// or `None` if it can't be determined.
+/// On Linux this is the filesystem UUID from `/dev/disk/by-uuid`.
+#[cfg(target_os = "linux")]
+pub fn stable_id(usb: &UsbDevice) -> Option<String> {
+    let source = if usb.device_id.starts_with("/dev/") {
+        usb.device_id.clone()
+    } else {
+        find_mount(&fs::read_to_string("/proc/mounts").ok()?, &usb.mount_point)?.source
+    };
+    // Resolve /dev/disk/by-label style sources to the real device node
+    let device = fs::canonicalize(&source).unwrap_or_else(|_| PathBuf::from(&source));
+    link_for_device(&read_links(Path::new("/dev/disk/by-uuid")).ok()?, &device)
+}
+
+/// Returns an identifier for the volume that survives replugging and remounting,
+/// or `None` if it can't be determined.
+/// On Windows this is the volume GUID path (`\\?\Volume{...}\`).
+#[cfg(windows)]
+pub fn stable_id(usb: &UsbDevice) -> Option<String> {
+    let script = format!("(Get-Volume -DriveLetter {}).UniqueId", usb.device_id.trim_end_matches(':'));
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .output()
+        .ok()?;
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !id.is_empty()).then_some(id)
+}
+
+/// Returns an identifier for the volume that survives replugging and remounting,
+/// or `None` if it can't be determined.
+/// On macOS this is the volume UUID reported by `diskutil info`.
+#[cfg(target_os = "macos")]
+pub fn stable_id(usb: &UsbDevice) -> Option<String> {
+    let output = Command::new("diskutil").arg("info").arg(&usb.mount_point).output().ok()?;
+    parse_diskutil_uuid(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// No stable identifier is available on this platform.
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn stable_id(_usb: &UsbDevice) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn not_mounted(usb: &UsbDevice) -> RuError {
+    RuError::UsbNotFound(format!("{:?} is not a mount point", usb.mount_point))
+}
+
+/// Returns the `/dev` node backing the device, looking up its mount if `device_id` isn't one.
+#[cfg(target_os = "linux")]
+fn device_node(usb: &UsbDevice) -> Result<String, RuError> {
+    if usb.device_id.starts_with("/dev/") {
+        return Ok(usb.device_id.clone());
+    }
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    Ok(find_mount(&mounts, &usb.mount_point).ok_or_else(|| not_mounted(usb))?.source)
+}
+
+/// Detects the filesystem on the device (e.g. "vfat", "exfat", "ntfs"), lowercased.
+#[cfg(target_os = "linux")]
+pub fn filesystem_type(usb: &UsbDevice) -> Result<String, RuError> {
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    Ok(find_mount(&mounts, &usb.mount_point).ok_or_else(|| not_mounted(usb))?.fstype.to_lowercase())
+}
+
+/// Detects the filesystem on the device (e.g. "fat32", "exfat", "ntfs"), lowercased.
+#[cfg(windows)]
+pub fn filesystem_type(usb: &UsbDevice) -> Result<String, RuError> {
+    let script = format!("(Get-Volume -DriveLetter {}).FileSystem", usb.device_id.trim_end_matches(':'));
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
         .output()?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    for line in stdout.lines().skip(2) {
-        let fields: Vec<&str> = line.split(',').collect();
-        if fields.len() >= 5 {
-            let device_id = fields[1].trim().to_string();
-            let label = if fields[2].trim().is_empty() { None } else { Some(fields[2].trim().to_owned()) };
-            let total_space = fields[3].trim().parse::<u64>().ok();
-            let free_space = fields[4].trim().parse::<u64>().ok();
-            let mount_point = PathBuf::from(&device_id);
-            if mount_point.exists() {
-                usb_devices.push(UsbDevice {
-                    device_id,
-                    mount_point,
-                    label,
-                    total_space,
-                    free_space,
-                });
+    let fs_type = String::from_utf8_lossy(&output.stdout).trim().to_lowercase();
+    if fs_type.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "Could not determine the filesystem type").into());
+    }
+    Ok(fs_type)
+}
+
+/// Detects the filesystem on the device (e.g. "msdos", "exfat", "apfs"), lowercased.
+#[cfg(target_os = "macos")]
+pub fn filesystem_type(usb: &UsbDevice) -> Result<String, RuError> {
+    let output = Command::new("diskutil").arg("info").arg(&usb.mount_point).output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Type (Bundle):").map(|v| v.trim().to_lowercase()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not determine the filesystem type").into())
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn filesystem_type(_usb: &UsbDevice) -> Result<String, RuError> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Detecting the filesystem is not supported on this platform").into())
+}
+
+/// Name and path length limits of a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsLimits {
+    pub max_name: usize,
+    pub max_path: usize,
+    /// Whether lengths are counted in UTF-16 units (FAT/exFAT/NTFS) rather than bytes.
+    pub utf16: bool,
+    /// Whether names differing only in case refer to the same file.
+    pub case_insensitive: bool,
+    /// Seconds a stored mtime may differ from the original (FAT keeps only even seconds).
+    pub mtime_tolerance: u64,
+}
+
+impl FsLimits {
+    /// Looks up the limits for a filesystem type as reported by `filesystem_type`.
+    pub fn for_filesystem(fs_type: &str) -> FsLimits {
+        match fs_type {
+            // Long file names are capped at 255 UTF-16 units; Windows tools stop at MAX_PATH
+            "vfat" | "fat" | "fat16" | "fat32" | "msdos" => {
+                FsLimits { max_name: 255, max_path: 260, utf16: true, case_insensitive: true, mtime_tolerance: 2 }
             }
+            "exfat" => FsLimits { max_name: 255, max_path: 260, utf16: true, case_insensitive: true, mtime_tolerance: 0 },
+            "ntfs" | "ntfs3" | "fuseblk" => {
+                FsLimits { max_name: 255, max_path: 32_767, utf16: true, case_insensitive: true, mtime_tolerance: 0 }
+            }
+            _ => FsLimits { max_name: 255, max_path: 4096, utf16: false, case_insensitive: false, mtime_tolerance: 0 },
         }
     }
-    Ok(usb_devices)
+
+    fn len(&self, s: &str) -> usize {
+        if self.utf16 {
+            s.encode_utf16().count()
+        } else {
+            s.len()
+        }
+    }
+}
+
+/// What to do with names that exceed the target filesystem's limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LongNamePolicy {
+    /// Report offenders and copy nothing.
+    Refuse,
+    /// Shorten over-long names (keeping the extension) and copy.
+    Truncate,
+}
+
+/// What to do with files whose paths differ only in case when the target ignores case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseCollisionPolicy {
+    /// Report the collisions and copy nothing.
+    Refuse,
+    /// Copy the later file of each collision under a numbered name such as `file (2).txt`.
+    Rename,
+}
+
+/// A source path whose destination would exceed the filesystem limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathIssue {
+    pub relative: PathBuf,
+    pub reason: String,
+}
+
+/// Shortens a single name to `limits.max_name`, keeping its extension when possible.
+fn truncate_name(name: &str, limits: &FsLimits) -> String {
+    if limits.len(name) <= limits.max_name {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    };
+    let mut out = String::new();
+    for c in stem.chars() {
+        let mut candidate = out.clone();
+        candidate.push(c);
+        if limits.len(&candidate) + limits.len(ext) > limits.max_name {
+            break;
+        }
+        out = candidate;
+    }
+    out + ext
+}
+
+/// Maps a relative source path to its destination form under the given policy.
+fn destination_relative(relative: &Path, limits: &FsLimits, policy: LongNamePolicy) -> PathBuf {
+    match policy {
+        LongNamePolicy::Refuse => relative.to_path_buf(),
+        LongNamePolicy::Truncate => relative
+            .components()
+            .map(|c| truncate_name(&c.as_os_str().to_string_lossy(), limits))
+            .collect(),
+    }
+}
+
+/// Checks every file under `src_dir` against the limits as if copied to `dest_root`.
+/// Nothing is written.
+pub fn check_path_lengths(
+    src_dir: &Path,
+    dest_root: &Path,
+    limits: &FsLimits,
+    policy: LongNamePolicy,
+) -> Result<Vec<PathIssue>, RuError> {
+    let mut issues = Vec::new();
+    for (relative, _) in walk_files(src_dir)? {
+        let dest_relative = destination_relative(&relative, limits, policy);
+        let long_name = dest_relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .find(|name| limits.len(name) > limits.max_name);
+        let full = dest_root.join(&dest_relative).to_string_lossy().into_owned();
+        let reason = if let Some(name) = long_name {
+            format!("name '{}' exceeds {} characters", name, limits.max_name)
+        } else if limits.len(&full) > limits.max_path {
+            format!("path is {} characters, limit is {}", limits.len(&full), limits.max_path)
+        } else {
+            continue;
+        };
+        issues.push(PathIssue { relative, reason });
+    }
+    Ok(issues)
+}
+
+/// Finds files under `src` whose relative paths are equal when compared case-insensitively.
+/// Each pair holds the first path in sorted order and one that collides with it.
+pub fn check_case_collisions(src: &Path) -> Result<Vec<(PathBuf, PathBuf)>, RuError> {
+    let mut seen: HashMap<String, PathBuf> = HashMap::new();
+    let mut collisions = Vec::new();
+    for (relative, _) in walk_files(src)? {
+        let key = relative.to_string_lossy().to_lowercase();
+        match seen.get(&key) {
+            Some(first) => collisions.push((first.clone(), relative)),
+            None => {
+                seen.insert(key, relative);
+            }
+        }
+    }
+    Ok(collisions)
+}
+
+/// Picks a numbered name for the second path of each collision that collides with nothing else.
+fn case_collision_renames(src: &Path, collisions: &[(PathBuf, PathBuf)]) -> io::Result<HashMap<PathBuf, PathBuf>> {
+    let mut taken: HashSet<String> = walk_files(src)?
+        .into_iter()
+        .map(|(relative, _)| relative.to_string_lossy().to_lowercase())
+        .collect();
+    let mut renames = HashMap::new();
+    for (_, relative) in collisions {
+        let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = relative.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        let renamed = (2..)
+            .map(|n| relative.with_file_name(format!("{} ({}){}", stem, n, ext)))
+            .find(|candidate| !taken.contains(&candidate.to_string_lossy().to_lowercase()))
+            .expect("unbounded range always yields a free name");
+        taken.insert(renamed.to_string_lossy().to_lowercase());
+        renames.insert(relative.clone(), renamed);
+    }
+    Ok(renames)
+}
+
+/// Copies a directory tree under `dest_root` after checking that every destination path
+/// fits the limits and, on case-insensitive targets, that no two files collide.
+/// If any path doesn't fit or collides under `Refuse`, nothing is copied and the offenders are returned.
+fn copy_tree_checked(
+    src_dir: &Path,
+    dest_root: &Path,
+    limits: &FsLimits,
+    policy: LongNamePolicy,
+    case_policy: CaseCollisionPolicy,
+    progress: &mut dyn ProgressReporter,
+) -> io::Result<Result<(usize, u64), Vec<PathIssue>>> {
+    let mut issues = check_path_lengths(src_dir, dest_root, limits, policy)?;
+    let mut renames = HashMap::new();
+    if limits.case_insensitive {
+        let collisions = check_case_collisions(src_dir)?;
+        match case_policy {
+            CaseCollisionPolicy::Refuse => issues.extend(collisions.into_iter().map(|(first, second)| PathIssue {
+                relative: second,
+                reason: format!("collides with '{}' on a case-insensitive filesystem", first.display()),
+            })),
+            CaseCollisionPolicy::Rename => renames = case_collision_renames(src_dir, &collisions)?,
+        }
+    }
+    if !issues.is_empty() {
+        return Ok(Err(issues));
+    }
+    let files = walk_files(src_dir)?;
+    let mut copied = 0;
+    for (i, (relative, _)) in files.iter().enumerate() {
+        let target = renames.get(relative).unwrap_or(relative);
+        let dest = dest_root.join(destination_relative(target, limits, policy));
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        progress.on_start(&format!("[{}/{}] {}", i + 1, files.len(), relative.display()));
+        copied += copy_with_progress(&src_dir.join(relative), &dest, false, progress)?;
+    }
+    Ok(Ok((files.len(), copied)))
+}
+
+/// Copies a directory to the USB device under a folder of the same name. Write-protected devices
+/// are refused, and the total size is checked against the device's free space first, and destination paths against the device's
+/// filesystem limits; offenders are reported and nothing is written unless `policy` allows
+/// truncating the long names. On case-insensitive filesystems, files whose names differ only
+/// in case are refused or renamed per `case_policy`. Each file's progress goes to `progress`,
+/// then a summary is printed.
+pub fn copy_dir_to_usb(
+    usb: &UsbDevice,
+    src_dir: &Path,
+    policy: LongNamePolicy,
+    case_policy: CaseCollisionPolicy,
+    progress: &mut dyn ProgressReporter,
+) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    if usb.read_only {
+        return Err(RuError::ReadOnly(usb.device_id.clone()));
+    }
+    ensure_space(usb, walk_files(src_dir)?.iter().map(|(_, meta)| meta.len()).sum())?;
+    let limits = FsLimits::for_filesystem(&filesystem_type(usb)?);
+    let dest_root = usb.mount_point.join(src_dir.file_name().unwrap_or_default());
+    match copy_tree_checked(src_dir, &dest_root, &limits, policy, case_policy, progress)? {
+        Ok((files, bytes)) => {
+            let summary = format!("Copied {} file(s), {} ({} bytes) to {:?}", files, human_size(bytes), bytes, dest_root);
+            progress.on_message(&summary);
+            Ok(())
+        }
+        Err(issues) => {
+            for issue in &issues {
+                eprintln!("Cannot copy to target filesystem: {} ({})", issue.relative.display(), issue.reason);
+            }
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} path(s) cannot be represented on the target filesystem", issues.len()),
+            )
+            .into())
+        }
+    }
+}
+
+/// Returns an error unless the device is confirmed to be removable.
+fn ensure_removable(usb: &UsbDevice) -> Result<(), RuError> {
+    if is_truly_removable(usb)? {
+        Ok(())
+    } else {
+        Err(RuError::NotRemovable(usb.device_id.clone()))
+    }
+}
+
+/// Paths that must never be on a device we modify: the running executable and the OS root.
+fn system_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = env::current_exe().ok().into_iter().collect();
+    if cfg!(windows) {
+        paths.push(env::var_os("SystemRoot").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(r"C:\Windows")));
+    } else {
+        paths.push(PathBuf::from("/"));
+    }
+    paths.into_iter().map(|p| fs::canonicalize(&p).unwrap_or(p)).collect()
+}
+
+/// Fails with `SystemDrive` if any of `system_paths` lives under the device's mount point.
+fn ensure_not_system(usb: &UsbDevice, system_paths: &[PathBuf]) -> Result<(), RuError> {
+    let mount = fs::canonicalize(&usb.mount_point).unwrap_or_else(|_| usb.mount_point.clone());
+    if system_paths.iter().any(|p| p.starts_with(&mount)) {
+        return Err(RuError::SystemDrive(usb.device_id.clone()));
+    }
+    Ok(())
+}
+
+/// Fails with `NotRemovable` unless the device is among the freshly enumerated removable drives.
+fn ensure_listed(usb: &UsbDevice, removable: &[UsbDevice]) -> Result<(), RuError> {
+    if removable.iter().any(|d| d.device_id.eq_ignore_ascii_case(&usb.device_id)) {
+        Ok(())
+    } else {
+        Err(RuError::NotRemovable(usb.device_id.clone()))
+    }
+}
+
+/// Fails with `SystemDrive` if `disk`, the whole disk holding the device, also holds one of
+/// `system_disks`. Formatting on macOS erases the whole disk, not just the volume.
+#[cfg(any(target_os = "macos", test))]
+fn ensure_not_system_disk(usb: &UsbDevice, disk: &str, system_disks: &[String]) -> Result<(), RuError> {
+    if system_disks.iter().any(|d| d == disk) {
+        return Err(RuError::SystemDrive(usb.device_id.clone()));
+    }
+    Ok(())
+}
+
+/// Checks before formatting: besides `ensure_removable`, the device must not hold the operating
+/// system or this program, and it is re-enumerated so a stale or hand-built `UsbDevice` pointing
+/// at a fixed drive is rejected.
+fn ensure_format_target(usb: &UsbDevice) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    let system = system_paths();
+    ensure_not_system(usb, &system)?;
+    #[cfg(target_os = "macos")]
+    {
+        let disk = whole_disk_of(&usb.mount_point)
+            .ok_or_else(|| RuError::UsbNotFound(format!("diskutil has no disk for {:?}", usb.mount_point)))?;
+        let system_disks: Vec<String> = system.iter().filter_map(|p| whole_disk_of(p)).collect();
+        ensure_not_system_disk(usb, &disk, &system_disks)?;
+    }
+    ensure_listed(usb, &list_usb_devices()?)
+}
+
+/// One line of `/proc/mounts`.
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq, Eq)]
+struct MountEntry {
+    source: String,
+    fstype: String,
+    options: String,
+}
+
+/// Parses one line of `/proc/mounts` into its mount point and entry.
+#[cfg(any(target_os = "linux", test))]
+fn parse_mount_line(line: &str) -> Option<(PathBuf, MountEntry)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return None;
+    }
+    // Spaces in mount points are octal-escaped
+    let target = PathBuf::from(fields[1].replace("\\040", " "));
+    Some((
+        target,
+        MountEntry { source: fields[0].to_string(), fstype: fields[2].to_string(), options: fields[3].to_string() },
+    ))
+}
+
+/// Finds the filesystem mounted at `mount_point` in `/proc/mounts` contents.
+#[cfg(any(target_os = "linux", test))]
+fn find_mount(mounts: &str, mount_point: &Path) -> Option<MountEntry> {
+    mounts
+        .lines()
+        .filter_map(parse_mount_line)
+        .find_map(|(target, entry)| (target == mount_point).then_some(entry))
+}
+
+/// Pairs each device node in `devices` with where it is mounted and whether the mount is
+/// read-only (`ro`), taking the first mount of a device that is mounted more than once.
+#[cfg(any(target_os = "linux", test))]
+fn removable_mounts(mounts: &str, devices: &HashSet<String>) -> Vec<(String, PathBuf, bool)> {
+    let mut seen = HashSet::new();
+    mounts
+        .lines()
+        .filter_map(parse_mount_line)
+        .filter(|(_, entry)| devices.contains(&entry.source) && seen.insert(entry.source.clone()))
+        .map(|(target, entry)| {
+            let read_only = entry.options.split(',').any(|option| option == "ro");
+            (entry.source, target, read_only)
+        })
+        .collect()
+}
+
+/// Lists the symlinks in a directory such as `/dev/disk/by-uuid` as (name, target) pairs.
+#[cfg(target_os = "linux")]
+fn read_links(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut links = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Ok(target) = fs::read_link(entry.path()) {
+            links.push((entry.file_name().to_string_lossy().into_owned(), target));
+        }
+    }
+    Ok(links)
+}
+
+/// Finds the name of the `/dev/disk/by-uuid` (or `by-label`) link that points at `device`
+/// (e.g. `/dev/sdb1`). Links are relative (`../../sdb1`), so they are matched on the device node's name.
+#[cfg(target_os = "linux")]
+fn link_for_device(links: &[(String, PathBuf)], device: &Path) -> Option<String> {
+    let name = device.file_name()?;
+    links
+        .iter()
+        .find(|(_, target)| target.file_name() == Some(name))
+        .map(|(uuid, _)| uuid.clone())
+}
+
+/// Decodes the `\\xHH` escapes udev uses in `/dev/disk/by-label` names, e.g. `My\\x20Stick`.
+#[cfg(any(target_os = "linux", test))]
+fn unescape_udev(name: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = name.as_bytes();
+    while !rest.is_empty() {
+        let hex = rest.strip_prefix(b"\\x").and_then(|r| r.get(..2)).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &rest[4..];
+            }
+            None => {
+                bytes.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parses `/sys/block/<disk>/removable`.
+#[cfg(any(target_os = "linux", test))]
+fn parse_sys_removable(contents: &str) -> Option<bool> {
+    match contents.trim() {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses `BusType,IsSystem,IsBoot` as printed from `MSFT_Disk`.
+/// Only USB/SD/MMC disks that are neither system nor boot disks count as removable.
+#[cfg(any(windows, test))]
+fn parse_msft_disk(output: &str) -> Option<bool> {
+    let line = output.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() != 3 {
+        return None;
+    }
+    let removable_bus = matches!(fields[0].to_ascii_uppercase().as_str(), "USB" | "SD" | "MMC");
+    let is_system = fields[1].eq_ignore_ascii_case("true");
+    let is_boot = fields[2].eq_ignore_ascii_case("true");
+    Some(removable_bus && !is_system && !is_boot)
+}
+
+/// Returns the value of a `Name: value` field in `diskutil info` output.
+#[cfg(any(target_os = "macos", test))]
+fn diskutil_field(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|l| {
+        let (key, value) = l.split_once(':')?;
+        (key.trim() == name).then(|| value.trim().to_string())
+    })
+}
+
+/// Extracts the device node from `df -P` output, the first field of the line after the header.
+#[cfg(any(target_os = "macos", test))]
+fn parse_df_device(output: &str) -> Option<String> {
+    output.lines().nth(1)?.split_whitespace().next().map(str::to_string)
+}
+
+/// The whole disk (`Part of Whole` in `diskutil info`) holding `path`, found through `df`.
+#[cfg(target_os = "macos")]
+fn whole_disk_of(path: &Path) -> Option<String> {
+    let df = Command::new("df").arg("-P").arg(path).output().ok()?;
+    let node = parse_df_device(&String::from_utf8_lossy(&df.stdout))?;
+    let info = Command::new("diskutil").arg("info").arg(node).output().ok()?;
+    diskutil_field(&String::from_utf8_lossy(&info.stdout), "Part of Whole").filter(|disk| !disk.is_empty())
+}
+
+/// Parses `diskutil info` output: removable media, or an external USB device.
+#[cfg(any(target_os = "macos", test))]
+fn parse_diskutil_removable(output: &str) -> Option<bool> {
+    let media = diskutil_field(output, "Removable Media")?;
+    let external_usb = diskutil_field(output, "Device Location").as_deref() == Some("External")
+        && diskutil_field(output, "Protocol").as_deref() == Some("USB");
+    Some(media == "Removable" || external_usb)
+}
+
+/// Extracts the `Volume UUID` field from `diskutil info` output.
+#[cfg(any(target_os = "macos", test))]
+fn parse_diskutil_uuid(output: &str) -> Option<String> {
+    diskutil_field(output, "Volume UUID").filter(|uuid| !uuid.is_empty())
+}
+
+/// Builds a device from the `diskutil info` output for the volume at `mount_point`, or `None`
+/// if it isn't removable or the output lacks a device node. Space is left for the caller.
+#[cfg(any(target_os = "macos", test))]
+fn parse_diskutil_volume(output: &str, mount_point: &Path) -> Option<UsbDevice> {
+    if !parse_diskutil_removable(output)? {
+        return None;
+    }
+    Some(UsbDevice {
+        device_id: diskutil_field(output, "Device Node").filter(|node| !node.is_empty())?,
+        mount_point: mount_point.to_path_buf(),
+        label: diskutil_field(output, "Volume Name").filter(|name| !name.is_empty()),
+        total_space: None,
+        free_space: None,
+        read_only: diskutil_field(output, "Read-Only Volume").as_deref() == Some("Yes")
+            || diskutil_field(output, "Media Read-Only").as_deref() == Some("Yes"),
+    })
 }
 
 /// Checks if there's enough free space on the USB for the file
-pub fn has_enough_space(usb: &UsbDevice, file_path: &Path) -> io::Result<bool> {
+pub fn has_enough_space(usb: &UsbDevice, file_path: &Path) -> Result<bool, RuError> {
     let metadata = fs::metadata(file_path)?;
     if let Some(free) = usb.free_space {
         Ok(metadata.len() < free)
@@ -55,44 +849,358 @@ pub fn has_enough_space(usb: &UsbDevice, file_path: &Path) -> io::Result<bool> {
     }
 }
 
-/// Copies a file to the given USB device with progress reporting.
-pub fn copy_file_to_usb<P: AsRef<Path>>(usb: &UsbDevice, src_file: P) -> io::Result<()> {
+/// Receives byte counts while a file is copied, so callers other than the terminal (a GUI, a log,
+/// a test) can show or record progress.
+pub trait ProgressReporter {
+    /// Called before each file is copied with a name to show for it.
+    fn on_start(&mut self, _name: &str) {}
+    fn on_progress(&mut self, transferred: u64, total: u64);
+    fn on_done(&mut self);
+    /// Called with status lines such as a resumed copy or a finished summary.
+    fn on_message(&mut self, _message: &str) {}
+}
+
+/// Draws a single-line progress bar on stdout, rewritten in place as bytes arrive.
+#[derive(Debug, Default)]
+pub struct StdoutProgress {
+    name: String,
+}
+
+impl ProgressReporter for StdoutProgress {
+    fn on_start(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    fn on_progress(&mut self, transferred: u64, total: u64) {
+        let percent = if total == 0 { 100.0 } else { transferred as f64 / total as f64 * 100.0 };
+        print!("\rCopying {}... {}/{} bytes ({:.1}%)", self.name, transferred, total, percent);
+        io::stdout().flush().ok();
+    }
+
+    fn on_done(&mut self) {
+        println!();
+    }
+
+    fn on_message(&mut self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+/// Discards all progress, for redirected output or callers that only want the result.
+#[derive(Debug, Default)]
+pub struct NullProgress;
+
+impl ProgressReporter for NullProgress {
+    fn on_progress(&mut self, _transferred: u64, _total: u64) {}
+    fn on_done(&mut self) {}
+}
+
+/// Copies a file to the given USB device, reporting progress to `progress`. Write-protected devices
+/// and files that won't fit in the free space are refused up front. With `resume`, a shorter
+/// destination left by an interrupted copy is continued rather than rewritten, provided its
+/// contents match the start of the source. With `verify`, both copies are hashed with SHA-256
+/// afterwards and a mismatch is returned as an error carrying both digests.
+pub fn copy_file_to_usb<P: AsRef<Path>>(
+    usb: &UsbDevice,
+    src_file: P,
+    verify: bool,
+    resume: bool,
+    progress: &mut dyn ProgressReporter,
+) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    if usb.read_only {
+        return Err(RuError::ReadOnly(usb.device_id.clone()));
+    }
     let file_name = src_file.as_ref().file_name().unwrap();
     let dest = usb.mount_point.join(file_name);
-    let src_metadata = fs::metadata(&src_file)?;
+    // Whatever is already at `dest` is either resumed or replaced, so only the difference is needed
+    let existing = fs::metadata(&dest).map(|m| m.len()).unwrap_or(0);
+    ensure_space(usb, fs::metadata(src_file.as_ref())?.len().saturating_sub(existing))?;
+    progress.on_start(&file_name.to_string_lossy());
+    copy_with_progress(src_file.as_ref(), &dest, resume, progress)?;
+    progress.on_message(&format!("File copied to USB: {:?}", dest));
+    if verify {
+        verify_copy(src_file.as_ref(), &dest)?;
+        progress.on_message(&format!("Verified {:?} (sha256 matches source)", dest));
+    }
+    Ok(())
+}
+
+/// Copies `files` to the root of the USB device on a pool of `threads` workers, reporting the
+/// combined byte count of the batch to `progress`. Free space is checked for the whole batch
+/// first. A file that fails doesn't stop the others; all failures are returned together.
+pub fn copy_files_to_usb(
+    usb: &UsbDevice,
+    files: &[PathBuf],
+    threads: usize,
+    progress: &mut (dyn ProgressReporter + Send),
+) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    if usb.read_only {
+        return Err(RuError::ReadOnly(usb.device_id.clone()));
+    }
+    let total: u64 = files.iter().filter_map(|f| fs::metadata(f).ok()).map(|m| m.len()).sum();
+    ensure_space(usb, total)?;
+    let failures = copy_batch(files, &usb.mount_point, threads, total, progress)?;
+    if failures.is_empty() {
+        progress.on_message(&format!("Copied {} file(s), {} to {:?}", files.len(), human_size(total), usb.mount_point));
+        Ok(())
+    } else {
+        Err(RuError::CopyFailed(failures))
+    }
+}
+
+/// Copies each of `files` into `dest_dir` on its own pool of `threads` workers and returns the
+/// files that failed. `total` is the batch size that progress is reported against. Files that
+/// share a name with another in the batch would land on the same destination, so none of them
+/// are copied and each is reported as a failure.
+fn copy_batch(
+    files: &[PathBuf],
+    dest_dir: &Path,
+    threads: usize,
+    total: u64,
+    progress: &mut (dyn ProgressReporter + Send),
+) -> io::Result<Vec<(PathBuf, io::Error)>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .build()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let mut name_counts: HashMap<&OsStr, usize> = HashMap::new();
+    for name in files.iter().filter_map(|f| f.file_name()) {
+        *name_counts.entry(name).or_default() += 1;
+    }
+    let (unique, clashing): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        files.iter().partition(|f| f.file_name().is_none_or(|name| name_counts[name] == 1));
+    let clashes = clashing.into_iter().map(|src| {
+        let message = format!("{} has the same name as another file in the batch", src.display());
+        (src.clone(), io::Error::new(io::ErrorKind::AlreadyExists, message))
+    });
+    let shared = Mutex::new((progress, 0u64));
+    let failures = Mutex::new(clashes.collect::<Vec<_>>());
+    pool.install(|| {
+        unique.par_iter().for_each(|src| {
+            let mut reporter = BatchProgress { shared: &shared, total, reported: 0 };
+            let result = match src.file_name() {
+                Some(name) => copy_with_progress(src, &dest_dir.join(name), false, &mut reporter),
+                None => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file path")),
+            };
+            if let Err(e) = result {
+                failures.lock().unwrap().push(((*src).clone(), e));
+            }
+        })
+    });
+    shared.into_inner().unwrap().0.on_done();
+    Ok(failures.into_inner().unwrap())
+}
+
+/// Forwards one file's progress into the running total of a batch copy.
+struct BatchProgress<'a, 'p> {
+    shared: &'a Mutex<(&'p mut (dyn ProgressReporter + Send), u64)>,
+    total: u64,
+    reported: u64,
+}
+
+impl ProgressReporter for BatchProgress<'_, '_> {
+    fn on_progress(&mut self, transferred: u64, _total: u64) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.1 += transferred - self.reported;
+        self.reported = transferred;
+        let sum = shared.1;
+        shared.0.on_progress(sum, self.total);
+    }
+
+    fn on_done(&mut self) {}
+
+    fn on_message(&mut self, message: &str) {
+        self.shared.lock().unwrap().0.on_message(message);
+    }
+}
+
+/// What `sync_dir_to_usb` did, in files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncStats {
+    pub copied: usize,
+    pub skipped: usize,
+    pub deleted: usize,
+}
+
+/// Mirrors `src` into a folder of the same name on the USB device, copying only files that are
+/// new or whose size or mtime differ. Copies keep the source's timestamps, so unchanged files
+/// are skipped on the next run. With `delete_extra`, files no longer in `src` are removed.
+pub fn sync_dir_to_usb(
+    usb: &UsbDevice,
+    src: &Path,
+    delete_extra: bool,
+    progress: &mut dyn ProgressReporter,
+) -> Result<SyncStats, RuError> {
+    ensure_removable(usb)?;
+    if usb.read_only {
+        return Err(RuError::ReadOnly(usb.device_id.clone()));
+    }
+    let dest_root = usb.mount_point.join(src.file_name().unwrap_or_default());
+    let tolerance = filesystem_type(usb).map_or(0, |t| FsLimits::for_filesystem(&t).mtime_tolerance);
+    let plan = plan_sync_with_tolerance(src, &dest_root, tolerance)?;
+    ensure_space(usb, plan.bytes_to_copy())?;
+    let stats = apply_sync_plan(&plan, src, &dest_root, delete_extra, progress)?;
+    progress.on_message(&format!(
+        "Synced {:?}: {} copied, {} unchanged, {} deleted",
+        dest_root, stats.copied, stats.skipped, stats.deleted
+    ));
+    Ok(stats)
+}
+
+/// Carries out a plan from `plan_sync_with_tolerance(src, dest_root, ..)`.
+fn apply_sync_plan(
+    plan: &SyncPlan,
+    src: &Path,
+    dest_root: &Path,
+    delete_extra: bool,
+    progress: &mut dyn ProgressReporter,
+) -> io::Result<SyncStats> {
+    let mut stats = SyncStats { skipped: plan.unchanged.len(), ..SyncStats::default() };
+    for entry in &plan.copies {
+        let dest = dest_root.join(&entry.relative);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        progress.on_start(&entry.relative.display().to_string());
+        copy_with_progress(&src.join(&entry.relative), &dest, false, progress)?;
+        stats.copied += 1;
+    }
+    if delete_extra {
+        for relative in &plan.extra {
+            fs::remove_file(dest_root.join(relative))?;
+            progress.on_message(&format!("Deleted {}", relative.display()));
+            stats.deleted += 1;
+        }
+    }
+    Ok(stats)
+}
+
+/// Streams both files through SHA-256 and fails with both digests if they differ.
+fn verify_copy(src: &Path, dest: &Path) -> Result<(), RuError> {
+    let source_digest = sha256_file(src)?;
+    let dest_digest = sha256_file(dest)?;
+    if source_digest != dest_digest {
+        return Err(RuError::ChecksumMismatch {
+            path: dest.display().to_string(),
+            source_digest,
+            dest_digest,
+        });
+    }
+    Ok(())
+}
+
+/// Returns how many bytes of `dest` can be kept when resuming a copy of `src`: the destination's
+/// length if it is a strictly shorter prefix of the source, otherwise zero.
+fn resumable_length(src: &Path, dest: &Path, total_size: u64, progress: &mut dyn ProgressReporter) -> io::Result<u64> {
+    let existing = match fs::metadata(dest) {
+        Ok(meta) if meta.is_file() => meta.len(),
+        _ => return Ok(0),
+    };
+    if existing == 0 || existing >= total_size {
+        return Ok(0);
+    }
+    if sha256_prefix(src, existing)? != sha256_file(dest)? {
+        progress.on_message(&format!("Existing {} does not match the source; copying from the start", dest.display()));
+        return Ok(0);
+    }
+    Ok(existing)
+}
+
+/// Copies `src` to `dest`, reporting the running byte count, then gives `dest` the source's access
+/// and modification times so later syncs can tell it is up to date. With `resume`, a matching
+/// partial `dest` is appended to instead of truncated. Returns the size of the copy.
+fn copy_with_progress(src: &Path, dest: &Path, resume: bool, progress: &mut dyn ProgressReporter) -> io::Result<u64> {
+    let file_name = src.file_name().unwrap_or_default().to_string_lossy();
+    let src_metadata = fs::metadata(src)?;
     let total_size = src_metadata.len();
-    let mut src = BufReader::new(File::open(&src_file)?);
-    let mut dst = BufWriter::new(File::create(&dest)?);
+    let offset = if resume { resumable_length(src, dest, total_size, progress)? } else { 0 };
+    let mut src_file = File::open(src)?;
+    let dest_file = if offset > 0 {
+        progress.on_message(&format!("Resuming {} at {}/{} bytes", file_name, offset, total_size));
+        src_file.seek(SeekFrom::Start(offset))?;
+        let mut file = fs::OpenOptions::new().write(true).open(dest)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file
+    } else {
+        File::create(dest)?
+    };
+    let mut reader = BufReader::new(src_file);
+    let mut dst = BufWriter::new(dest_file);
 
-    let mut transferred: u64 = 0;
+    let mut transferred: u64 = offset;
+    progress.on_progress(transferred, total_size);
     let mut buffer = [0u8; 8192];
     loop {
-        let n = src.read(&mut buffer)?;
+        let n = reader.read(&mut buffer)?;
         if n == 0 { break; }
         dst.write_all(&buffer[..n])?;
         transferred += n as u64;
-        print!("\rCopying {}... {}/{} bytes ({:.1}%)", file_name.to_string_lossy(), transferred, total_size, (transferred as f64 / total_size as f64) * 100.0);
-        io::stdout().flush().ok();
+        progress.on_progress(transferred, total_size);
     }
     dst.flush()?;
-    println!("\nFile copied to USB: {:?}", dest);
-    Ok(())
+    drop(dst);
+    progress.on_done();
+    filetime::set_file_times(
+        dest,
+        filetime::FileTime::from_last_access_time(&src_metadata),
+        filetime::FileTime::from_last_modification_time(&src_metadata),
+    )?;
+    Ok(transferred)
+}
+
+/// Returns an error if `needed` bytes won't fit in the device's free space. Space is re-read from
+/// the filesystem when the device record doesn't carry it; if it still can't be told, the copy
+/// is allowed.
+fn ensure_space(usb: &UsbDevice, needed: u64) -> Result<(), RuError> {
+    let free = usb.free_space.or_else(|| fs2::available_space(&usb.mount_point).ok());
+    match free {
+        Some(available) if needed > available => Err(RuError::InsufficientSpace {
+            device: usb.device_id.clone(),
+            needed,
+            available,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Lists the files and directories on the USB device. With `recursive`, the whole device is
+/// walked breadth-first like `list_dir` and each entry is shown by its path relative to the mount
+/// point, with file sizes; folders below the mount point that can't be read are reported and
+/// skipped, but an unreadable or missing mount point is an error.
+pub fn list_files_on_usb(usb: &UsbDevice, recursive: bool) -> Result<(), RuError> {
+    list_files_on_usb_to(&mut io::stdout(), usb, recursive)
 }
 
-/// Lists all files and directories on the USB device (non-recursive).
-pub fn list_files_on_usb(usb: &UsbDevice) -> io::Result<()> {
-    println!("Listing files on USB ({}):", usb.device_id);
+/// Like `list_files_on_usb`, writing the listing to `out`.
+pub fn list_files_on_usb_to(out: &mut dyn Write, usb: &UsbDevice, recursive: bool) -> Result<(), RuError> {
+    // Read the root up front: collect_dir only warns about folders it can't read
     let entries = fs::read_dir(&usb.mount_point)?;
-    for entry in entries {
-        let entry = entry?;
-        let typ = if entry.file_type()?.is_dir() { "DIR " } else { "FILE" };
-        println!("[{}] {:?}", typ, entry.file_name());
+    writeln!(out, "Listing files on USB ({}):", usb.device_id)?;
+    if !recursive {
+        for entry in entries {
+            let entry = entry?;
+            let typ = if entry.file_type()?.is_dir() { "DIR " } else { "FILE" };
+            writeln!(out, "[{}] {:?}", typ, entry.file_name())?;
+        }
+        return Ok(());
     }
+    let items = collect_dir(&usb.mount_point, true, None, &[], &SortOrder::default(), true)?;
+    for item in &items {
+        let relative = item.path.strip_prefix(&usb.mount_point).unwrap_or(&item.path);
+        if item.is_dir {
+            writeln!(out, "[DIR ] {}", relative.display())?;
+        } else {
+            writeln!(out, "[FILE] {} ({})", relative.display(), human_size(item.size))?;
+        }
+    }
+    writeln!(out, "{} entries", items.len())?;
     Ok(())
 }
 
 /// Deletes a file from the USB device.
-pub fn delete_file_from_usb(usb: &UsbDevice, file_name: &str) -> io::Result<()> {
+pub fn delete_file_from_usb(usb: &UsbDevice, file_name: &str) -> Result<(), RuError> {
     let path = usb.mount_point.join(file_name);
     if path.exists() && path.is_file() {
         fs::remove_file(&path)?;
@@ -103,8 +1211,10 @@ pub fn delete_file_from_usb(usb: &UsbDevice, file_name: &str) -> io::Result<()>
     Ok(())
 }
 
-/// Safely ejects the USB device (Windows only, uses PowerShell).
-pub fn eject_usb(usb: &UsbDevice) -> io::Result<()> {
+/// Safely ejects the USB device through the shell's Eject verb, run from PowerShell.
+#[cfg(windows)]
+pub fn eject_usb(usb: &UsbDevice) -> Result<(), RuError> {
+    ensure_removable(usb)?;
     // Try to eject using PowerShell's Remove-PhysicalDisk
     let script = format!(
         r#"
@@ -126,13 +1236,84 @@ pub fn eject_usb(usb: &UsbDevice) -> io::Result<()> {
         println!("Safely ejected USB device: {}", usb.device_id);
         Ok(())
     } else {
-        eprintln!("Failed to eject USB device: {}", usb.device_id);
-        Err(io::Error::new(io::ErrorKind::Other, "Failed to eject USB"))
+        Err(RuError::EjectFailed(usb.device_id.clone()))
     }
 }
 
+/// Unmounts the device with `udisksctl` and powers the drive off so it can be unplugged,
+/// falling back to `eject` where udisks isn't installed.
+#[cfg(target_os = "linux")]
+pub fn eject_usb(usb: &UsbDevice) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    let node = device_node(usb)?;
+    if which::which("udisksctl").is_ok() {
+        run_eject_tool(usb, Command::new("udisksctl").args(["unmount", "-b", &node]))?;
+        run_eject_tool(usb, Command::new("udisksctl").args(["power-off", "-b", &node]))?;
+    } else if which::which("eject").is_ok() {
+        run_eject_tool(usb, Command::new("eject").arg(&node))?;
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Neither udisksctl nor eject is installed; install udisks2 or eject to eject drives",
+        )
+        .into());
+    }
+    println!("Safely ejected USB device: {}", usb.device_id);
+    Ok(())
+}
+
+/// Unmounts and ejects the volume's disk with `diskutil eject`.
+#[cfg(target_os = "macos")]
+pub fn eject_usb(usb: &UsbDevice) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    if which::which("diskutil").is_err() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "diskutil is not installed").into());
+    }
+    run_eject_tool(usb, Command::new("diskutil").arg("eject").arg(&usb.mount_point))?;
+    println!("Safely ejected USB device: {}", usb.device_id);
+    Ok(())
+}
+
+/// Ejecting isn't implemented for this platform.
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn eject_usb(usb: &UsbDevice) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Ejecting is not supported on this platform").into())
+}
+
+/// Runs an unmount/eject command, turning a busy device into `DeviceBusy` and any other
+/// failure into `EjectFailed` carrying the tool's message.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run_eject_tool(usb: &UsbDevice, command: &mut Command) -> Result<(), RuError> {
+    let output = command.output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr.trim();
+    if is_busy_message(message) {
+        Err(RuError::DeviceBusy(usb.device_id.clone()))
+    } else if message.is_empty() {
+        Err(RuError::EjectFailed(usb.device_id.clone()))
+    } else {
+        Err(RuError::EjectFailed(format!("{}: {}", usb.device_id, message)))
+    }
+}
+
+/// Recognises the "in use" errors of umount/udisks (`target is busy`, `device is busy`) and
+/// diskutil (`in use by process`, `dissented`).
+#[cfg(any(target_os = "linux", target_os = "macos", test))]
+fn is_busy_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["is busy", "in use", "dissented", "ebusy"].iter().any(|needle| message.contains(needle))
+}
+
 /// Writes a test file to the USB device to verify write access.
-pub fn test_usb_write(usb: &UsbDevice) -> io::Result<()> {
+pub fn test_usb_write(usb: &UsbDevice) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    if usb.read_only {
+        return Err(RuError::ReadOnly(usb.device_id.clone()));
+    }
     let test_file = usb.mount_point.join("test_write.txt");
     let mut file = File::create(&test_file)?;
     file.write_all(b"USB write test successful.")?;
@@ -141,8 +1322,46 @@ pub fn test_usb_write(usb: &UsbDevice) -> io::Result<()> {
     Ok(())
 }
 
-/// Calls Rufus via a C wrapper to create bootable USB.
-pub fn create_bootable_usb_with_rufus(usb: &UsbDevice, iso_path: &Path) -> io::Result<()> {
+/// Benchmarks sequential writes: writes a `test_size`-byte scratch file to the device, syncing it
+/// so the time covers the device rather than the page cache, deletes it, and returns MB/s.
+pub fn measure_write_speed(usb: &UsbDevice, test_size: u64) -> Result<f64, RuError> {
+    ensure_removable(usb)?;
+    if usb.read_only {
+        return Err(RuError::ReadOnly(usb.device_id.clone()));
+    }
+    ensure_space(usb, test_size)?;
+    let test_file = usb.mount_point.join(".datrain_speed_test");
+    let result = timed_write(&test_file, test_size);
+    fs::remove_file(&test_file).ok();
+    Ok(result?)
+}
+
+/// Writes `size` bytes to `path` in 1 MiB chunks and syncs it, returning the rate in MB/s.
+fn timed_write(path: &Path, size: u64) -> io::Result<f64> {
+    // Varying bytes, so devices that compress or deduplicate zeroes don't flatter the result
+    let chunk: Vec<u8> = (0..1024 * 1024).map(|i: u32| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+    let start = Instant::now();
+    let mut file = File::create(path)?;
+    let mut remaining = size;
+    while remaining > 0 {
+        let n = remaining.min(chunk.len() as u64) as usize;
+        file.write_all(&chunk[..n])?;
+        remaining -= n as u64;
+    }
+    file.sync_all()?;
+    let seconds = start.elapsed().as_secs_f64().max(f64::EPSILON);
+    Ok(size as f64 / 1_000_000.0 / seconds)
+}
+
+/// Calls Rufus via a C wrapper to create bootable USB. The image is checked first with
+/// `validate_iso`, so a missing or corrupt download fails before the drive is touched.
+pub fn create_bootable_usb_with_rufus(
+    usb: &UsbDevice,
+    iso_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    validate_iso(usb, iso_path, expected_sha256)?;
     // Assuming rufus_usb.exe is in PATH or current directory and takes arguments: <usb_path> <iso_path>
     let status = Command::new("rufus_usb.exe")
         .arg(&usb.device_id)
@@ -152,17 +1371,123 @@ pub fn create_bootable_usb_with_rufus(usb: &UsbDevice, iso_path: &Path) -> io::R
         println!("Rufus operation completed successfully.");
         Ok(())
     } else {
-        eprintln!("Rufus failed to create bootable USB.");
-        Err(io::Error::new(io::ErrorKind::Other, "Rufus failed"))
+        Err(RuError::RufusFailed(usb.device_id.clone()))
     }
 }
 
-/// Formats the USB device (WARNING: This will erase all data).
-pub fn format_usb(usb: &UsbDevice, fs_type: &str, label: Option<&str>) -> io::Result<()> {
+/// Checks an image before flashing: it must be a readable, non-empty file, match
+/// `expected_sha256` when one is given, and fit on the device. Flashing replaces everything on
+/// the drive, so the image is compared with the device's capacity rather than its free space.
+pub fn validate_iso(usb: &UsbDevice, iso_path: &Path, expected_sha256: Option<&str>) -> Result<(), RuError> {
+    let metadata = fs::metadata(iso_path).map_err(|e| {
+        io::Error::new(e.kind(), format!("Cannot read ISO image {}: {}", iso_path.display(), e))
+    })?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not an ISO image (not a file, or empty)", iso_path.display()),
+        )
+        .into());
+    }
+    File::open(iso_path)?.read_exact(&mut [0u8; 1])?;
+    if let Some(capacity) = usb.total_space.filter(|&capacity| metadata.len() > capacity) {
+        return Err(RuError::InsufficientSpace {
+            device: usb.device_id.clone(),
+            needed: metadata.len(),
+            available: capacity,
+        });
+    }
+    if let Some(expected) = expected_sha256 {
+        println!("Verifying SHA-256 of {}...", iso_path.display());
+        let actual = sha256_file(iso_path)?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has SHA-256 {}, expected {}; the download may be corrupt",
+                    iso_path.display(),
+                    actual,
+                    expected.trim()
+                ),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Filesystems `format_usb` accepts on Windows, mapped to the `format /FS:` name.
+#[cfg(any(windows, test))]
+const WINDOWS_FORMATS: &[(&str, &str)] = &[("fat32", "FAT32"), ("exfat", "exFAT"), ("ntfs", "NTFS")];
+
+/// Filesystems `format_usb` accepts on Linux, mapped to the mkfs tool that creates them.
+#[cfg(any(target_os = "linux", test))]
+const LINUX_FORMATS: &[(&str, &str)] = &[
+    ("vfat", "mkfs.vfat"),
+    ("fat32", "mkfs.vfat"),
+    ("exfat", "mkfs.exfat"),
+    ("ext4", "mkfs.ext4"),
+];
+
+/// Filesystems `format_usb` accepts on macOS, mapped to the `diskutil eraseDisk` format name.
+#[cfg(any(target_os = "macos", test))]
+const MACOS_FORMATS: &[(&str, &str)] = &[
+    ("fat32", "MS-DOS FAT32"),
+    ("exfat", "ExFAT"),
+    ("apfs", "APFS"),
+    ("hfs+", "JHFS+"),
+];
+
+/// Looks up `fs_type` (case-insensitively) in a platform's format table, or explains which
+/// filesystems are supported.
+#[cfg(any(windows, target_os = "linux", target_os = "macos", test))]
+fn lookup_format(formats: &[(&str, &'static str)], fs_type: &str) -> Result<&'static str, RuError> {
+    let wanted = fs_type.to_lowercase();
+    formats.iter().find(|(name, _)| *name == wanted).map(|(_, tool)| *tool).ok_or_else(|| {
+        let names: Vec<&str> = formats.iter().map(|(name, _)| *name).collect();
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unsupported filesystem '{}'; choose one of: {}", fs_type, names.join(", ")),
+        )
+        .into()
+    })
+}
+
+/// Refuses to go on with a destructive operation unless the caller confirmed it.
+fn ensure_confirmed(usb: &UsbDevice, confirm: bool) -> Result<(), RuError> {
+    if confirm {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Formatting {} erases all of its data; refusing without confirmation", usb.device_id),
+    )
+    .into())
+}
+
+/// Builds the mkfs invocation that creates `fs_type` labelled `label` on `node`.
+#[cfg(any(target_os = "linux", test))]
+fn mkfs_command(fs_type: &str, label: &str, node: &str) -> Result<(&'static str, Vec<String>), RuError> {
+    let tool = lookup_format(LINUX_FORMATS, fs_type)?;
+    let args = match tool {
+        "mkfs.vfat" => vec!["-F", "32", "-n", label, node],
+        "mkfs.ext4" => vec!["-F", "-L", label, node],
+        _ => vec!["-L", label, node],
+    };
+    Ok((tool, args.into_iter().map(str::to_string).collect()))
+}
+
+/// Formats the USB device (WARNING: This will erase all data). Nothing is done unless
+/// `confirm` is set. `fs_type` must be fat32, exfat or ntfs.
+#[cfg(windows)]
+pub fn format_usb(usb: &UsbDevice, fs_type: &str, label: Option<&str>, confirm: bool) -> Result<(), RuError> {
+    ensure_format_target(usb)?;
+    ensure_confirmed(usb, confirm)?;
+    let fs_name = lookup_format(WINDOWS_FORMATS, fs_type)?;
     let label = label.unwrap_or("USB");
     let status = Command::new("format")
         .arg(&usb.device_id)
-        .arg("/FS:".to_owned() + fs_type)
+        .arg("/FS:".to_owned() + fs_name)
         .arg("/V:".to_owned() + label)
         .arg("/Q")
         .arg("/Y")
@@ -171,13 +1496,176 @@ pub fn format_usb(usb: &UsbDevice, fs_type: &str, label: Option<&str>) -> io::Re
         println!("Formatted USB device: {}", usb.device_id);
         Ok(())
     } else {
-        eprintln!("Failed to format USB device: {}", usb.device_id);
-        Err(io::Error::new(io::ErrorKind::Other, "Failed to format USB"))
+        Err(RuError::FormatFailed(usb.device_id.clone()))
+    }
+}
+
+/// Formats the USB device (WARNING: This will erase all data). Nothing is done unless
+/// `confirm` is set. The label is checked against `fs_type`'s rules, the device is unmounted,
+/// then `fs_type` (vfat/fat32, exfat or ext4) is created with the matching mkfs tool. A failed
+/// unmount is reported as `FormatFailed` (or `DeviceBusy`).
+#[cfg(target_os = "linux")]
+pub fn format_usb(usb: &UsbDevice, fs_type: &str, label: Option<&str>, confirm: bool) -> Result<(), RuError> {
+    ensure_format_target(usb)?;
+    ensure_confirmed(usb, confirm)?;
+    let label = label.unwrap_or("USB");
+    check_label(fs_type, label)?;
+    let node = device_node(usb)?;
+    let (tool, args) = mkfs_command(fs_type, label, &node)?;
+    if which::which(tool).is_err() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not installed", tool)).into());
+    }
+    let unmounted = if which::which("udisksctl").is_ok() {
+        run_eject_tool(usb, Command::new("udisksctl").args(["unmount", "-b", &node]))
+    } else {
+        run_eject_tool(usb, Command::new("umount").arg(&node))
+    };
+    unmounted.map_err(|e| match e {
+        RuError::EjectFailed(detail) => RuError::FormatFailed(format!("{} (it could not be unmounted)", detail)),
+        other => other,
+    })?;
+    let status = Command::new(tool).args(&args).status()?;
+    if status.success() {
+        println!("Formatted USB device: {}", usb.device_id);
+        Ok(())
+    } else {
+        Err(RuError::FormatFailed(usb.device_id.clone()))
+    }
+}
+
+/// Formats the whole disk holding the volume with `diskutil eraseDisk` (WARNING: This will erase
+/// all data, including other volumes on the disk). Nothing is done unless `confirm` is set.
+/// `fs_type` must be fat32, exfat, apfs or hfs+.
+#[cfg(target_os = "macos")]
+pub fn format_usb(usb: &UsbDevice, fs_type: &str, label: Option<&str>, confirm: bool) -> Result<(), RuError> {
+    ensure_format_target(usb)?;
+    ensure_confirmed(usb, confirm)?;
+    let format = lookup_format(MACOS_FORMATS, fs_type)?;
+    let output = Command::new("diskutil").arg("info").arg(&usb.mount_point).output()?;
+    let disk = diskutil_field(&String::from_utf8_lossy(&output.stdout), "Part of Whole")
+        .ok_or_else(|| RuError::UsbNotFound(format!("diskutil has no disk for {:?}", usb.mount_point)))?;
+    let status = Command::new("diskutil")
+        .args(["eraseDisk", format, label.unwrap_or("USB"), "MBRFormat", &disk])
+        .status()?;
+    if status.success() {
+        println!("Formatted USB device: {}", usb.device_id);
+        Ok(())
+    } else {
+        Err(RuError::FormatFailed(usb.device_id.clone()))
+    }
+}
+
+/// Formatting isn't implemented for this platform.
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn format_usb(usb: &UsbDevice, _fs_type: &str, _label: Option<&str>, confirm: bool) -> Result<(), RuError> {
+    ensure_format_target(usb)?;
+    ensure_confirmed(usb, confirm)?;
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Formatting is not supported on this platform").into())
+}
+
+/// Checks `label` against the volume label rules of `fs_type`: FAT allows 11 bytes and no
+/// `"*+,./:;<=>?[\]|`, exFAT 15 UTF-16 units, ext2/3/4 16 bytes and NTFS 32 UTF-16 units.
+fn check_label(fs_type: &str, label: &str) -> Result<(), RuError> {
+    let invalid = |reason: String| -> RuError {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid {} label '{}': {}", fs_type, label, reason)).into()
+    };
+    if label.is_empty() {
+        return Err(invalid("the label is empty".to_string()));
+    }
+    let (max, len) = match fs_type {
+        "vfat" | "fat" | "fat16" | "fat32" | "msdos" => {
+            if let Some(c) = label.chars().find(|c| "\"*+,./:;<=>?[\\]|".contains(*c) || c.is_control()) {
+                return Err(invalid(format!("'{}' is not allowed on FAT", c)));
+            }
+            (11, label.len())
+        }
+        "exfat" => (15, label.encode_utf16().count()),
+        "ext2" | "ext3" | "ext4" => (16, label.len()),
+        "ntfs" | "ntfs3" | "fuseblk" => (32, label.encode_utf16().count()),
+        _ => return Ok(()),
+    };
+    if len > max {
+        return Err(invalid(format!("at most {} characters are allowed", max)));
+    }
+    Ok(())
+}
+
+/// Returns an error naming `tool` if it isn't on the PATH.
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+fn require_tool(tool: &str) -> Result<(), RuError> {
+    which::which(tool)
+        .map(|_| ())
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("{} is not installed or not on the PATH", tool)).into())
+}
+
+/// The labelling utility for a Linux filesystem type.
+#[cfg(any(target_os = "linux", test))]
+fn linux_label_tool(fs_type: &str) -> Option<&'static str> {
+    match fs_type {
+        "vfat" | "fat" | "msdos" => Some("fatlabel"),
+        "exfat" => Some("exfatlabel"),
+        "ext2" | "ext3" | "ext4" => Some("e2label"),
+        "ntfs" | "ntfs3" | "fuseblk" => Some("ntfslabel"),
+        _ => None,
     }
 }
 
+/// Changes the volume label with Windows' `label` command.
+#[cfg(windows)]
+pub fn set_usb_label(usb: &UsbDevice, new_label: &str) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    check_label(&filesystem_type(usb)?, new_label)?;
+    require_tool("label")?;
+    run_label_tool(usb, Command::new("label").arg(&usb.device_id).arg(new_label))
+}
+
+/// Changes the volume label with the tool for the device's filesystem: `fatlabel`,
+/// `exfatlabel`, `e2label` or `ntfslabel`.
+#[cfg(target_os = "linux")]
+pub fn set_usb_label(usb: &UsbDevice, new_label: &str) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    let fs_type = filesystem_type(usb)?;
+    check_label(&fs_type, new_label)?;
+    let tool = linux_label_tool(&fs_type).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Unsupported, format!("Relabelling {} volumes is not supported", fs_type))
+    })?;
+    require_tool(tool)?;
+    run_label_tool(usb, Command::new(tool).arg(device_node(usb)?).arg(new_label))
+}
+
+/// Changes the volume label with `diskutil rename`.
+#[cfg(target_os = "macos")]
+pub fn set_usb_label(usb: &UsbDevice, new_label: &str) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    check_label(&filesystem_type(usb)?, new_label)?;
+    require_tool("diskutil")?;
+    run_label_tool(usb, Command::new("diskutil").arg("rename").arg(&usb.mount_point).arg(new_label))
+}
+
+/// Relabelling isn't implemented for this platform.
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn set_usb_label(usb: &UsbDevice, _new_label: &str) -> Result<(), RuError> {
+    ensure_removable(usb)?;
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Relabelling is not supported on this platform").into())
+}
+
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+fn run_label_tool(usb: &UsbDevice, command: &mut Command) -> Result<(), RuError> {
+    let output = command.output()?;
+    if output.status.success() {
+        println!("Relabelled USB device: {}", usb.device_id);
+        return Ok(());
+    }
+    let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(RuError::LabelFailed(if message.is_empty() {
+        usb.device_id.clone()
+    } else {
+        format!("{}: {}", usb.device_id, message)
+    }))
+}
+
 /// Example workflow: List devices, write test, copy file, list files, delete test, eject
-pub fn example_usb_workflow() -> io::Result<()> {
+pub fn example_usb_workflow() -> Result<(), RuError> {
     let usbs = list_usb_devices()?;
     if usbs.is_empty() {
         println!("No USB devices detected.");
@@ -186,20 +1674,512 @@ pub fn example_usb_workflow() -> io::Result<()> {
     println!("Detected USB devices:");
     for (i, usb) in usbs.iter().enumerate() {
         println!(
-            "[{}] {} at {:?} (label: {:?}, free: {:?}, total: {:?})",
-            i, usb.device_id, usb.mount_point, usb.label, usb.free_space, usb.total_space
+            "[{}] {} at {:?} (label: {:?}, free: {:?}, total: {:?}{})",
+            i,
+            usb.device_id,
+            usb.mount_point,
+            usb.label,
+            usb.free_space,
+            usb.total_space,
+            if usb.read_only { ", read-only" } else { "" }
         );
     }
     // We'll use the first USB device for this example
     let usb = &usbs[0];
     test_usb_write(usb)?;
+    match measure_write_speed(usb, 16 * 1024 * 1024) {
+        Ok(speed) => println!("Write speed: {:.1} MB/s", speed),
+        Err(e) => println!("Could not measure write speed: {}", e),
+    }
     // Example: copy a file named "example.txt" if it exists
     let src = Path::new("example.txt");
     if src.exists() && has_enough_space(usb, src)? {
-        copy_file_to_usb(usb, src)?;
+        copy_file_to_usb(usb, src, true, false, &mut StdoutProgress::default())?;
     }
-    list_files_on_usb(usb)?;
+    list_files_on_usb(usb, false)?;
     delete_file_from_usb(usb, "test_write.txt").ok();
     // Eject (uncomment if you want to actually eject)
     // eject_usb(usb)?;
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::plan_sync;
+
+    #[test]
+    fn test_list_files_on_usb_recursive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("backup/photos")).unwrap();
+        fs::write(temp_dir.path().join("backup/photos/a.jpg"), b"jpeg").unwrap();
+        let usb = UsbDevice {
+            device_id: "test".to_string(),
+            mount_point: temp_dir.path().to_path_buf(),
+            label: None,
+            total_space: None,
+            free_space: None,
+            read_only: false,
+        };
+        let mut out = Vec::new();
+        list_files_on_usb_to(&mut out, &usb, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Listing files on USB (test):\n[DIR ] \"backup\"\n");
+        let mut out = Vec::new();
+        list_files_on_usb_to(&mut out, &usb, true).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+        let photos = Path::new("backup").join("photos");
+        let expected = format!(
+            "Listing files on USB (test):\n[DIR ] backup\n[DIR ] {}\n[FILE] {} (4 B)\n3 entries\n",
+            photos.display(),
+            photos.join("a.jpg").display()
+        );
+        assert_eq!(listing, expected);
+        let missing = UsbDevice { mount_point: temp_dir.path().join("gone"), ..usb };
+        assert!(list_files_on_usb(&missing, false).is_err());
+        assert!(list_files_on_usb(&missing, true).is_err());
+    }
+
+    #[test]
+    fn test_copy_tree_counts_and_space_check() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("a.txt"), b"hello").unwrap();
+        fs::write(src.join("nested/b.txt"), b"world!").unwrap();
+        fs::write(src.join("empty.txt"), b"").unwrap();
+        let dest = temp_dir.path().join("usb");
+        let ext4 = FsLimits::for_filesystem("ext4");
+
+        let copied = copy_tree_checked(&src, &dest, &ext4, LongNamePolicy::Refuse, CaseCollisionPolicy::Refuse, &mut NullProgress)
+            .unwrap()
+            .unwrap();
+        assert_eq!(copied, (3, 11));
+        assert_eq!(fs::read(dest.join("nested/b.txt")).unwrap(), b"world!");
+        assert_eq!(
+            fs::metadata(src.join("a.txt")).unwrap().modified().unwrap(),
+            fs::metadata(dest.join("a.txt")).unwrap().modified().unwrap()
+        );
+
+        let usb = UsbDevice {
+            device_id: "test".to_string(),
+            mount_point: temp_dir.path().to_path_buf(),
+            label: None,
+            total_space: Some(10),
+            free_space: Some(10),
+            read_only: false,
+        };
+        assert!(ensure_space(&usb, 10).is_ok());
+        assert!(matches!(
+            ensure_space(&usb, 11),
+            Err(RuError::InsufficientSpace { needed: 11, available: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_copy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src.bin");
+        let dest = temp_dir.path().join("dest.bin");
+        fs::write(&src, b"payload").unwrap();
+        fs::write(&dest, b"payload").unwrap();
+        assert!(verify_copy(&src, &dest).is_ok());
+
+        fs::write(&dest, b"paylaod").unwrap();
+        match verify_copy(&src, &dest) {
+            Err(RuError::ChecksumMismatch { source_digest, dest_digest, .. }) => {
+                assert_eq!(source_digest, sha256_file(&src).unwrap());
+                assert_ne!(source_digest, dest_digest);
+            }
+            other => panic!("expected a checksum mismatch, got {:?}", other),
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordedProgress {
+        reports: Vec<(u64, u64)>,
+        done: bool,
+        messages: Vec<String>,
+    }
+
+    impl ProgressReporter for RecordedProgress {
+        fn on_progress(&mut self, transferred: u64, total: u64) {
+            self.reports.push((transferred, total));
+        }
+
+        fn on_done(&mut self) {
+            self.done = true;
+        }
+
+        fn on_message(&mut self, message: &str) {
+            self.messages.push(message.to_string());
+        }
+    }
+
+    #[test]
+    fn test_copy_files_in_parallel() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dest = temp_dir.path().join("usb");
+        fs::create_dir(&dest).unwrap();
+        let files: Vec<PathBuf> = (0..6)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("f{}.txt", i));
+                fs::write(&path, vec![b'x'; 1000 * (i + 1)]).unwrap();
+                path
+            })
+            .collect();
+        let mut with_missing = files.clone();
+        with_missing.push(temp_dir.path().join("missing.txt"));
+        let mut recorded = RecordedProgress::default();
+        let failures = copy_batch(&with_missing, &dest, 3, 21_000, &mut recorded).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, temp_dir.path().join("missing.txt"));
+        assert_eq!(recorded.reports.last(), Some(&(21_000, 21_000)));
+        assert!(recorded.done);
+        assert_eq!(fs::read(dest.join("f5.txt")).unwrap().len(), 6000);
+
+        // Two inputs named f0.txt can't both go to the root of the stick.
+        fs::create_dir(temp_dir.path().join("other")).unwrap();
+        let twin = temp_dir.path().join("other/f0.txt");
+        fs::write(&twin, b"twin").unwrap();
+        let clashing = vec![files[0].clone(), twin.clone(), files[1].clone()];
+        let mut failures = copy_batch(&clashing, &dest, 2, 1004, &mut NullProgress).unwrap();
+        failures.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(failures.iter().map(|f| f.0.clone()).collect::<Vec<_>>(), vec![files[0].clone(), twin]);
+        assert!(failures.iter().all(|f| f.1.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read(dest.join("f0.txt")).unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_incremental_sync() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("docs");
+        let dest = temp_dir.path().join("usb/docs");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), b"alpha").unwrap();
+        fs::write(src.join("sub/b.txt"), b"beta").unwrap();
+        let sync = |delete_extra| {
+            let plan = plan_sync(&src, &dest).unwrap();
+            apply_sync_plan(&plan, &src, &dest, delete_extra, &mut NullProgress).unwrap()
+        };
+
+        assert_eq!(sync(false), SyncStats { copied: 2, skipped: 0, deleted: 0 });
+        assert_eq!(sync(false), SyncStats { copied: 0, skipped: 2, deleted: 0 });
+
+        fs::write(src.join("a.txt"), b"alpha, revised").unwrap();
+        fs::remove_file(src.join("sub/b.txt")).unwrap();
+        assert_eq!(sync(false), SyncStats { copied: 1, skipped: 0, deleted: 0 });
+        assert!(dest.join("sub/b.txt").exists());
+        assert_eq!(sync(true), SyncStats { copied: 0, skipped: 1, deleted: 1 });
+        assert!(!dest.join("sub/b.txt").exists());
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"alpha, revised");
+    }
+
+    #[test]
+    fn test_resume_copy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("image.iso");
+        let dest = temp_dir.path().join("copy.iso");
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data).unwrap();
+
+        fs::write(&dest, &data[..12_345]).unwrap();
+        assert_eq!(resumable_length(&src, &dest, data.len() as u64, &mut NullProgress).unwrap(), 12_345);
+        let mut recorded = RecordedProgress::default();
+        assert_eq!(copy_with_progress(&src, &dest, true, &mut recorded).unwrap(), data.len() as u64);
+        assert_eq!(recorded.messages, vec!["Resuming image.iso at 12345/20000 bytes".to_string()]);
+        assert_eq!(fs::read(&dest).unwrap(), data);
+        assert_eq!(recorded.reports.first(), Some(&(12_345, 20_000)));
+        assert_eq!(recorded.reports.last(), Some(&(20_000, 20_000)));
+        assert!(recorded.done);
+
+        let mut corrupt = data[..5_000].to_vec();
+        corrupt[10] ^= 0xff;
+        fs::write(&dest, &corrupt).unwrap();
+        let mut recorded = RecordedProgress::default();
+        assert_eq!(resumable_length(&src, &dest, data.len() as u64, &mut recorded).unwrap(), 0);
+        assert!(recorded.messages[0].contains("does not match the source"));
+        copy_with_progress(&src, &dest, true, &mut NullProgress).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+
+    #[test]
+    fn test_long_path_flagged_before_copy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        let deep = src.join("a".repeat(100)).join("b".repeat(100));
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(deep.join(format!("{}.txt", "c".repeat(100))), b"x").unwrap();
+        fs::write(src.join("short.txt"), b"ok").unwrap();
+        let dest = temp_dir.path().join("usb");
+        let fat = FsLimits::for_filesystem("vfat");
+
+        let result = copy_tree_checked(&src, &dest, &fat, LongNamePolicy::Refuse, CaseCollisionPolicy::Refuse, &mut NullProgress).unwrap();
+        let issues = result.unwrap_err();
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].reason.contains("path is"));
+        assert!(!dest.exists());
+
+        let ext4 = FsLimits::for_filesystem("ext4");
+        assert!(check_path_lengths(&src, &dest, &ext4, LongNamePolicy::Refuse).unwrap().is_empty());
+        assert_eq!((fat.mtime_tolerance, ext4.mtime_tolerance), (2, 0));
+    }
+
+    #[test]
+    fn test_case_collision_detected_before_copy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("File.txt"), b"upper").unwrap();
+        fs::write(src.join("file.txt"), b"lower").unwrap();
+        fs::write(src.join("other.txt"), b"x").unwrap();
+        let dest = temp_dir.path().join("usb");
+        let fat = FsLimits::for_filesystem("vfat");
+
+        let collisions = check_case_collisions(&src).unwrap();
+        assert_eq!(collisions, vec![(PathBuf::from("File.txt"), PathBuf::from("file.txt"))]);
+
+        let result = copy_tree_checked(&src, &dest, &fat, LongNamePolicy::Refuse, CaseCollisionPolicy::Refuse, &mut NullProgress);
+        let issues = result.unwrap().unwrap_err();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].relative, PathBuf::from("file.txt"));
+        assert!(!dest.exists());
+
+        copy_tree_checked(&src, &dest, &fat, LongNamePolicy::Refuse, CaseCollisionPolicy::Rename, &mut NullProgress)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read(dest.join("File.txt")).unwrap(), b"upper");
+        assert_eq!(fs::read(dest.join("file (2).txt")).unwrap(), b"lower");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_uuid_from_by_uuid_links() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let by_uuid = temp_dir.path();
+        std::os::unix::fs::symlink("../../sda2", by_uuid.join("0f1e2d3c-aaaa-bbbb-cccc-123456789abc")).unwrap();
+        std::os::unix::fs::symlink("../../sdb1", by_uuid.join("1A2B-3C4D")).unwrap();
+
+        let links = read_links(by_uuid).unwrap();
+        assert_eq!(link_for_device(&links, Path::new("/dev/sdb1")), Some("1A2B-3C4D".to_string()));
+        assert_eq!(link_for_device(&links, Path::new("/dev/sdc1")), None);
+    }
+
+    #[test]
+    fn test_diskutil_uuid() {
+        let info = "   Device Node:              /dev/disk4s1\n   Volume UUID:              8F3A1B2C-0D4E-3F5A-9B6C-7D8E9F0A1B2C\n";
+        assert_eq!(parse_diskutil_uuid(info).as_deref(), Some("8F3A1B2C-0D4E-3F5A-9B6C-7D8E9F0A1B2C"));
+        assert_eq!(parse_diskutil_uuid("   Volume UUID:\n"), None);
+    }
+
+    #[test]
+    fn test_truncate_long_names() {
+        let fat = FsLimits::for_filesystem("exfat");
+        let long = format!("{}.iso", "n".repeat(300));
+        let short = truncate_name(&long, &fat);
+        assert_eq!(short.len(), 255);
+        assert!(short.ends_with(".iso"));
+        assert_eq!(truncate_name("fine.txt", &fat), "fine.txt");
+    }
+
+    #[test]
+    fn test_sys_removable_flag() {
+        assert_eq!(parse_sys_removable("1\n"), Some(true));
+        assert_eq!(parse_sys_removable("0\n"), Some(false));
+        assert_eq!(parse_sys_removable(""), None);
+    }
+
+    #[test]
+    fn test_find_mount() {
+        let mounts = "/dev/nvme0n1p2 / ext4 rw,relatime 0 0\n/dev/sdb1 /media/My\\040Stick vfat rw 0 0\n";
+        assert_eq!(
+            find_mount(mounts, Path::new("/media/My Stick")),
+            Some(MountEntry { source: "/dev/sdb1".into(), fstype: "vfat".into(), options: "rw".into() })
+        );
+        assert_eq!(find_mount(mounts, Path::new("/media/other")), None);
+    }
+
+    #[test]
+    fn test_removable_mounts() {
+        let mounts = "/dev/sda2 / ext4 rw 0 0\n/dev/sdb1 /media/My\\040Stick vfat rw 0 0\n\
+                      /dev/sdb1 /mnt/bind vfat rw 0 0\n/dev/sdc /media/card exfat ro,nosuid 0 0\n";
+        let devices: HashSet<String> = ["/dev/sdb", "/dev/sdb1", "/dev/sdc"].iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            removable_mounts(mounts, &devices),
+            vec![
+                ("/dev/sdb1".to_string(), PathBuf::from("/media/My Stick"), false),
+                ("/dev/sdc".to_string(), PathBuf::from("/media/card"), true),
+            ]
+        );
+        assert_eq!(unescape_udev("My\\x20Stick"), "My Stick");
+        assert_eq!(unescape_udev("plain\\x2"), "plain\\x2");
+    }
+
+    #[test]
+    fn test_parse_windows_volume_listings() {
+        let json = r#"[{"DriveLetter":"E","FileSystemLabel":"STICK","Size":16000000000,"SizeRemaining":8000000000,"ReadOnly":true},
+                       {"DriveLetter":"F","FileSystemLabel":"","Size":1000},{"DriveLetter":"","Size":5}]"#;
+        let devices = parse_get_volume_json(json).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].device_id, "E:");
+        assert_eq!(devices[0].mount_point, PathBuf::from("E:\\"));
+        assert_eq!(devices[0].label.as_deref(), Some("STICK"));
+        assert_eq!(devices[0].free_space, Some(8_000_000_000));
+        assert!(devices[0].read_only && !devices[1].read_only);
+        assert_eq!((devices[1].label.as_deref(), devices[1].free_space), (None, None));
+        assert!(parse_get_volume_json("[]").unwrap().is_empty());
+        assert!(parse_get_volume_json("Get-Volume : not recognized").is_none());
+
+        let wmic = "\r\r\nNode,Access,DeviceID,FreeSpace,Size,VolumeName\r\r\nPC,1,E:,8000,16000,STICK\r\r\nPC,,F:,,,\r\r\n";
+        let devices = parse_wmic_csv(wmic);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].label.as_deref(), Some("STICK"));
+        assert_eq!((devices[0].total_space, devices[0].free_space), (Some(16000), Some(8000)));
+        assert!(devices[0].read_only && !devices[1].read_only);
+        assert_eq!((devices[1].device_id.as_str(), devices[1].label.as_deref(), devices[1].free_space), ("F:", None, None));
+    }
+
+    #[test]
+    fn test_busy_eject_messages() {
+        assert!(is_busy_message(
+            "Error unmounting /dev/sdb1: GDBus.Error:org.freedesktop.UDisks2.Error.DeviceBusy: Error unmounting /dev/sdb1: target is busy"
+        ));
+        assert!(is_busy_message("Unmount of disk4 failed: at least one volume could not be unmounted\nUnmount was dissented by PID 412"));
+        assert!(!is_busy_message("Error looking up object for device /dev/sdz1"));
+    }
+
+    #[test]
+    fn test_fixed_and_system_drives_rejected() {
+        let stick = drive_device("E", Some("STICK".to_string()), None, None, false);
+        let fixed = drive_device("C", None, None, None, false);
+        let removable = vec![stick.clone()];
+        assert!(ensure_listed(&stick, &removable).is_ok());
+        assert!(matches!(ensure_listed(&fixed, &removable), Err(RuError::NotRemovable(id)) if id == "C:"));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let usb = UsbDevice { mount_point: temp_dir.path().to_path_buf(), ..stick };
+        let exe_on_stick = vec![temp_dir.path().join("bin/datrain")];
+        assert!(matches!(ensure_not_system(&usb, &exe_on_stick), Err(RuError::SystemDrive(_))));
+        assert!(ensure_not_system(&usb, &[PathBuf::from("/usr/bin/datrain")]).is_ok());
+
+        let df = "Filesystem   512-blocks     Used Available Capacity  Mounted on\n/dev/disk4s1   60000000  1200000  58800000     3%    /Volumes/STICK\n";
+        assert_eq!(parse_df_device(df).as_deref(), Some("/dev/disk4s1"));
+        let system_disks = vec!["disk0".to_string(), "disk4".to_string()];
+        assert!(matches!(ensure_not_system_disk(&usb, "disk4", &system_disks), Err(RuError::SystemDrive(_))));
+        assert!(ensure_not_system_disk(&usb, "disk5", &system_disks).is_ok());
+    }
+
+    #[test]
+    fn test_timed_write() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("speed");
+        let speed = timed_write(&path, 3 * 1024 * 1024 + 17).unwrap();
+        assert!(speed > 0.0);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 1024 * 1024 + 17);
+    }
+
+    #[test]
+    fn test_validate_iso() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let iso = temp_dir.path().join("distro.iso");
+        fs::write(&iso, b"not really an iso").unwrap();
+        let digest = sha256_file(&iso).unwrap();
+        let usb = UsbDevice { total_space: Some(1000), ..drive_device("E", None, None, None, false) };
+
+        assert!(validate_iso(&usb, &iso, None).is_ok());
+        assert!(validate_iso(&usb, &iso, Some(&digest.to_uppercase())).is_ok());
+        let err = validate_iso(&usb, &iso, Some(&"0".repeat(64))).unwrap_err();
+        assert!(err.to_string().contains(&digest));
+        assert!(validate_iso(&usb, &temp_dir.path().join("missing.iso"), None).is_err());
+        assert!(validate_iso(&usb, temp_dir.path(), None).is_err());
+
+        let tiny = UsbDevice { total_space: Some(4), ..usb };
+        assert!(matches!(validate_iso(&tiny, &iso, None), Err(RuError::InsufficientSpace { .. })));
+    }
+
+    #[test]
+    fn test_find_usb_by_label_and_mount() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("backup/photos")).unwrap();
+        let device = |id: &str, label: &str, mount: PathBuf| UsbDevice {
+            label: Some(label.to_string()),
+            mount_point: mount,
+            ..drive_device(id, None, None, None, false)
+        };
+        let devices = vec![
+            device("E", "MyBackup", temp_dir.path().join("backup")),
+            device("F", "Ventoy", temp_dir.path().join("ventoy")),
+        ];
+
+        assert_eq!(usb_with_label(devices.clone(), "MYBACKUP").unwrap().device_id, "E:");
+        assert!(usb_with_label(devices.clone(), "MYBACK").is_none());
+        let found = usb_at_mount(devices.clone(), &temp_dir.path().join("backup/photos")).unwrap();
+        assert_eq!(found.device_id, "E:");
+        assert_eq!(usb_at_mount(devices.clone(), &temp_dir.path().join("ventoy")).unwrap().device_id, "F:");
+        assert!(usb_at_mount(devices, temp_dir.path()).is_none());
+    }
+
+    #[test]
+    fn test_volume_labels() {
+        assert!(check_label("vfat", "BACKUP2024").is_ok());
+        assert!(check_label("vfat", "TWELVECHARSX").unwrap_err().to_string().contains("at most 11"));
+        assert!(check_label("fat32", "A/B").unwrap_err().to_string().contains("'/'"));
+        assert!(check_label("exfat", "Fifteen chars!!").is_ok());
+        assert!(check_label("ext4", "seventeen-chars!!").is_err());
+        assert!(check_label("ntfs", "").is_err());
+        assert!(check_label("apfs", "Anything goes, really").is_ok());
+        assert_eq!(linux_label_tool("vfat"), Some("fatlabel"));
+        assert_eq!(linux_label_tool("ext4"), Some("e2label"));
+        assert_eq!(linux_label_tool("btrfs"), None);
+    }
+
+    #[test]
+    fn test_format_types() {
+        assert_eq!(lookup_format(WINDOWS_FORMATS, "FAT32").unwrap(), "FAT32");
+        assert_eq!(lookup_format(MACOS_FORMATS, "exfat").unwrap(), "ExFAT");
+        let err = lookup_format(WINDOWS_FORMATS, "ext4").unwrap_err();
+        assert!(err.to_string().contains("choose one of: fat32, exfat, ntfs"));
+
+        let (tool, args) = mkfs_command("fat32", "STICK", "/dev/sdb1").unwrap();
+        assert_eq!(tool, "mkfs.vfat");
+        assert_eq!(args, ["-F", "32", "-n", "STICK", "/dev/sdb1"]);
+        assert_eq!(mkfs_command("ext4", "data", "/dev/sdb1").unwrap().1, ["-F", "-L", "data", "/dev/sdb1"]);
+        assert!(mkfs_command("ntfs", "x", "/dev/sdb1").is_err());
+    }
+
+    #[test]
+    fn test_msft_disk_flags() {
+        assert_eq!(parse_msft_disk("USB,False,False\r\n"), Some(true));
+        assert_eq!(parse_msft_disk("NVMe,True,True"), Some(false));
+        assert_eq!(parse_msft_disk("USB,False,True"), Some(false));
+        assert_eq!(parse_msft_disk(""), None);
+    }
+
+    #[test]
+    fn test_diskutil_removable() {
+        let stick = "   Protocol:                  USB\n   Removable Media:           Removable\n";
+        let usb_ssd = "   Protocol:                  USB\n   Device Location:           External\n   Removable Media:           Fixed\n";
+        let internal = "   Protocol:                  Apple Fabric\n   Device Location:           Internal\n   Removable Media:           Fixed\n";
+        assert_eq!(parse_diskutil_removable(stick), Some(true));
+        assert_eq!(parse_diskutil_removable(usb_ssd), Some(true));
+        assert_eq!(parse_diskutil_removable(internal), Some(false));
+        assert_eq!(parse_diskutil_removable("garbage"), None);
+    }
+
+    #[test]
+    fn test_diskutil_volume() {
+        let stick = "   Device Node:               /dev/disk4s1\n   Volume Name:               BACKUP\n   Removable Media:           Removable\n";
+        let usb = parse_diskutil_volume(stick, Path::new("/Volumes/BACKUP")).unwrap();
+        assert_eq!(usb.device_id, "/dev/disk4s1");
+        assert_eq!(usb.label.as_deref(), Some("BACKUP"));
+        assert_eq!(usb.mount_point, PathBuf::from("/Volumes/BACKUP"));
+        assert!(!usb.read_only);
+        let locked = format!("{}   Media Read-Only:           Yes\n", stick);
+        assert!(parse_diskutil_volume(&locked, Path::new("/Volumes/BACKUP")).unwrap().read_only);
+
+        let unnamed = "   Device Node:               /dev/disk5s1\n   Volume Name:\n   Removable Media:           Removable\n";
+        assert_eq!(parse_diskutil_volume(unnamed, Path::new("/Volumes/Untitled")).unwrap().label, None);
+        let internal = "   Device Node:               /dev/disk1s1\n   Removable Media:           Fixed\n   Device Location:           Internal\n";
+        assert!(parse_diskutil_volume(internal, Path::new("/")).is_none());
+        assert!(parse_diskutil_volume("garbage", Path::new("/Volumes/x")).is_none());
+    }
+}